@@ -0,0 +1,458 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result, anyhow};
+use serde_json::{Map, Value};
+
+use crate::config::TemplateConfig;
+use crate::utils::err;
+
+/// A person, as the author or editor of an entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct Name {
+    pub family: String,
+    pub given: Option<String>,
+}
+
+/// An entry of a bibliography, with the fields the built-in citation styles use
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Entry {
+    pub key: String,
+    /// The BibTeX entry type, e.g. `article` or `book`. CSL-JSON types are mapped to these
+    pub kind: String,
+    pub authors: Vec<Name>,
+    pub title: Option<String>,
+    pub year: Option<String>,
+    /// The journal, or the book or proceedings the entry is part of
+    pub container: Option<String>,
+    pub volume: Option<String>,
+    pub issue: Option<String>,
+    pub pages: Option<String>,
+    pub publisher: Option<String>,
+    pub url: Option<String>,
+    pub doi: Option<String>,
+}
+
+/// The entries of the bibliography files of a document
+#[derive(Debug, Default, Clone)]
+pub struct Bibliography {
+    /// The files the entries were read from, for pandoc
+    pub files: Vec<PathBuf>,
+    pub entries: Vec<Entry>,
+}
+
+impl Bibliography {
+    /// Loads the bibliography of a document, named with `bibliography` in its front matter
+    /// (relative to the document) or in the template's template.toml (relative to the template).
+    ///
+    /// Returns `None` if neither names one.
+    pub fn for_document(
+        md_path: &Path,
+        template_path: &Path,
+        meta: &Map<String, Value>,
+    ) -> Result<Option<Bibliography>> {
+        let files: Vec<PathBuf> = match meta.get("bibliography") {
+            Some(value) => {
+                let base = md_path.parent().unwrap_or(Path::new("."));
+                let names = match value {
+                    Value::String(name) => vec![name.as_str()],
+                    Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+                    _ => {
+                        return Err(anyhow!(err("`bibliography` must be a file or a list of files")))
+                    }
+                };
+                names.into_iter().map(|name| base.join(name)).collect()
+            }
+            None => match TemplateConfig::load(template_path)?.bibliography {
+                Some(file) => vec![template_path.join(file)],
+                None => return Ok(None),
+            },
+        };
+
+        let mut bibliography = Bibliography::default();
+        for file in files {
+            let source = fs::read_to_string(&file)
+                .with_context(|| err(format!("Could not read bibliography {:?}", file).as_str()))?;
+            let is_json = file.extension().is_some_and(|ext| ext == "json");
+            let entries = if is_json { parse_csl_json(&source) } else { parse_bibtex(&source) };
+            let entries = entries.map_err(|e| {
+                anyhow!(err(format!("Invalid bibliography {:?}: {}", file, e).as_str()))
+            })?;
+            bibliography.entries.extend(entries);
+            bibliography.files.push(fs::canonicalize(&file).unwrap_or(file));
+        }
+        Ok(Some(bibliography))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.key == key)
+    }
+}
+
+/// Parses the entries of a BibTeX or BibLaTeX file.
+///
+/// `@string`, `@preamble` and `@comment` blocks, and text outside of entries, are skipped.
+pub fn parse_bibtex(source: &str) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    let mut rest = source;
+
+    // entries start with an `@` at the start of a line, anything else is a comment
+    while let Some(at) = rest
+        .match_indices('@')
+        .map(|(at, _)| at)
+        .find(|at| rest[..*at].rsplit('\n').next().is_some_and(|line| line.trim().is_empty()))
+    {
+        let line = source[..source.len() - rest.len() + at].matches('\n').count() + 1;
+        rest = &rest[at + 1..];
+
+        let open = rest.find(['{', '(']).ok_or(format!("line {}: expected `{{`", line))?;
+        let kind = rest[..open].trim().to_lowercase();
+        let close = matching_close(&rest[open..]).ok_or(format!("line {}: unclosed entry", line))?;
+        let body = &rest[open + 1..open + close];
+        rest = &rest[open + close + 1..];
+
+        if matches!(kind.as_str(), "string" | "preamble" | "comment") {
+            continue;
+        }
+
+        let (key, fields) = body.split_once(',').unwrap_or((body, ""));
+        let mut entry = Entry { key: key.trim().to_string(), kind, ..Entry::default() };
+        for (name, raw) in parse_fields(fields).map_err(|e| format!("line {}: {}", line, e))? {
+            let value = clean_latex(&raw);
+            match name.as_str() {
+                "author" => entry.authors = parse_names(&raw),
+                "editor" if entry.authors.is_empty() => entry.authors = parse_names(&raw),
+                "title" => entry.title = Some(value),
+                "year" => entry.year = Some(value),
+                "date" if entry.year.is_none() => {
+                    entry.year = Some(value.chars().take(4).collect())
+                }
+                "journal" | "journaltitle" | "booktitle" => entry.container = Some(value),
+                "volume" => entry.volume = Some(value),
+                "number" | "issue" => entry.issue = Some(value),
+                "pages" => entry.pages = Some(value.replace("--", "–")),
+                "publisher" | "institution" | "school" | "organization" => {
+                    entry.publisher = Some(value)
+                }
+                "url" => entry.url = Some(value),
+                "doi" => entry.doi = Some(value),
+                _ => {}
+            }
+        }
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// Finds the bracket closing the one `text` starts with
+fn matching_close(text: &str) -> Option<usize> {
+    let (open, close) = if text.starts_with('(') { ('(', ')') } else { ('{', '}') };
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// Parses the `name = {value}` fields of an entry, with lower case names
+fn parse_fields(fields: &str) -> Result<Vec<(String, String)>, String> {
+    let mut parsed = Vec::new();
+    let chars: Vec<char> = fields.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        while i < chars.len() && (chars[i].is_whitespace() || chars[i] == ',') {
+            i += 1;
+        }
+        if i == chars.len() {
+            break;
+        }
+        let start = i;
+        while i < chars.len() && chars[i] != '=' {
+            i += 1;
+        }
+        if i == chars.len() {
+            let rest: String = chars[start..].iter().collect();
+            return Err(format!("expected `=` after `{}`", rest.trim()));
+        }
+        let name: String = chars[start..i].iter().collect::<String>().trim().to_lowercase();
+        i += 1;
+
+        // values are braced, quoted or bare, and can be joined with `#`
+        let mut value = String::new();
+        loop {
+            while i < chars.len() && chars[i].is_whitespace() {
+                i += 1;
+            }
+            match chars.get(i) {
+                Some('{') => {
+                    let mut depth = 0;
+                    let start = i + 1;
+                    while i < chars.len() {
+                        match chars[i] {
+                            '{' => depth += 1,
+                            '}' => {
+                                depth -= 1;
+                                if depth == 0 {
+                                    break;
+                                }
+                            }
+                            _ => {}
+                        }
+                        i += 1;
+                    }
+                    if i == chars.len() {
+                        return Err(format!("unclosed `{{` in `{}`", name));
+                    }
+                    value.extend(&chars[start..i]);
+                    i += 1;
+                }
+                Some('"') => {
+                    let start = i + 1;
+                    i += 1;
+                    while i < chars.len() && !(chars[i] == '"' && chars[i - 1] != '\\') {
+                        i += 1;
+                    }
+                    if i == chars.len() {
+                        return Err(format!("unclosed `\"` in `{}`", name));
+                    }
+                    value.extend(&chars[start..i]);
+                    i += 1;
+                }
+                _ => {
+                    let start = i;
+                    while i < chars.len() && !matches!(chars[i], ',' | '#') {
+                        i += 1;
+                    }
+                    value.push_str(chars[start..i].iter().collect::<String>().trim());
+                }
+            }
+
+            while i < chars.len() && chars[i].is_whitespace() {
+                i += 1;
+            }
+            if chars.get(i) == Some(&'#') {
+                i += 1;
+                continue;
+            }
+            break;
+        }
+
+        parsed.push((name, value));
+    }
+
+    Ok(parsed)
+}
+
+/// Splits an `author` field at `and`, reading `Family, Given` and `Given Family` names.
+/// Names in braces, e.g. `{World Health Organization}`, are kept whole
+fn parse_names(value: &str) -> Vec<Name> {
+    split_outside_braces(value, " and ")
+        .into_iter()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            if let Some((family, given)) = split_outside_braces(name, ",").split_first() {
+                if !given.is_empty() {
+                    let given = clean_latex(&given.join(","));
+                    return Name {
+                        family: clean_latex(family),
+                        given: Some(given).filter(|g| !g.is_empty()),
+                    };
+                }
+            }
+            match split_outside_braces(name, " ").split_last() {
+                Some((family, given)) if !given.is_empty() => Name {
+                    family: clean_latex(family),
+                    given: Some(clean_latex(&given.join(" "))),
+                },
+                _ => Name { family: clean_latex(name), given: None },
+            }
+        })
+        .collect()
+}
+
+/// Splits `text` at `separator`, except inside braces
+fn split_outside_braces<'a>(text: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ if depth == 0 && i >= start && text[i..].starts_with(separator) => {
+                parts.push(&text[start..i]);
+                start = i + separator.len();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Turns the LaTeX of a field into plain text, e.g. `{\"o}` into `ö` and `--` into `–`
+fn clean_latex(value: &str) -> String {
+    let mut out = String::new();
+    let mut chars = value.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' => {}
+            '~' => out.push('\u{a0}'),
+            '\\' => match chars.next() {
+                Some(accent @ ('"' | '\'' | '`' | '^' | '~')) => {
+                    if chars.peek() == Some(&'{') {
+                        chars.next();
+                    }
+                    if let Some(letter) = chars.next() {
+                        out.push(letter);
+                    }
+                    out.push(match accent {
+                        '"' => '\u{308}',
+                        '\'' => '\u{301}',
+                        '`' => '\u{300}',
+                        '^' => '\u{302}',
+                        _ => '\u{303}',
+                    });
+                }
+                Some(escaped) if !escaped.is_alphabetic() => out.push(escaped),
+                // other commands, e.g. `\emph`, are dropped and their argument kept
+                Some(first) => {
+                    let mut command = first.to_string();
+                    while let Some(c) = chars.next_if(|c| c.is_alphabetic()) {
+                        command.push(c);
+                    }
+                    if matches!(command.as_str(), "LaTeX" | "TeX") {
+                        out.push_str(&command);
+                    }
+                }
+                None => {}
+            },
+            _ => out.push(c),
+        }
+    }
+
+    let out = out.replace("---", "—").replace("--", "–");
+    out.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Parses a CSL-JSON file, as exported by Zotero
+pub fn parse_csl_json(source: &str) -> Result<Vec<Entry>, String> {
+    let items: Vec<Map<String, Value>> = serde_json::from_str(source).map_err(|e| e.to_string())?;
+
+    let text = |item: &Map<String, Value>, name: &str| -> Option<String> {
+        match item.get(name)? {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    };
+
+    let entries = items
+        .iter()
+        .map(|item| {
+            let names = item.get("author").or_else(|| item.get("editor"));
+            let authors = names
+                .and_then(Value::as_array)
+                .map(|names| {
+                    names
+                        .iter()
+                        .filter_map(|name| {
+                            let name = name.as_object()?;
+                            let family = text(name, "family").or_else(|| text(name, "literal"))?;
+                            Some(Name { family, given: text(name, "given") })
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            let year = item
+                .get("issued")
+                .and_then(|issued| issued.get("date-parts"))
+                .and_then(|parts| parts.get(0))
+                .and_then(|part| part.get(0))
+                .map(|year| match year {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                });
+
+            let kind = match text(item, "type").as_deref() {
+                Some("article-journal" | "article-magazine" | "article-newspaper") => "article",
+                Some("book") => "book",
+                Some("chapter") => "incollection",
+                Some("paper-conference") => "inproceedings",
+                Some("thesis") => "phdthesis",
+                Some("report") => "techreport",
+                _ => "misc",
+            };
+
+            Entry {
+                key: text(item, "id").unwrap_or_default(),
+                kind: kind.to_string(),
+                authors,
+                title: text(item, "title"),
+                year,
+                container: text(item, "container-title"),
+                volume: text(item, "volume"),
+                issue: text(item, "issue"),
+                pages: text(item, "page").map(|pages| pages.replace('-', "–")),
+                publisher: text(item, "publisher"),
+                url: text(item, "URL"),
+                doi: text(item, "DOI"),
+            }
+        })
+        .collect();
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bibtex() {
+        let entries = parse_bibtex(
+            "% comment\n\
+             @string{cj = \"The Computer Journal\"}\n\
+             @article{knuth84,\n  author = {Knuth, Donald E.},\n  \
+             title = {Literate {P}rogramming},\n  journal = \"The Computer Journal\",\n  \
+             year = 1984,\n  pages = {97--111},\n}\n\
+             @book{goedel, author = {Kurt G{\\\"o}del and {World Health Organization}},\n  \
+             title = {Proofs}}\n",
+        )
+        .unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].key, "knuth84");
+        assert_eq!(entries[0].title.as_deref(), Some("Literate Programming"));
+        assert_eq!(entries[0].year.as_deref(), Some("1984"));
+        assert_eq!(entries[0].pages.as_deref(), Some("97–111"));
+        assert_eq!(entries[0].authors[0].given.as_deref(), Some("Donald E."));
+        assert_eq!(entries[1].authors[0].family, "Go\u{308}del");
+        assert_eq!(entries[1].authors[1].family, "World Health Organization");
+    }
+
+    #[test]
+    fn test_parse_csl_json() {
+        let entries = parse_csl_json(
+            r#"[{"id": "lamport94", "type": "book", "title": "LaTeX",
+                 "author": [{"family": "Lamport", "given": "Leslie"}],
+                 "issued": {"date-parts": [[1994, 6]]}, "publisher": "Addison-Wesley"}]"#,
+        )
+        .unwrap();
+        assert_eq!(entries[0].key, "lamport94");
+        assert_eq!(entries[0].kind, "book");
+        assert_eq!(entries[0].year.as_deref(), Some("1994"));
+        assert_eq!(entries[0].authors[0].family, "Lamport");
+    }
+}
@@ -0,0 +1,516 @@
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result, anyhow};
+use pulldown_cmark::{Event, HeadingLevel, LinkType, Tag};
+use serde_json::{Map, Value};
+use url::Url;
+
+use crate::commands::{build_document, build_latex, build_pdf, DocumentFormat};
+use crate::config::Config;
+use crate::crossref;
+use crate::exec;
+use crate::front_matter::{document_title, parse_front_matter, split_front_matter};
+use crate::includes;
+use crate::render::{render_doc, NavLink, Navigation, RenderOptions};
+use crate::utils::{create_parent_directories, err, notify_overwrite, relative_path};
+
+/// What `tatum book` builds
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum BookFormat {
+    /// A single PDF, with each chapter starting a new page
+    Pdf,
+    /// The standalone `.tex` file the PDF would be built from
+    Latex,
+    /// A directory with a page for each chapter, linked to the previous and next one
+    Html,
+    /// An EPUB3 for e-readers, with a table of contents of the chapters and their sections
+    Epub,
+}
+
+/// A markdown file of a book
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+    pub title: String,
+    pub path: PathBuf,
+    /// How deeply the chapter is nested in the summary. Its headings are moved down as many levels
+    pub depth: usize,
+}
+
+/// Markdown files combined in reading order, listed in a `SUMMARY.md` or the `[book]` section of
+/// the config
+#[derive(Debug, Clone)]
+pub struct Book {
+    pub title: String,
+    /// The directory chapter paths are relative to
+    pub root: PathBuf,
+    pub summary: Option<PathBuf>,
+    pub chapters: Vec<Chapter>,
+}
+
+impl Book {
+    /// Loads the book of `summary`, which may be a `SUMMARY.md` or a directory containing one.
+    ///
+    /// Without `summary`, the chapters of the config are used, falling back to `./SUMMARY.md`.
+    pub fn load(summary: Option<&Path>, config: &Config) -> Result<Book> {
+        let summary = match summary {
+            Some(path) if path.is_dir() => Some(path.join("SUMMARY.md")),
+            Some(path) => Some(path.to_path_buf()),
+            None if !config.book.chapters.is_empty() => None,
+            None => Some(PathBuf::from("SUMMARY.md")),
+        };
+
+        let Some(summary) = summary else {
+            let root = config.project_root.clone().unwrap_or_else(|| PathBuf::from("."));
+            let root = root.canonicalize().unwrap_or(root);
+            let chapters = config
+                .book
+                .chapters
+                .iter()
+                .map(|path| {
+                    let path = canonicalize_chapter(path)?;
+                    Ok(Chapter { title: document_title(&path), path, depth: 0 })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let title = config.book.title.clone().unwrap_or_else(|| dir_name(&root));
+            return Ok(Book { title, root, summary: None, chapters });
+        };
+
+        let source = fs::read_to_string(&summary).with_context(|| {
+            err(format!(
+                "Could not read {:?}. Pass a SUMMARY.md, or list `chapters` under [book] in \
+                 .tatum/config.toml",
+                summary
+            ).as_str())
+        })?;
+        let summary = summary.canonicalize()?;
+        let root = summary.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+
+        let (title, entries) = parse_summary(&source);
+        let chapters = entries
+            .into_iter()
+            .map(|(title, path, depth)| {
+                Ok(Chapter { title, path: canonicalize_chapter(&root.join(path))?, depth })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let title = title
+            .or_else(|| config.book.title.clone())
+            .unwrap_or_else(|| dir_name(&root));
+
+        Ok(Book { title, root, summary: Some(summary), chapters })
+    }
+
+    /// Combines the chapters into one markdown document for pandoc.
+    ///
+    /// Each chapter starts with a level 1 heading, moved down by its depth, so that it becomes a
+    /// `\chapter`. Links to other chapters point to them within the document instead, and
+    /// relative images are made absolute, as the document is not next to its chapters.
+    fn to_markdown(&self, config: &Config) -> Result<String> {
+        let anchors: HashMap<PathBuf, String> = self
+            .chapters
+            .iter()
+            .map(|chapter| (chapter.path.clone(), self.anchor(chapter)))
+            .collect();
+
+        let mut body = String::new();
+        let mut bibliography = Vec::new();
+        for chapter in &self.chapters {
+            let source = fs::read_to_string(&chapter.path)
+                .with_context(|| err(format!("Could not read {:?}", chapter.path).as_str()))?;
+            let source = includes::expand(&chapter.path, &source)?.source;
+            let source = exec::run_blocks(
+                &chapter.path,
+                &source,
+                &config.exec,
+                config.exec_enabled(),
+            );
+
+            // bibliographies are relative to the chapter naming them
+            let dir = chapter.path.parent().unwrap_or(Path::new("."));
+            match parse_front_matter(&source)?.remove("bibliography") {
+                Some(Value::String(file)) => bibliography.push(dir.join(file)),
+                Some(Value::Array(files)) => bibliography.extend(
+                    files.iter().filter_map(Value::as_str).map(|file| dir.join(file)),
+                ),
+                _ => {}
+            }
+
+            let source = split_front_matter(&source).1;
+            body.push_str(&chapter_markdown(chapter, source, &anchors[&chapter.path], &anchors));
+            body.push_str("\n\n");
+        }
+
+        // report, so that level 1 headings become chapters
+        let mut meta = Map::new();
+        meta.insert("title".into(), self.title.clone().into());
+        meta.insert("documentclass".into(), "report".into());
+        meta.insert("toc".into(), true.into());
+        meta.insert("numbersections".into(), true.into());
+        bibliography.dedup();
+        if !bibliography.is_empty() {
+            let files = bibliography.iter().map(|file| file.to_string_lossy().into());
+            meta.insert("bibliography".into(), Value::Array(files.collect()));
+        }
+
+        Ok(format!("---\n{}\n---\n\n{}", Value::Object(meta), body))
+    }
+
+    /// The id a chapter is linked to within the combined document
+    fn anchor(&self, chapter: &Chapter) -> String {
+        let path = chapter.path.strip_prefix(&self.root).unwrap_or(&chapter.path);
+        let mut slug = String::from("chapter");
+        for word in path
+            .with_extension("")
+            .to_string_lossy()
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+        {
+            slug.push('-');
+            slug.push_str(word);
+        }
+        slug
+    }
+
+    /// Where the page of a chapter is written, mirroring its place under the root
+    fn page_path(&self, out_dir: &Path, chapter: &Chapter) -> PathBuf {
+        let path = match chapter.path.strip_prefix(&self.root) {
+            Ok(path) => path,
+            Err(_) => Path::new(chapter.path.file_name().unwrap_or_default()),
+        };
+        out_dir.join(path).with_extension("html")
+    }
+}
+
+/// Builds the book of `summary` as a PDF, a `.tex` file, an HTML site or an EPUB
+pub async fn build(
+    summary: Option<PathBuf>,
+    format: BookFormat,
+    out: Option<PathBuf>,
+    template: Option<String>,
+    parent: bool,
+    exec: Option<bool>
+) -> Result<()> {
+
+    let start = match &summary {
+        Some(path) => path.clone(),
+        None => std::env::current_dir()?,
+    };
+    let mut config = Config::load(&start)?;
+    config.set_exec(exec);
+    let template_path = config.resolve_template(template)?;
+    let book = Book::load(summary.as_deref(), &config)?;
+    if book.chapters.is_empty() {
+        return Err(anyhow!(err("The book has no chapters")));
+    }
+
+    let default_dir = |dir: &Option<PathBuf>| dir.clone().unwrap_or_else(|| book.root.clone());
+    let out = out.unwrap_or_else(|| match format {
+        BookFormat::Pdf => default_dir(&config.output.pdf).join("book.pdf"),
+        BookFormat::Latex => default_dir(&config.output.latex).join("book.tex"),
+        BookFormat::Html => default_dir(&config.output.html).join("book"),
+        BookFormat::Epub => default_dir(&config.output.epub).join("book.epub"),
+    });
+
+    if format == BookFormat::Html {
+        build_site(&book, &out, &template_path, exec).await?;
+        println!("Book written to {:?}", out);
+        return Ok(());
+    }
+
+    if out.exists() {
+        notify_overwrite()?;
+    }
+    if parent {
+        create_parent_directories(&out);
+    }
+
+    let markdown = book.to_markdown(&config)?;
+    // chapters have been run already
+    config.exec.enabled = Some(false);
+
+    let build_dir = tempfile::Builder::new()
+        .prefix("tatum-book-")
+        .tempdir()
+        .with_context(|| err("Could not create build directory"))?;
+    let md_path = build_dir.path().join("book.md");
+    fs::write(&md_path, markdown).with_context(|| err("Could not write the combined book"))?;
+
+    match format {
+        BookFormat::Latex => build_latex(&md_path, &template_path, &out, &config, false)?,
+        BookFormat::Epub => {
+            build_document(&md_path, &template_path, &out, &config, DocumentFormat::Epub)?
+        }
+        _ => build_pdf(&md_path, &template_path, &out, &config, false, false)?,
+    }
+    println!("Book written to {:?}", out);
+
+    Ok(())
+}
+
+/// Renders each chapter to a page of `out_dir`, linked to the chapters before and after it.
+///
+/// The summary becomes `index.html`. Books listed in the config get an `index.html` leading to
+/// the first chapter instead.
+async fn build_site(
+    book: &Book,
+    out_dir: &Path,
+    template_path: &str,
+    exec: Option<bool>
+) -> Result<()> {
+    if out_dir.join("index.html").exists() {
+        notify_overwrite()?;
+    }
+    fs::create_dir_all(out_dir)
+        .with_context(|| err(format!("Could not create {:?}", out_dir).as_str()))?;
+    let out_dir = out_dir.canonicalize()?;
+
+    let pages: Vec<PathBuf> = book
+        .chapters
+        .iter()
+        .map(|chapter| book.page_path(&out_dir, chapter))
+        .collect();
+    let link = |from: &Path, i: usize| NavLink {
+        title: book.chapters[i].title.clone(),
+        href: relative_path(from.parent().unwrap_or(&out_dir), &pages[i])
+            .to_string_lossy()
+            .into_owned(),
+    };
+
+    for (i, chapter) in book.chapters.iter().enumerate() {
+        let page = &pages[i];
+        let options = RenderOptions {
+            exec,
+            static_links: true,
+            navigation: Navigation {
+                previous: i.checked_sub(1).map(|previous| link(page, previous)),
+                next: (i + 1 < pages.len()).then(|| link(page, i + 1)),
+            },
+            ..RenderOptions::default()
+        };
+        let html = render_doc(&chapter.path, &options, template_path.to_string()).await?;
+
+        if let Some(dir) = page.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| err(format!("Could not create {:?}", dir).as_str()))?;
+        }
+        fs::write(page, html).with_context(|| err(format!("Could not write {:?}", page).as_str()))?;
+    }
+
+    let index = out_dir.join("index.html");
+    let html = match &book.summary {
+        Some(summary) => {
+            let options = RenderOptions {
+                static_links: true,
+                navigation: Navigation { previous: None, next: Some(link(&index, 0)) },
+                ..RenderOptions::default()
+            };
+            render_doc(summary, &options, template_path.to_string()).await?
+        }
+        None => {
+            let first = link(&index, 0).href;
+            format!(
+                "<!DOCTYPE html>\n<meta http-equiv=\"refresh\" content=\"0; url={}\">\n",
+                first
+            )
+        }
+    };
+    fs::write(&index, html).with_context(|| err(format!("Could not write {:?}", index).as_str()))?;
+
+    Ok(())
+}
+
+/// Reads the title and chapters of a summary in the format of mdBook:
+///
+/// ```markdown
+/// # Linear Algebra
+///
+/// [Preface](preface.md)
+///
+/// - [Vector Spaces](vector-spaces.md)
+///     - [Subspaces](vector-spaces/subspaces.md)
+/// - [Linear Maps](linear-maps.md)
+/// ```
+///
+/// The depth of a chapter follows the indentation of its list item. Items without a file, i.e.
+/// drafts, are left out.
+fn parse_summary(source: &str) -> (Option<String>, Vec<(String, String, usize)>) {
+    let mut title = None;
+    let mut chapters = Vec::new();
+    let mut indents: Vec<usize> = Vec::new();
+
+    for line in source.lines() {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+
+        if let Some(heading) = trimmed.strip_prefix("# ") {
+            title.get_or_insert_with(|| heading.trim().to_string());
+            continue;
+        }
+
+        let item = trimmed
+            .strip_prefix("- ")
+            .or_else(|| trimmed.strip_prefix("* "))
+            .unwrap_or(trimmed);
+        let Some(rest) = item.strip_prefix('[') else { continue };
+        let Some((name, rest)) = rest.split_once("](") else { continue };
+        let Some((path, _)) = rest.split_once(')') else { continue };
+
+        while indents.last().is_some_and(|last| *last >= indent) {
+            indents.pop();
+        }
+        let depth = indents.len();
+        indents.push(indent);
+
+        if !path.trim().is_empty() {
+            chapters.push((name.to_string(), path.trim().to_string(), depth));
+        }
+    }
+
+    (title, chapters)
+}
+
+/// The markdown of a chapter within the combined document, given the anchors of all chapters
+fn chapter_markdown(
+    chapter: &Chapter,
+    source: &str,
+    anchor: &str,
+    anchors: &HashMap<PathBuf, String>,
+) -> String {
+    let dir = chapter.path.parent().unwrap_or(Path::new("."));
+    let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+    let mut has_title = false;
+
+    for (event, range) in crossref::parse(source) {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                let text = &source[range.clone()];
+                let hashes = text.trim_start().chars().take_while(|c| *c == '#').count();
+                let start = range.start + text.len() - text.trim_start().len();
+
+                // headings underlined with `===` or `---` keep their level
+                if hashes > 0 {
+                    let level = (hashes + chapter.depth).min(6);
+                    edits.push((start..start + hashes, "#".repeat(level)));
+                }
+                if level == HeadingLevel::H1 && !has_title {
+                    has_title = true;
+                    let end = source[..range.end].trim_end().len();
+                    edits.push((end..end, format!("\n\n[]{{#{}}}", anchor)));
+                }
+            }
+            Event::Start(Tag::Link { link_type: LinkType::Inline, dest_url, .. }) => {
+                if dest_url.parse::<Url>().is_ok() || dest_url.starts_with('#') {
+                    continue;
+                }
+                let (file, fragment) = dest_url.split_once('#').unwrap_or((&dest_url, ""));
+                let Some(target) = dir.join(file).canonicalize().ok() else { continue };
+                let Some(target_anchor) = anchors.get(&target) else { continue };
+
+                let Some(dest) = destination(&source[range.clone()], &dest_url) else { continue };
+                let link = match fragment {
+                    "" => format!("#{}", target_anchor),
+                    fragment => format!("#{}", fragment),
+                };
+                edits.push((range.start + dest.start..range.start + dest.end, link));
+            }
+            Event::Start(Tag::Image { link_type: LinkType::Inline, dest_url, .. }) => {
+                if dest_url.parse::<Url>().is_ok() || Path::new(dest_url.as_ref()).is_absolute() {
+                    continue;
+                }
+                let Some(dest) = destination(&source[range.clone()], &dest_url) else { continue };
+                let image = dir.join(dest_url.as_ref());
+                edits.push((
+                    range.start + dest.start..range.start + dest.end,
+                    format!("<{}>", image.to_string_lossy()),
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    let mut out = String::with_capacity(source.len());
+    if !has_title {
+        out.push_str(&format!(
+            "{} {}\n\n[]{{#{}}}\n\n",
+            "#".repeat((1 + chapter.depth).min(6)),
+            chapter.title,
+            anchor
+        ));
+    }
+
+    let mut last = 0;
+    edits.sort_by_key(|(range, _)| range.start);
+    for (range, text) in edits {
+        out.push_str(&source[last..range.start]);
+        out.push_str(&text);
+        last = range.end;
+    }
+    out.push_str(&source[last..]);
+    out.trim_end().to_string()
+}
+
+/// Where the destination of an inline link or image is within its source, e.g. `a.md` in
+/// `[see](a.md "A")`
+fn destination(text: &str, dest: &str) -> Option<Range<usize>> {
+    [format!("](<{}>", dest), format!("]({}", dest)]
+        .iter()
+        .find_map(|candidate| text.rfind(candidate.as_str()).map(|i| i + 2..i + candidate.len()))
+}
+
+fn canonicalize_chapter(path: &Path) -> Result<PathBuf> {
+    path.canonicalize()
+        .with_context(|| err(format!("Chapter {:?} does not exist", path).as_str()))
+}
+
+fn dir_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "Book".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_summary() {
+        let source = "# Linear Algebra\n\n[Preface](preface.md)\n\n\
+                      - [Vector Spaces](vector-spaces.md)\n    \
+                      - [Subspaces](vector-spaces/subspaces.md)\n    \
+                      - [Draft]()\n\
+                      - [Linear Maps](linear-maps.md)\n";
+        let (title, chapters) = parse_summary(source);
+        assert_eq!(title.as_deref(), Some("Linear Algebra"));
+        assert_eq!(
+            chapters,
+            vec![
+                ("Preface".into(), "preface.md".into(), 0),
+                ("Vector Spaces".into(), "vector-spaces.md".into(), 0),
+                ("Subspaces".into(), "vector-spaces/subspaces.md".into(), 1),
+                ("Linear Maps".into(), "linear-maps.md".into(), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chapter_markdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::write(root.join("maps.md"), "# Maps\n").unwrap();
+        let chapter = Chapter { title: "Spaces".into(), path: root.join("spaces.md"), depth: 1 };
+        let anchors = HashMap::from([(root.join("maps.md"), "chapter-maps".to_string())]);
+
+        let source = "# Spaces\n\nSee [maps](maps.md) and [kernels](maps.md#kernel).\n\n\
+                      ## Bases\n\n![basis](basis.png)\n";
+        assert_eq!(
+            chapter_markdown(&chapter, source, "chapter-spaces", &anchors),
+            format!(
+                "## Spaces\n\n[]{{#chapter-spaces}}\n\nSee [maps](#chapter-maps) and \
+                 [kernels](#kernel).\n\n### Bases\n\n![basis](<{}>)",
+                root.join("basis.png").to_string_lossy()
+            )
+        );
+    }
+}
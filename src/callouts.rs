@@ -0,0 +1,441 @@
+use pulldown_cmark::{BlockQuoteKind, CowStr, Event, Options, Parser, Tag, TagEnd};
+
+/// Callout types with the colour of their box. GitHub's five alerts are included, along with
+/// the types Obsidian adds
+static KINDS: &[(&str, &str)] = &[
+    ("note", "4493F8"),
+    ("abstract", "00BFBC"),
+    ("info", "4493F8"),
+    ("todo", "4493F8"),
+    ("tip", "3FB950"),
+    ("success", "08B94E"),
+    ("question", "EC7500"),
+    ("warning", "D29922"),
+    ("failure", "E93147"),
+    ("danger", "E93147"),
+    ("bug", "E93147"),
+    ("example", "7852EE"),
+    ("quote", "9E9E9E"),
+    ("important", "AB7DF8"),
+    ("caution", "F85149"),
+];
+
+/// Other names Obsidian accepts for a callout type
+static ALIASES: &[(&str, &str)] = &[
+    ("summary", "abstract"),
+    ("tldr", "abstract"),
+    ("hint", "tip"),
+    ("check", "success"),
+    ("done", "success"),
+    ("help", "question"),
+    ("faq", "question"),
+    ("attention", "warning"),
+    ("fail", "failure"),
+    ("missing", "failure"),
+    ("error", "danger"),
+    ("cite", "quote"),
+];
+
+/// A `[!type]` marker starting a blockquote
+#[derive(Debug, Clone, PartialEq)]
+struct Marker<'a> {
+    /// The type as written, in lower case
+    kind: String,
+    /// `Some(true)` for `[!type]+`, which is folded open, and `Some(false)` for `[!type]-`
+    fold: Option<bool>,
+    /// The rest of the line, used as the title
+    title: &'a str,
+}
+
+impl Marker<'_> {
+    /// The type the callout is styled as. Unknown types look like notes, as in Obsidian
+    fn canonical(&self) -> &'static str {
+        let kind = ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == self.kind)
+            .map_or(self.kind.as_str(), |(_, kind)| kind);
+        KINDS.iter().find(|(name, _)| *name == kind).map_or("note", |(name, _)| name)
+    }
+
+    /// The title shown when none is given, e.g. `Warning`
+    fn default_title(&self) -> String {
+        let mut chars = self.kind.chars();
+        match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect(),
+            None => String::new(),
+        }
+    }
+}
+
+/// Parses `[!type]`, `[!type]+ Title` or `[!type]- Title` at the start of a line
+fn parse_marker(text: &str) -> Option<Marker<'_>> {
+    let rest = text.trim_start().strip_prefix("[!")?;
+    let close = rest.find(']')?;
+    let kind = &rest[..close];
+    if kind.is_empty() || !kind.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+        return None;
+    }
+
+    let rest = &rest[close + 1..];
+    let (fold, title) = match rest.chars().next() {
+        Some('+') => (Some(true), &rest[1..]),
+        Some('-') => (Some(false), &rest[1..]),
+        _ => (None, rest),
+    };
+    Some(Marker { kind: kind.to_lowercase(), fold, title: title.trim_start() })
+}
+
+fn gfm_kind(kind: BlockQuoteKind) -> &'static str {
+    match kind {
+        BlockQuoteKind::Note => "note",
+        BlockQuoteKind::Tip => "tip",
+        BlockQuoteKind::Important => "important",
+        BlockQuoteKind::Warning => "warning",
+        BlockQuoteKind::Caution => "caution",
+    }
+}
+
+/// Turns blockquotes starting with a callout marker into callout boxes.
+///
+/// Callouts become `<div class="callout callout-<type>">`, or `<details>` when they can be
+/// folded, with a `callout-title` and a `callout-content` inside.
+pub fn to_html(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    let mut out = Vec::with_capacity(events.len());
+    // the closing HTML of each open blockquote, if it is a callout
+    let mut open: Vec<Option<&str>> = Vec::new();
+    let mut events = events.into_iter().peekable();
+
+    while let Some(event) = events.next() {
+        let quote = match event {
+            Event::Start(Tag::BlockQuote(quote)) => quote,
+            Event::End(TagEnd::BlockQuote) => {
+                match open.pop().flatten() {
+                    Some(closing) => out.push(Event::Html(closing.into())),
+                    None => out.push(Event::End(TagEnd::BlockQuote)),
+                }
+                continue;
+            }
+            event => {
+                out.push(event);
+                continue;
+            }
+        };
+
+        // GitHub alerts are recognised by the parser already, Obsidian callouts are the text of
+        // the first paragraph
+        let mut title_events = Vec::new();
+        let mut reopen_paragraph = false;
+        let marker = match quote {
+            Some(kind) => Some(Marker { kind: gfm_kind(kind).to_string(), fold: None, title: "" }),
+            None => {
+                if !matches!(events.peek(), Some(Event::Start(Tag::Paragraph))) {
+                    None
+                } else {
+                    let mut ahead = events.clone();
+                    ahead.next();
+                    let mut text = String::new();
+                    while let Some(Event::Text(part)) = ahead.peek() {
+                        text.push_str(part);
+                        ahead.next();
+                    }
+                    match parse_marker(&text) {
+                        Some(marker) => {
+                            let title = marker.title.to_string();
+                            if !title.trim().is_empty() {
+                                title_events.push(Event::Text(CowStr::from(title)));
+                            }
+                            // the title runs to the end of the first line
+                            loop {
+                                match ahead.next() {
+                                    Some(Event::SoftBreak | Event::HardBreak) => {
+                                        reopen_paragraph = true;
+                                        break;
+                                    }
+                                    Some(Event::End(TagEnd::Paragraph)) | None => break,
+                                    Some(event) => title_events.push(event),
+                                }
+                            }
+                            events = ahead;
+                            Some(Marker { title: "", ..marker })
+                        }
+                        None => None,
+                    }
+                }
+            }
+        };
+
+        let Some(marker) = marker else {
+            out.push(Event::Start(Tag::BlockQuote(quote)));
+            open.push(None);
+            continue;
+        };
+
+        let (tag, title_tag) = match marker.fold {
+            Some(_) => ("details", "summary"),
+            None => ("div", "div"),
+        };
+        let expanded = if marker.fold == Some(true) { " open" } else { "" };
+        out.push(Event::Html(
+            format!(
+                "<{} class=\"callout callout-{}\" data-callout=\"{}\"{}>\n\
+                 <{} class=\"callout-title\">",
+                tag,
+                marker.canonical(),
+                marker.kind,
+                expanded,
+                title_tag
+            )
+            .into(),
+        ));
+        if title_events.is_empty() {
+            out.push(Event::Text(marker.default_title().into()));
+        }
+        out.extend(title_events);
+        out.push(Event::Html(
+            format!("</{}>\n<div class=\"callout-content\">\n", title_tag).into(),
+        ));
+        if reopen_paragraph {
+            out.push(Event::Start(Tag::Paragraph));
+        }
+        open.push(Some(if tag == "details" { "</div>\n</details>\n" } else { "</div>\n</div>\n" }));
+    }
+
+    out
+}
+
+/// Turns callouts into `tatumcallout` boxes for pandoc, made with tcolorbox.
+///
+/// Folding is left out in LaTeX, as the content of a PDF is always shown.
+pub fn to_latex_markdown(source: &str) -> String {
+    convert_callouts(source, latex_callout)
+}
+
+/// Replaces the top level blockquotes of a document that are callouts with what `convert`
+/// returns for their source
+fn convert_callouts(source: &str, convert: fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut last = 0;
+    let mut depth = 0;
+
+    for (event, range) in Parser::new_ext(source, Options::all()).into_offset_iter() {
+        match event {
+            Event::Start(Tag::BlockQuote(_)) => {
+                depth += 1;
+                if depth > 1 {
+                    continue;
+                }
+                let Some(converted) = convert(&source[range.clone()]) else { continue };
+                out.push_str(&source[last..range.start]);
+                out.push_str(&converted);
+                last = range.end;
+            }
+            Event::End(TagEnd::BlockQuote) => depth -= 1,
+            _ => {}
+        }
+    }
+
+    out.push_str(&source[last..]);
+    out
+}
+
+/// Converts the source of a blockquote, if it is a callout. Callouts nested in it are
+/// converted as well
+fn latex_callout(quote: &str) -> Option<String> {
+    let mut lines = quote.split_inclusive('\n');
+    let marker = parse_marker(unquote(lines.next()?).1)?;
+    let lines: Vec<_> = lines.map(unquote).collect();
+
+    // the quote starts at its `>`, so the indent of a quote in a list is on the lines below, and
+    // is already in front of the first one
+    let indent = lines.first().map_or("", |(indent, _)| *indent);
+    let content: String =
+        lines.iter().map(|(indent, rest)| format!("{}{}", indent, rest)).collect();
+
+    let title = match marker.title.trim() {
+        "" => marker.default_title(),
+        title => latex_title(title),
+    };
+    let mut out = format!(
+        "```{{=latex}}\n{indent}\\begin{{tatumcallout}}{{callout-{}}}{{{}}}\n{indent}```\n",
+        marker.canonical(),
+        title
+    );
+    out.push_str(&to_latex_markdown(&content));
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str(&format!("{indent}```{{=latex}}\n{indent}\\end{{tatumcallout}}\n{indent}```\n"));
+    Some(out)
+}
+
+/// Turns callouts into blockquotes starting with their title in bold, for pandoc's Word and
+/// OpenDocument writers, which have no boxes. Folding is left out, as in LaTeX.
+pub fn to_word_markdown(source: &str) -> String {
+    convert_callouts(source, word_callout)
+}
+
+/// Converts the source of a blockquote, if it is a callout, along with the callouts nested in it
+fn word_callout(quote: &str) -> Option<String> {
+    let mut lines = quote.split_inclusive('\n');
+    let marker = parse_marker(unquote(lines.next()?).1)?;
+    let lines: Vec<_> = lines.map(unquote).collect();
+
+    // as in `latex_callout`, the indent of the first line is already in front of the quote
+    let indent = lines.first().map_or("", |(indent, _)| *indent);
+    let content: String = lines.iter().map(|(_, rest)| *rest).collect();
+
+    let title = match marker.title.trim() {
+        "" => marker.default_title(),
+        title => title.to_string(),
+    };
+    let mut out = format!("> **{}**\n{indent}>\n", title);
+    for line in to_word_markdown(&content).split_inclusive('\n') {
+        let line = line.trim_end_matches('\n');
+        let separator = if line.is_empty() { "" } else { " " };
+        out.push_str(&format!("{indent}>{separator}{line}\n"));
+    }
+    if !quote.ends_with('\n') {
+        out.pop();
+    }
+    Some(out)
+}
+
+/// Turns callouts into coloured boxes for the Typst export, written as raw Typst around their
+/// content
+pub fn to_typst_markdown(source: &str) -> String {
+    convert_callouts(source, typst_callout)
+}
+
+/// Converts the source of a blockquote, if it is a callout, along with the callouts nested in it
+fn typst_callout(quote: &str) -> Option<String> {
+    let mut lines = quote.split_inclusive('\n');
+    let marker = parse_marker(unquote(lines.next()?).1)?;
+    let lines: Vec<_> = lines.map(unquote).collect();
+
+    // as in `latex_callout`, the indent of the first line is already in front of the quote
+    let indent = lines.first().map_or("", |(indent, _)| *indent);
+    let content: String =
+        lines.iter().map(|(indent, rest)| format!("{}{}", indent, rest)).collect();
+
+    let title = match marker.title.trim() {
+        "" => marker.default_title(),
+        title => title.to_string(),
+    };
+    let color = KINDS
+        .iter()
+        .find(|(name, _)| *name == marker.canonical())
+        .map_or("4493F8", |(_, color)| color);
+    let mut out = format!(
+        "```{{=typst}}\n{indent}#block(width: 100%, inset: 8pt, radius: 2pt, \
+         stroke: (left: 2pt + rgb(\"#{color}\")), fill: rgb(\"#{color}\").lighten(90%))[\n\
+         {indent}```\n{indent}**{title}**\n{indent}\n"
+    );
+    out.push_str(&to_typst_markdown(&content));
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str(&format!("{indent}```{{=typst}}\n{indent}]\n{indent}```\n"));
+    Some(out)
+}
+
+/// Splits a line of a blockquote into its indent and the line without the `>`
+fn unquote(line: &str) -> (&str, &str) {
+    let trimmed = line.trim_start_matches([' ', '\t']);
+    let indent = &line[..line.len() - trimmed.len()];
+    match trimmed.strip_prefix('>') {
+        Some(rest) => (indent, rest.strip_prefix(' ').unwrap_or(rest)),
+        // lazy continuation lines have no `>`
+        None => (indent, trimmed),
+    }
+}
+
+/// Escapes a markdown title for LaTeX, keeping its math as it is
+fn latex_title(title: &str) -> String {
+    let mut out = String::new();
+    let mut in_math = false;
+    for c in title.chars() {
+        match c {
+            '$' => {
+                in_math = !in_math;
+                out.push(c);
+            }
+            _ if in_math => out.push(c),
+            '*' | '`' => {}
+            '&' | '%' | '#' | '_' | '{' | '}' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '~' => out.push_str("\\textasciitilde{}"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            '\\' => out.push_str("\\textbackslash{}"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// LaTeX defining the `tatumcallout` box and the colour of each type
+pub fn latex_preamble() -> String {
+    let mut preamble = String::from("\\usepackage{tcolorbox}\n\\tcbuselibrary{breakable}\n");
+    for (kind, color) in KINDS {
+        preamble.push_str(&format!("\\definecolor{{callout-{}}}{{HTML}}{{{}}}\n", kind, color));
+    }
+    preamble.push_str(
+        "\\newtcolorbox{tatumcallout}[2]{breakable, colback=#1!5!white, colframe=#1, \
+         fonttitle=\\bfseries, title={#2}}\n",
+    );
+    preamble
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOC: &str = "\
+> [!NOTE]
+> Plain note.
+
+> [!warning]- Foldable *title*
+> Body $x$.
+
+> [!hint]+
+>
+> Tip body
+
+> regular quote
+";
+
+    #[test]
+    fn test_to_html() {
+        let events = Parser::new_ext(DOC, Options::all()).collect();
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, to_html(events).into_iter());
+
+        assert!(html.contains(
+            "<div class=\"callout callout-note\" data-callout=\"note\">\n\
+             <div class=\"callout-title\">Note</div>\n<div class=\"callout-content\">\n\
+             <p>Plain note.</p>\n</div>\n</div>"
+        ));
+        assert!(html.contains(
+            "<details class=\"callout callout-warning\" data-callout=\"warning\">\n\
+             <summary class=\"callout-title\">Foldable <em>title</em></summary>"
+        ));
+        assert!(html.contains("<p>Body <span class=\"math math-inline\">x</span>.</p>"));
+        assert!(html.contains(
+            "<details class=\"callout callout-tip\" data-callout=\"hint\" open>\n\
+             <summary class=\"callout-title\">Hint</summary>"
+        ));
+        assert!(html.contains("<blockquote>\n<p>regular quote</p>\n</blockquote>"));
+    }
+
+    #[test]
+    fn test_to_latex_markdown() {
+        let markdown = to_latex_markdown(DOC);
+        assert!(markdown.contains(
+            "```{=latex}\n\\begin{tatumcallout}{callout-note}{Note}\n```\nPlain note.\n\
+             ```{=latex}\n\\end{tatumcallout}\n```\n"
+        ));
+        assert!(markdown.contains("\\begin{tatumcallout}{callout-warning}{Foldable title}"));
+        assert!(markdown.contains("> regular quote"));
+    }
+}
@@ -0,0 +1,526 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result, anyhow};
+use colored::*;
+use pulldown_cmark::{Event, Options, Parser, TagEnd};
+
+use crate::config::Config;
+use crate::latex_commands::{
+    is_katex_command, is_katex_environment, is_latex_command, is_package_command, katex_commands,
+};
+use crate::latex_log::Diagnostic;
+use crate::macros::read_katex_macros;
+use crate::utils::{err, err_no_md_file};
+
+/// Commands whose arguments are checked, with whether they take an optional `[...]` argument
+/// first and how many mandatory arguments follow
+static BUILTIN_ARGS: &[(&str, bool, usize)] = &[
+    ("frac", false, 2),
+    ("dfrac", false, 2),
+    ("tfrac", false, 2),
+    ("cfrac", false, 2),
+    ("binom", false, 2),
+    ("dbinom", false, 2),
+    ("tbinom", false, 2),
+    ("overset", false, 2),
+    ("underset", false, 2),
+    ("stackrel", false, 2),
+    ("textcolor", false, 2),
+    ("sqrt", true, 1),
+    ("text", false, 1),
+    ("mathbb", false, 1),
+    ("mathbf", false, 1),
+    ("mathrm", false, 1),
+    ("mathcal", false, 1),
+    ("operatorname", false, 1),
+];
+
+/// Commands that define a new macro named by the control sequence following them
+static DEFINING_COMMANDS: &[&str] = &[
+    "def", "gdef", "edef", "xdef", "let", "newcommand", "renewcommand", "providecommand",
+];
+
+/// Checks the math of a markdown file for mistakes that break the LaTeX build
+pub fn check(file: PathBuf, template: Option<String>) -> Result<()> {
+    if !file.exists() {
+        return Err(anyhow!(err_no_md_file(&file)));
+    }
+
+    let source = fs::read_to_string(&file)
+        .with_context(|| err(format!("Could not read {:?}", file).as_str()))?;
+
+    // the macros of the template are optional, unless a template is asked for
+    let config = Config::load(&file)?;
+    let template_path = match template {
+        Some(template) => Some(config.resolve_template(Some(template))?),
+        None => config.resolve_template(None).ok(),
+    };
+    let mut macros = MathMacros::default();
+    if let Some(template_path) = &template_path {
+        if Path::new(template_path).join("katex-macros.js").exists() {
+            for m in read_katex_macros(template_path)?.macros {
+                let name = m.name.trim_start_matches('\\').to_string();
+                macros.args.insert(name, (m.default.is_some(), m.args as usize));
+            }
+        }
+    }
+
+    let diagnostics = check_markdown(&source, &mut macros);
+    let name = file.to_string_lossy();
+
+    for diagnostic in &diagnostics {
+        println!("{}\n", diagnostic.pretty(&name));
+    }
+
+    if !diagnostics.is_empty() {
+        return Err(anyhow!(err(
+            format!("Found {} problem(s) in {}", diagnostics.len(), name).as_str()
+        )));
+    }
+
+    println!("{} No problems found in {}", "✓".green().bold(), name);
+    Ok(())
+}
+
+/// The macros math can use on top of the commands KaTeX supports
+#[derive(Debug, Default)]
+pub struct MathMacros {
+    /// Macros of katex-macros.js by name without the backslash, with whether the first argument
+    /// is optional and the number of arguments
+    pub args: HashMap<String, (bool, usize)>,
+    /// Macros defined in the document itself, e.g. with `\gdef`
+    pub defined: HashSet<String>,
+}
+
+/// Checks every inline and display math expression of a markdown document
+pub fn check_markdown(source: &str, macros: &mut MathMacros) -> Vec<Diagnostic> {
+    let expressions = math_expressions(source);
+
+    // macros defined in one expression can be used in the next, so collect them first
+    for (_, math, _) in &expressions {
+        macros.defined.extend(defined_macros(&tokenize(math)));
+    }
+
+    let mut diagnostics = Vec::new();
+    for (start, math, parsed) in &expressions {
+        let mut problems = check_math(math, macros);
+
+        // `$` pairs that are not parsed as math are usually just dollar signs, unless the braces
+        // inside them are unbalanced, which stops the parser from seeing the math
+        if !parsed {
+            if !problems.iter().any(|p| p.message.contains('{') || p.message.contains('}')) {
+                continue;
+            }
+            for problem in problems.iter_mut().filter(|p| p.hint.is_none()) {
+                problem.hint =
+                    Some("Because of this, the expression is shown as plain text".to_string());
+            }
+        }
+
+        for problem in problems {
+            let offset = start + problem.offset;
+            let line = source[..offset].matches('\n').count() + 1;
+            let snippet = source.lines().nth(line - 1).unwrap_or_default().trim().to_string();
+
+            diagnostics.push(Diagnostic {
+                message: problem.message,
+                tex_line: None,
+                context: None,
+                source_line: Some(line),
+                snippet: Some(snippet),
+                hint: problem.hint,
+            });
+        }
+    }
+    diagnostics
+}
+
+/// Returns the math expressions of a document with their offset, and whether the markdown parser
+/// recognised them as math.
+///
+/// Math with unbalanced braces is not recognised, so pairs of stray `$` or `$$` in a block are
+/// returned as well.
+fn math_expressions(source: &str) -> Vec<(usize, String, bool)> {
+    let mut expressions = Vec::new();
+    let mut dollars = Vec::new();
+
+    for (event, range) in Parser::new_ext(source, Options::all()).into_offset_iter() {
+        match event {
+            Event::InlineMath(math) | Event::DisplayMath(math) => {
+                // offset of the expression itself, after the opening `$` or `$$`
+                let start = range.start + source[range].find(math.as_ref()).unwrap_or(0);
+                expressions.push((start, math.to_string(), true));
+            }
+            Event::Text(text) if text.as_ref() == "$" => dollars.push(range.start),
+            Event::End(
+                TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::Item | TagEnd::TableCell,
+            ) => {
+                expressions.extend(stray_math(source, &dollars));
+                dollars.clear();
+            }
+            _ => {}
+        }
+    }
+
+    expressions.sort_by_key(|(start, _, _)| *start);
+    expressions
+}
+
+/// Pairs up the stray `$` at `dollars`, treating two adjacent ones as `$$`
+fn stray_math(source: &str, dollars: &[usize]) -> Vec<(usize, String, bool)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for &offset in dollars {
+        match runs.last_mut() {
+            Some((start, len)) if *start + *len == offset && *len < 2 => *len += 1,
+            _ => runs.push((offset, 1)),
+        }
+    }
+
+    let mut expressions = Vec::new();
+    let mut open: Option<(usize, usize)> = None;
+    for (start, len) in runs {
+        match open {
+            Some((open_start, open_len)) if open_len == len => {
+                let content_start = open_start + open_len;
+                expressions.push((content_start, source[content_start..start].to_string(), false));
+                open = None;
+            }
+            _ => open = Some((start, len)),
+        }
+    }
+    expressions
+}
+
+/// A mistake in a math expression, at a byte offset into it
+#[derive(Debug, Clone, PartialEq)]
+pub struct MathProblem {
+    pub offset: usize,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    /// A control word such as `\alpha`, without the backslash
+    Word(String),
+    /// A control symbol such as `\{` or `\,`
+    Symbol(char),
+    Char(char),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    kind: TokenKind,
+    offset: usize,
+}
+
+fn tokenize(math: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = math.char_indices().peekable();
+
+    while let Some((offset, c)) = chars.next() {
+        let kind = if c == '\\' {
+            match chars.peek().copied() {
+                Some((_, next)) if next.is_ascii_alphabetic() => {
+                    let mut word = String::new();
+                    while let Some(&(_, c)) = chars.peek() {
+                        if !c.is_ascii_alphabetic() {
+                            break;
+                        }
+                        word.push(c);
+                        chars.next();
+                    }
+                    TokenKind::Word(word)
+                }
+                Some((_, next)) => {
+                    chars.next();
+                    TokenKind::Symbol(next)
+                }
+                None => TokenKind::Char('\\'),
+            }
+        } else {
+            TokenKind::Char(c)
+        };
+        tokens.push(Token { kind, offset });
+    }
+
+    tokens
+}
+
+/// Names of the macros defined with e.g. `\gdef\name` or `\newcommand{\name}`
+fn defined_macros(tokens: &[Token]) -> Vec<String> {
+    let mut names = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let TokenKind::Word(word) = &token.kind else {
+            continue;
+        };
+        if !DEFINING_COMMANDS.contains(&word.as_str()) {
+            continue;
+        }
+
+        let name = tokens[i + 1..]
+            .iter()
+            .find(|t| !matches!(t.kind, TokenKind::Char(c) if c.is_whitespace() || c == '{'));
+        if let Some(Token { kind: TokenKind::Word(name), .. }) = name {
+            names.push(name.clone());
+        }
+    }
+    names
+}
+
+/// Checks a single math expression for unbalanced braces, `\left`/`\right` and
+/// `\begin`/`\end` pairs, unknown commands, and commands missing arguments
+pub fn check_math(math: &str, macros: &MathMacros) -> Vec<MathProblem> {
+    let tokens = tokenize(math);
+    let mut problems = Vec::new();
+    let mut problem = |offset: usize, message: String, hint: Option<&str>| {
+        problems.push(MathProblem { offset, message, hint: hint.map(str::to_string) })
+    };
+
+    let mut braces = Vec::new();
+    let mut lefts = Vec::new();
+    let mut environments: Vec<(usize, String)> = Vec::new();
+
+    for (i, token) in tokens.iter().enumerate() {
+        match &token.kind {
+            TokenKind::Char('{') => braces.push(token.offset),
+            TokenKind::Char('}') => {
+                let unmatched = braces.pop().is_none();
+                if unmatched {
+                    let hint = "Remove it, or escape it as `\\}`";
+                    problem(token.offset, "Unmatched `}`".into(), Some(hint));
+                }
+            }
+            TokenKind::Word(word) => match word.as_str() {
+                "left" => lefts.push(token.offset),
+                "right" => {
+                    if lefts.pop().is_none() {
+                        let message = "`\\right` without a matching `\\left`";
+                        problem(token.offset, message.into(), None);
+                    }
+                }
+                "middle" if lefts.is_empty() => {
+                    let message = "`\\middle` outside of `\\left` ... `\\right`";
+                    problem(token.offset, message.into(), None);
+                }
+                "begin" => {
+                    let name = group_text(&tokens, i + 1).unwrap_or_default();
+                    if !is_katex_environment(&name) {
+                        problem(token.offset, format!("Unknown environment `{}`", name), None);
+                    }
+                    environments.push((token.offset, name));
+                }
+                "end" => {
+                    let name = group_text(&tokens, i + 1).unwrap_or_default();
+                    match environments.pop() {
+                        Some((_, open)) if open == name => {}
+                        Some((_, open)) => problem(
+                            token.offset,
+                            format!("`\\end{{{}}}` closes `\\begin{{{}}}`", name, open),
+                            None,
+                        ),
+                        None => problem(
+                            token.offset,
+                            format!("`\\end{{{}}}` without a matching `\\begin`", name),
+                            None,
+                        ),
+                    }
+                }
+                _ => {
+                    if let Some((message, hint)) = unknown_command(word, macros) {
+                        problem(token.offset, message, hint.as_deref());
+                        continue;
+                    }
+
+                    let spec = macros.args.get(word).copied().or_else(|| {
+                        BUILTIN_ARGS
+                            .iter()
+                            .find(|(name, _, _)| name == word)
+                            .map(|(_, optional, args)| (*optional, *args + usize::from(*optional)))
+                    });
+                    if let Some((optional, args)) = spec {
+                        let required = args - usize::from(optional);
+                        let given = count_arguments(&tokens, i + 1, optional, required);
+                        if given < required {
+                            let verb = if given == 1 { "was" } else { "were" };
+                            let message = format!(
+                                "`\\{}` takes {} argument(s) but {} {} given",
+                                word, required, given, verb
+                            );
+                            problem(token.offset, message, None);
+                        }
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    for offset in braces {
+        problem(offset, "Unclosed `{`".to_string(), None);
+    }
+    for offset in lefts {
+        let hint = "Use `\\right.` for an invisible delimiter";
+        problem(offset, "`\\left` without a matching `\\right`".into(), Some(hint));
+    }
+    for (offset, name) in environments {
+        problem(offset, format!("`\\begin{{{}}}` is never closed", name), None);
+    }
+
+    problems.sort_by_key(|p| p.offset);
+    problems
+}
+
+/// Returns a message and hint if `word` is a command KaTeX does not know
+fn unknown_command(word: &str, macros: &MathMacros) -> Option<(String, Option<String>)> {
+    if is_katex_command(word) || macros.args.contains_key(word) || macros.defined.contains(word) {
+        return None;
+    }
+
+    let hint = if is_latex_command(word) || is_package_command(word) {
+        Some(format!("`\\{}` works in LaTeX but not in KaTeX. Define it in katex-macros.js", word))
+    } else {
+        let builtin = katex_commands().map(|name| -> &str { name });
+        let candidates = builtin.chain(macros.args.keys().map(String::as_str));
+        closest(word, candidates).map(|name| format!("Did you mean `\\{}`?", name))
+    };
+
+    Some((format!("Unknown command `\\{}`", word), hint))
+}
+
+/// Finds the closest name to `word` within a small edit distance
+fn closest<'a>(word: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max = if word.len() <= 3 { 1 } else { 2 };
+    candidates
+        .map(|candidate| (edit_distance(word, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+fn skip_whitespace(tokens: &[Token], mut i: usize) -> usize {
+    while let Some(Token { kind: TokenKind::Char(c), .. }) = tokens.get(i) {
+        if !c.is_whitespace() {
+            break;
+        }
+        i += 1;
+    }
+    i
+}
+
+/// Index after the group opened at `i`, or the end if it is never closed
+fn skip_group(tokens: &[Token], i: usize, open: char, close: char) -> usize {
+    let mut depth = 0;
+    for (j, token) in tokens.iter().enumerate().skip(i) {
+        match token.kind {
+            TokenKind::Char(c) if c == open => depth += 1,
+            TokenKind::Char(c) if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return j + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    tokens.len()
+}
+
+/// Counts the arguments following a command, up to `required`
+fn count_arguments(tokens: &[Token], mut i: usize, optional: bool, required: usize) -> usize {
+    i = skip_whitespace(tokens, i);
+    if optional && matches!(tokens.get(i), Some(Token { kind: TokenKind::Char('['), .. })) {
+        i = skip_group(tokens, i, '[', ']');
+    }
+
+    for given in 0..required {
+        i = skip_whitespace(tokens, i);
+        match tokens.get(i).map(|t| &t.kind) {
+            None | Some(TokenKind::Char('}')) => return given,
+            Some(TokenKind::Char('{')) => i = skip_group(tokens, i, '{', '}'),
+            Some(_) => i += 1,
+        }
+    }
+    required
+}
+
+/// The text of the `{...}` group starting at `i`, e.g. the name of an environment
+fn group_text(tokens: &[Token], i: usize) -> Option<String> {
+    let i = skip_whitespace(tokens, i);
+    if !matches!(tokens.get(i)?.kind, TokenKind::Char('{')) {
+        return None;
+    }
+
+    let mut text = String::new();
+    for token in &tokens[i + 1..] {
+        match &token.kind {
+            TokenKind::Char('}') => return Some(text),
+            TokenKind::Char(c) => text.push(*c),
+            _ => return None,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(math: &str, macros: &MathMacros) -> Vec<String> {
+        check_math(math, macros).into_iter().map(|p| p.message).collect()
+    }
+
+    #[test]
+    fn test_balanced_delimiters() {
+        let macros = MathMacros::default();
+        assert!(messages(r"\left( \frac{1}{2} \right) \{ x \}", &macros).is_empty());
+        assert_eq!(messages(r"\frac{1}{2}}", &macros), ["Unmatched `}`"]);
+        assert_eq!(messages(r"\left( x", &macros), ["`\\left` without a matching `\\right`"]);
+        assert_eq!(
+            messages(r"\begin{pmatrix} 1 \end{bmatrix}", &macros),
+            ["`\\end{bmatrix}` closes `\\begin{pmatrix}`"]
+        );
+    }
+
+    #[test]
+    fn test_unknown_commands_and_arguments() {
+        let mut macros = MathMacros::default();
+        macros.args.insert("abs".to_string(), (false, 1));
+        macros.args.insert("norm".to_string(), (true, 2));
+
+        let problems = check_math(r"\alpah + \abs{x} + \norm[1]{x} + \frac{1}", &macros);
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0].message, "Unknown command `\\alpah`");
+        assert_eq!(problems[0].hint.as_deref(), Some("Did you mean `\\alpha`?"));
+        assert_eq!(problems[1].message, "`\\frac` takes 2 argument(s) but 1 was given");
+
+        assert_eq!(messages(r"\norm", &macros), ["`\\norm` takes 1 argument(s) but 0 were given"]);
+    }
+
+    #[test]
+    fn test_check_markdown_lines() {
+        let source =
+            "# Notes\n\nSome $x^2$ and\n\n$$\n\\gdef\\f{f}\n\\sum_{i}^{n\n$$\n\nThen $\\f(x)$.\n";
+        let diagnostics = check_markdown(source, &mut MathMacros::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "Unclosed `{`");
+        assert_eq!(diagnostics[0].source_line, Some(7));
+    }
+}
@@ -0,0 +1,643 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use pulldown_cmark::{Event, Tag, TagEnd};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::bibliography::{Bibliography, Entry, Name};
+use crate::config::TemplateConfig;
+use crate::crossref;
+use crate::utils::{escape_html, warn};
+
+/// How citations and the references section are formatted in the HTML output
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CitationStyle {
+    /// "(Knuth 1984)", as pandoc's default Chicago author-date style
+    #[default]
+    AuthorDate,
+    /// "[1]", with references in the order they are first cited
+    Numeric,
+}
+
+/// A cited entry, e.g. `see @knuth84, p. 33`
+#[derive(Debug, Clone, PartialEq)]
+struct Item {
+    key: String,
+    prefix: String,
+    locator: String,
+    /// `-@key` leaves out the authors
+    suppress_author: bool,
+}
+
+/// A `[@key; @other]` group, or an `@key` in the text
+#[derive(Debug, Clone, PartialEq)]
+struct Citation {
+    range: Range<usize>,
+    items: Vec<Item>,
+    /// `@key` in the text, which names the authors outside of the brackets
+    narrative: bool,
+}
+
+/// Finds the pandoc style citations of a piece of text.
+///
+/// Cross-references like `@eq:energy` are not citations.
+fn find_citations(text: &str) -> Vec<Citation> {
+    let mut citations = Vec::new();
+    let mut i = 0;
+
+    while let Some(c) = text[i..].chars().next() {
+        if c == '[' {
+            if let Some(close) = text[i..].find(']') {
+                if let Some(items) = parse_group(&text[i + 1..i + close]) {
+                    citations.push(Citation { range: i..i + close + 1, items, narrative: false });
+                    i += close + 1;
+                    continue;
+                }
+            }
+        }
+
+        let after_word = text[..i].chars().next_back().is_some_and(char::is_alphanumeric);
+        if c == '@' && !after_word {
+            let key = key_at(&text[i + 1..]);
+            if !key.is_empty() && !crossref::is_reference(key) {
+                let item = Item {
+                    key: key.to_string(),
+                    prefix: String::new(),
+                    locator: String::new(),
+                    suppress_author: false,
+                };
+                let end = i + 1 + key.len();
+                citations.push(Citation { range: i..end, items: vec![item], narrative: true });
+                i = end;
+                continue;
+            }
+        }
+
+        i += c.len_utf8();
+    }
+
+    citations
+}
+
+/// Parses the inside of `[see @a, p. 3; -@b]`, if every part cites a key
+fn parse_group(inner: &str) -> Option<Vec<Item>> {
+    inner
+        .split(';')
+        .map(|part| {
+            let (at, _) = part.char_indices().find(|(i, c)| {
+                let before = part[..*i].chars().next_back();
+                *c == '@' && before.is_none_or(|p| p.is_whitespace() || p == '-')
+            })?;
+            let key = key_at(&part[at + 1..]);
+            if key.is_empty() || crossref::is_reference(key) {
+                return None;
+            }
+
+            let before = &part[..at];
+            let suppress_author = before.ends_with('-');
+            let locator = part[at + 1 + key.len()..].trim_start_matches(',').trim();
+            Some(Item {
+                key: key.to_string(),
+                prefix: before.trim_end_matches('-').trim().to_string(),
+                locator: locator.to_string(),
+                suppress_author,
+            })
+        })
+        .collect()
+}
+
+/// The citation key at the start of `text`. Keys may contain punctuation, but not end in it
+fn key_at(text: &str) -> &str {
+    let end = text
+        .find(|c: char| !(c.is_alphanumeric() || "_:.#$%&-+?<>~/".contains(c)))
+        .unwrap_or(text.len());
+    let key = text[..end].trim_end_matches(|c: char| !(c.is_alphanumeric() || c == '_'));
+    if key.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+        key
+    } else {
+        ""
+    }
+}
+
+/// Finds the cited keys that are not in the bibliography, with their line
+pub fn unknown_keys(source: &str, bibliography: &Bibliography) -> Vec<(usize, String)> {
+    let mut unknown = Vec::new();
+    let mut in_code = false;
+    for (event, range) in crossref::parse(source) {
+        in_code = in_code_block(&event, in_code);
+        let Event::Text(text) = event else { continue };
+        if in_code {
+            continue;
+        }
+        for citation in find_citations(&text) {
+            for item in citation.items {
+                if bibliography.get(&item.key).is_none() {
+                    let line = source[..range.start].matches('\n').count() + 1;
+                    unknown.push((line, item.key));
+                }
+            }
+        }
+    }
+    unknown
+}
+
+/// Prints a warning for each cited key that is not in the bibliography.
+///
+/// `source` is the document before its includes are expanded, and the markdown files among
+/// `included` are checked on their own, so that each key is reported at its line in its file.
+pub fn warn_unknown_keys(
+    file: &Path,
+    source: &str,
+    included: &[PathBuf],
+    bibliography: &Bibliography,
+) {
+    let included = included
+        .iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "md"))
+        .filter_map(|path| Some((path.as_path(), fs::read_to_string(path).ok()?)));
+
+    for (file, source) in [(file, source.to_string())].into_iter().chain(included) {
+        for (line, key) in unknown_keys(&source, bibliography) {
+            let message =
+                format!("{}:{}: Unknown citation key `{}`", file.to_string_lossy(), line, key);
+            println!("{}", warn(&message));
+        }
+    }
+}
+
+/// Arguments making pandoc format the citations of a document with citeproc.
+///
+/// The references section gets the same title as in the HTML output, and a CSL style set in the
+/// template's template.toml is used unless the front matter sets one.
+pub fn pandoc_args(
+    bibliography: &Bibliography,
+    template_path: &Path,
+    meta: &Map<String, Value>,
+) -> Result<Vec<OsString>> {
+    let mut args: Vec<OsString> = vec!["--citeproc".into()];
+    for file in &bibliography.files {
+        let mut arg = OsString::from("--bibliography=");
+        arg.push(file);
+        args.push(arg);
+    }
+
+    if !meta.contains_key("csl") {
+        if let Some(csl) = TemplateConfig::load(template_path)?.csl {
+            let mut arg = OsString::from("--csl=");
+            arg.push(template_path.join(csl));
+            args.push(arg);
+        }
+    }
+
+    if !meta.contains_key("reference-section-title") {
+        args.push("--metadata=reference-section-title=References".into());
+    }
+    Ok(args)
+}
+
+/// Formats the citations of the document and adds a references section at its end
+pub fn to_html<'a>(
+    events: Vec<Event<'a>>,
+    bibliography: &Bibliography,
+    style: CitationStyle,
+    meta: &Map<String, Value>,
+) -> Vec<Event<'a>> {
+    // entries are numbered in the order they are first cited
+    let mut cited: Vec<String> = Vec::new();
+    let mut in_code = false;
+    for event in &events {
+        in_code = in_code_block(event, in_code);
+        if let (Event::Text(text), false) = (event, in_code) {
+            for citation in find_citations(text) {
+                for item in citation.items {
+                    if bibliography.get(&item.key).is_some() && !cited.contains(&item.key) {
+                        cited.push(item.key);
+                    }
+                }
+            }
+        }
+    }
+    let numbers: HashMap<&str, usize> =
+        cited.iter().enumerate().map(|(i, key)| (key.as_str(), i + 1)).collect();
+
+    let mut out = Vec::with_capacity(events.len());
+    let mut in_code = false;
+    for event in events {
+        in_code = in_code_block(&event, in_code);
+        let text = match event {
+            Event::Text(text) if !in_code => text,
+            event => {
+                out.push(event);
+                continue;
+            }
+        };
+
+        let mut last = 0;
+        for citation in find_citations(&text) {
+            if citation.range.start > last {
+                out.push(Event::Text(text[last..citation.range.start].to_string().into()));
+            }
+            let html = format_citation(&citation, bibliography, style, &numbers);
+            out.push(Event::InlineHtml(html.into()));
+            last = citation.range.end;
+        }
+        if last == 0 {
+            out.push(Event::Text(text));
+        } else if last < text.len() {
+            out.push(Event::Text(text[last..].to_string().into()));
+        }
+    }
+
+    if cited.is_empty() {
+        return out;
+    }
+
+    let mut entries: Vec<&Entry> = cited.iter().filter_map(|key| bibliography.get(key)).collect();
+    if style == CitationStyle::AuthorDate {
+        entries.sort_by_key(|entry| {
+            let author = entry.authors.first().map(|name| name.family.to_lowercase());
+            (author, entry.year.clone(), entry.title.clone())
+        });
+    }
+
+    let title = meta
+        .get("reference-section-title")
+        .and_then(Value::as_str)
+        .unwrap_or("References");
+    let mut html = format!(
+        "<section id=\"refs\" class=\"references\">\n<h1>{}</h1>\n",
+        escape_html(title)
+    );
+    for entry in entries {
+        let reference = match style {
+            CitationStyle::AuthorDate => author_date_reference(entry),
+            CitationStyle::Numeric => format!(
+                "<span class=\"csl-left-margin\">[{}]</span> {}",
+                numbers[entry.key.as_str()],
+                numeric_reference(entry)
+            ),
+        };
+        html.push_str(&format!(
+            "<div id=\"ref-{}\" class=\"csl-entry\">{}</div>\n",
+            escape_html(&entry.key),
+            reference
+        ));
+    }
+    html.push_str("</section>\n");
+    out.push(Event::Html(html.into()));
+
+    out
+}
+
+/// Whether the events after `event` are in a code block, whose text is never a citation, e.g. a
+/// Python decorator like `@property`. Inline code is an event of its own, so it is skipped anyway
+fn in_code_block(event: &Event, in_code: bool) -> bool {
+    match event {
+        Event::Start(Tag::CodeBlock(_)) => true,
+        Event::End(TagEnd::CodeBlock) => false,
+        _ => in_code,
+    }
+}
+
+fn format_citation(
+    citation: &Citation,
+    bibliography: &Bibliography,
+    style: CitationStyle,
+    numbers: &HashMap<&str, usize>,
+) -> String {
+    let keys: Vec<_> = citation.items.iter().map(|item| escape_html(&item.key)).collect();
+
+    let items: Vec<String> = citation
+        .items
+        .iter()
+        .map(|item| {
+            let Some(entry) = bibliography.get(&item.key) else {
+                let key = escape_html(&item.key);
+                return format!("<strong class=\"citation-missing\">{}?</strong>", key);
+            };
+            let link = |text: String| {
+                format!("<a href=\"#ref-{}\">{}</a>", escape_html(&entry.key), text)
+            };
+            let year = escape_html(entry.year.as_deref().unwrap_or("n.d."));
+            let locator = match item.locator.as_str() {
+                "" => String::new(),
+                locator => format!(", {}", escape_html(locator)),
+            };
+            let prefix = match item.prefix.as_str() {
+                "" => String::new(),
+                prefix => format!("{} ", escape_html(prefix)),
+            };
+
+            match (style, citation.narrative) {
+                (CitationStyle::AuthorDate, true) => {
+                    format!("{} ({}{})", link(in_text_authors(entry)), year, locator)
+                }
+                (CitationStyle::AuthorDate, false) if item.suppress_author => {
+                    format!("{}{}{}", prefix, link(year), locator)
+                }
+                (CitationStyle::AuthorDate, false) => format!(
+                    "{}{}{}",
+                    prefix,
+                    link(format!("{} {}", in_text_authors(entry), year)),
+                    locator
+                ),
+                (CitationStyle::Numeric, true) => format!(
+                    "{} [{}{}]",
+                    in_text_authors(entry),
+                    link(numbers[entry.key.as_str()].to_string()),
+                    locator
+                ),
+                (CitationStyle::Numeric, false) => format!(
+                    "{}{}{}",
+                    prefix,
+                    link(numbers[entry.key.as_str()].to_string()),
+                    locator
+                ),
+            }
+        })
+        .collect();
+
+    let text = match (style, citation.narrative) {
+        (_, true) => items.join("; "),
+        (CitationStyle::AuthorDate, false) => format!("({})", items.join("; ")),
+        (CitationStyle::Numeric, false) => format!("[{}]", items.join(", ")),
+    };
+    format!("<span class=\"citation\" data-cites=\"{}\">{}</span>", keys.join(" "), text)
+}
+
+/// Turns the citations of a piece of text into Typst `#cite` calls, writing the rest with
+/// `escape`. Typst formats them and the references itself, so citations of keys missing from
+/// the bibliography are left as text, which Typst would reject.
+pub fn to_typst(text: &str, bibliography: &Bibliography, escape: impl Fn(&str) -> String) -> String {
+    let mut out = String::new();
+    let mut last = 0;
+    for citation in find_citations(text) {
+        let known = citation.items.iter().all(|item| bibliography.get(&item.key).is_some());
+        if !known {
+            continue;
+        }
+        out.push_str(&escape(&text[last..citation.range.start]));
+
+        // Typst groups citations next to each other
+        let items: Vec<String> = citation
+            .items
+            .iter()
+            .map(|item| {
+                let mut args = format!("label(\"{}\")", item.key.replace(['\\', '"'], ""));
+                if citation.narrative {
+                    args.push_str(", form: \"prose\"");
+                } else if item.suppress_author {
+                    args.push_str(", form: \"year\"");
+                }
+                if !item.locator.is_empty() {
+                    args.push_str(&format!(", supplement: [{}]", escape(&item.locator)));
+                }
+                match item.prefix.as_str() {
+                    "" => format!("#cite({})", args),
+                    prefix => format!("{} #cite({})", escape(prefix), args),
+                }
+            })
+            .collect();
+        out.push_str(&items.join(" "));
+        last = citation.range.end;
+    }
+    out.push_str(&escape(&text[last..]));
+    out
+}
+
+/// The authors as named in the text, e.g. "Knuth", "Knuth and Lamport" or "Knuth et al."
+fn in_text_authors(entry: &Entry) -> String {
+    let families: Vec<_> = entry.authors.iter().map(|name| escape_html(&name.family)).collect();
+    match families.as_slice() {
+        [] => escape_html(entry.title.as_deref().unwrap_or(&entry.key)),
+        [one] => one.clone(),
+        [first, second] => format!("{} and {}", first, second),
+        [first, second, third] => format!("{}, {}, and {}", first, second, third),
+        [first, ..] => format!("{} et al.", first),
+    }
+}
+
+/// Joins names as "A", "A and B" or "A, B, and C"
+fn join_names(names: Vec<String>, and_after_comma: bool) -> String {
+    match names.as_slice() {
+        [] => String::new(),
+        [one] => one.clone(),
+        [first, second] if !and_after_comma => format!("{} and {}", first, second),
+        [rest @ .., last] => format!("{}, and {}", rest.join(", "), last),
+    }
+}
+
+/// Appends a full stop, unless the text ends with one already
+fn sentence(text: &str) -> String {
+    if text.ends_with(['.', '?', '!']) {
+        text.to_string()
+    } else {
+        format!("{}.", text)
+    }
+}
+
+/// A DOI or URL link to the entry
+fn entry_link(entry: &Entry) -> Option<String> {
+    let url = match (&entry.doi, &entry.url) {
+        (Some(doi), _) => format!("https://doi.org/{}", doi),
+        (None, Some(url)) => url.clone(),
+        (None, None) => return None,
+    };
+    let url = escape_html(&url);
+    Some(format!("<a href=\"{}\">{}</a>", url, url))
+}
+
+/// A reference in the Chicago author-date style, e.g.
+/// `Knuth, Donald E. 1984. “Literate Programming.” The Computer Journal 27 (2): 97–111.`
+fn author_date_reference(entry: &Entry) -> String {
+    let names: Vec<String> = entry
+        .authors
+        .iter()
+        .enumerate()
+        .map(|(i, name)| match (&name.given, i) {
+            (Some(given), 0) => format!("{}, {}", name.family, given),
+            (Some(given), _) => format!("{} {}", given, name.family),
+            (None, _) => name.family.clone(),
+        })
+        .collect();
+
+    let mut parts = Vec::new();
+    if !names.is_empty() {
+        parts.push(sentence(&escape_html(&join_names(names, true))));
+    }
+    parts.push(sentence(&escape_html(entry.year.as_deref().unwrap_or("n.d."))));
+
+    let title = escape_html(entry.title.as_deref().unwrap_or(&entry.key));
+    let container = entry.container.as_deref().map(escape_html);
+    let publisher = entry.publisher.as_deref().map(|p| sentence(&escape_html(p)));
+    match entry.kind.as_str() {
+        "book" => {
+            parts.push(format!("<em>{}</em>.", title));
+            parts.extend(publisher);
+        }
+        "article" => {
+            parts.push(format!("“{}.”", title));
+            let mut journal = container.map(|c| format!("<em>{}</em>", c)).unwrap_or_default();
+            if let Some(volume) = &entry.volume {
+                journal.push_str(&format!(" {}", escape_html(volume)));
+            }
+            if let Some(issue) = &entry.issue {
+                journal.push_str(&format!(" ({})", escape_html(issue)));
+            }
+            if let Some(pages) = &entry.pages {
+                journal.push_str(&format!(": {}", escape_html(pages)));
+            }
+            if !journal.is_empty() {
+                parts.push(sentence(journal.trim()));
+            }
+        }
+        _ => {
+            parts.push(format!("“{}.”", title));
+            if let Some(container) = container {
+                let pages = entry.pages.as_deref().map(|p| format!(", {}", escape_html(p)));
+                parts.push(format!("In <em>{}</em>{}.", container, pages.unwrap_or_default()));
+            }
+            parts.extend(publisher);
+        }
+    }
+
+    parts.extend(entry_link(entry).map(|link| format!("{}.", link)));
+    parts.join(" ")
+}
+
+/// A reference in the IEEE style, e.g.
+/// `D. E. Knuth, “Literate programming,” The Computer Journal, vol. 27, pp. 97–111, 1984.`
+fn numeric_reference(entry: &Entry) -> String {
+    let names: Vec<String> = entry.authors.iter().map(initials).collect();
+    let names = escape_html(&join_names(names, false));
+    let title = escape_html(entry.title.as_deref().unwrap_or(&entry.key));
+    let year = entry.year.as_deref().map(escape_html);
+
+    let mut details = Vec::new();
+    let head = match entry.kind.as_str() {
+        "book" => {
+            details.extend(entry.publisher.as_deref().map(escape_html));
+            format!("<em>{}</em>.", title)
+        }
+        kind => {
+            if let Some(container) = &entry.container {
+                let container = format!("<em>{}</em>", escape_html(container));
+                let is_part = matches!(kind, "inproceedings" | "incollection");
+                details.push(if is_part { format!("in {}", container) } else { container });
+            }
+            details.extend(entry.volume.as_deref().map(|v| format!("vol. {}", escape_html(v))));
+            details.extend(entry.issue.as_deref().map(|n| format!("no. {}", escape_html(n))));
+            details.extend(entry.pages.as_deref().map(|p| format!("pp. {}", escape_html(p))));
+            format!("“{},”", title)
+        }
+    };
+    details.extend(year);
+
+    let mut reference = match names.as_str() {
+        "" => head,
+        names => format!("{}, {}", names, head),
+    };
+    if !details.is_empty() {
+        reference.push_str(&format!(" {}.", details.join(", ")));
+    } else if reference.ends_with(",”") {
+        reference = format!("{}”", reference.trim_end_matches(",”"));
+    }
+    if let Some(link) = entry_link(entry) {
+        reference.push_str(&format!(" [Online]. Available: {}", link));
+    }
+    reference
+}
+
+/// A name as "D. E. Knuth"
+fn initials(name: &Name) -> String {
+    let Some(given) = &name.given else { return name.family.clone() };
+    let initials: Vec<String> = given
+        .split_whitespace()
+        .filter_map(|part| part.chars().next())
+        .map(|initial| format!("{}.", initial))
+        .collect();
+    format!("{} {}", initials.join(" "), name.family)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bibliography::parse_bibtex;
+
+    fn bibliography() -> Bibliography {
+        let entries = parse_bibtex(
+            "@article{knuth84, author = {Knuth, Donald E.}, title = {Literate Programming}, \
+             journal = {The Computer Journal}, volume = 27, number = 2, pages = {97--111}, \
+             year = 1984}\n\
+             @book{lamport94, author = {Leslie Lamport}, title = {LaTeX}, \
+             publisher = {Addison-Wesley}, year = 1994}\n",
+        )
+        .unwrap();
+        Bibliography { files: Vec::new(), entries }
+    }
+
+    #[test]
+    fn test_find_citations() {
+        let citations =
+            find_citations("As @knuth84 says [see @knuth84, p. 33; -@lamport94]. See @eq:one, a@b.c");
+        assert_eq!(citations.len(), 2);
+        assert!(citations[0].narrative);
+        assert_eq!(citations[1].items[0].prefix, "see");
+        assert_eq!(citations[1].items[0].locator, "p. 33");
+        assert!(citations[1].items[1].suppress_author);
+
+        // brackets without a citation are left alone
+        assert!(find_citations("[a link] and [x @ y]").is_empty());
+    }
+
+    #[test]
+    fn test_to_html() {
+        let source = "As @knuth84 shows [see @lamport94, p. 3; @missing].\n";
+        let events = crossref::parse(source).into_iter().map(|(event, _)| event).collect();
+        let bibliography = bibliography();
+
+        let mut html = String::new();
+        let events = to_html(events, &bibliography, CitationStyle::AuthorDate, &Map::new());
+        pulldown_cmark::html::push_html(&mut html, events.into_iter());
+        assert!(html.contains("<a href=\"#ref-knuth84\">Knuth</a> (1984)"));
+        assert!(html.contains("(see <a href=\"#ref-lamport94\">Lamport 1994</a>, p. 3; "));
+        assert!(html.contains("<strong class=\"citation-missing\">missing?</strong>"));
+        assert!(html.contains(
+            "Knuth, Donald E. 1984. “Literate Programming.” <em>The Computer Journal</em> 27 (2): \
+             97–111."
+        ));
+
+        let events = crossref::parse(source).into_iter().map(|(event, _)| event).collect();
+        let mut html = String::new();
+        let events = to_html(events, &bibliography, CitationStyle::Numeric, &Map::new());
+        pulldown_cmark::html::push_html(&mut html, events.into_iter());
+        assert!(html.contains("Knuth [<a href=\"#ref-knuth84\">1</a>]"));
+        assert!(html.contains(
+            "<span class=\"csl-left-margin\">[2]</span> L. Lamport, <em>LaTeX</em>. Addison-Wesley, 1994."
+        ));
+
+        assert_eq!(unknown_keys(source, &bibliography), [(1, "missing".to_string())]);
+    }
+
+    #[test]
+    fn test_code_is_not_cited() {
+        let source = "As @knuth84 shows:\n\n```python\n@property\ndef knuth84(self):\n    \
+                      return \"@knuth84\"\n```\n\nDecorators like `@staticmethod` too.\n";
+        let bibliography = bibliography();
+        assert!(unknown_keys(source, &bibliography).is_empty());
+
+        let events = crossref::parse(source).into_iter().map(|(event, _)| event).collect();
+        let mut html = String::new();
+        let events = to_html(events, &bibliography, CitationStyle::Numeric, &Map::new());
+        pulldown_cmark::html::push_html(&mut html, events.into_iter());
+        assert!(html.contains("Knuth [<a href=\"#ref-knuth84\">1</a>]"));
+        assert!(html.contains("@property\ndef knuth84(self):\n    return \"@knuth84\"\n</code>"));
+        assert!(html.contains("<code>@staticmethod</code>"));
+        assert!(!html.contains("citation-missing"));
+    }
+}
@@ -1,29 +1,57 @@
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use include_dir::include_dir;
 use serde_json::Value;
 use std::fs;
 use std::fs::File;
 use std::io::Write;
 use colored::*;
+use inquire::Confirm;
 use tokio::io::AsyncWriteExt;
 use anyhow::{Context, Result, anyhow};
 
+use crate::bibliography::Bibliography;
+use crate::callouts;
+use crate::citations;
+use crate::config::{load_vars, Config, TemplateConfig};
+use crate::crossref::{CrossRefs, Numbering, SECTION_PREAMBLE};
+use crate::diagrams;
+use crate::exec;
+use crate::theorems::Theorems;
+use crate::typst;
+use crate::front_matter::{document_title, parse_front_matter, split_front_matter};
+use crate::includes;
+use crate::slides;
+use crate::page_template::render_header;
 use crate::utils::*;
-use crate::render::render_doc;
+use crate::js_object::insertion_point;
+use crate::macros::{
+    is_generated, katex_entry, macros_preamble, macros_to_tex, parse_katex_macros, parse_tex_macros,
+    read_katex_macros, Definition, Macro,
+};
+use crate::latex_log::{locate_in_markdown, parse_latex_log, LatexError};
+use crate::render::{render_doc, RenderOptions};
+use crate::templates::{builtin_template, create_from, BUILTIN_TEMPLATES};
 
 
 // Async render function that converts to a standalone HTML document
-pub async fn to_html(mut in_file: PathBuf, out_file: Option<PathBuf>, template: String, parent: bool) -> Result<()> {
+pub async fn to_html(
+    in_file: PathBuf,
+    out_file: Option<PathBuf>,
+    template: Option<String>,
+    parent: bool,
+    exec: Option<bool>,
+    slides: bool
+) -> Result<()> {
+
+    let config = Config::load(&in_file)?;
+    let template = config.resolve_template(template)?;
  
-    let html = render_doc(&in_file, false, template)
-        .await
-        .expect("Failed to render document.");
+    let options = RenderOptions { exec, slides, ..RenderOptions::default() };
+    let html = render_doc(&in_file, &options, template).await?;
 
-    let out_file = out_file.unwrap_or_else(move || {
-        in_file.set_extension("html");
-        in_file
-    });
+    let out_file = out_file
+        .unwrap_or_else(|| default_output(&in_file, config.output.html.as_deref(), "html"));
 
     // if out file exists, ask if they want to overwrite
     if out_file.exists() {
@@ -60,24 +88,17 @@ pub fn init() -> Result<()> {
         return Err(anyhow!(err(".tatum/ directory already exists")));
     }
 
-    fs::create_dir_all(&root)
+    fs::create_dir_all(root)
         .expect("Could not create .tatum directory");
 
     println!("Created .tatum directory");
 
-    extract_templates_to(
-        &include_dir!("$CARGO_MANIFEST_DIR/templates/default"),
-        &root.join("default")
-    ).expect("Could not load template `default`");
-
-    println!("Created .tatum/default");
+    for (name, template) in &BUILTIN_TEMPLATES {
+        extract_templates_to(template, &root.join(name))
+            .unwrap_or_else(|_| panic!("Could not load template `{}`", name));
 
-    extract_templates_to(
-        &include_dir!("$CARGO_MANIFEST_DIR/templates/bluetot"),
-        &root.join("bluetot")
-    ).expect("Could not load template `bluetot`");
-
-    println!("Created .tatum/bluetot");
+        println!("Created .tatum/{}", name);
+    }
 
     const RENDER_LIST: &str = include_str!("../templates/render-list.json");
     let mut file = File::create(root.join("render-list.json"))
@@ -89,98 +110,203 @@ pub fn init() -> Result<()> {
     Ok(())
 }
 
-pub fn new(template_name: String) -> Result<()> {
-    let root = Path::new(".tatum");
-
-    if !root.exists() {
-        return Err(anyhow!(format!(
-            "{} {}\n{}",
-            "ERROR:".red().bold(),
-            format!(".tatum/ directory doesn't exist."),
-            "Please run `tatum init` first".yellow()
-        )));
-    }
-    
-    let dir = root.join(&template_name);
+// Creates a new template, copied from `from` or the built-in `default` template
+pub fn new(template_name: String, from: Option<String>) -> Result<()> {
+    let config = Config::load(&std::env::current_dir()?)?;
+    let dir = config.project_dir()?.join(&template_name);
     
     if dir.exists() {
-        return Err(anyhow!(format!(
-            "{} {}",
-            "ERROR:".red().bold(),
-            format!("{} directory doesn't exist.", dir.to_str().unwrap())
+        return Err(anyhow!(err(
+            format!("{} directory already exists.", dir.to_str().unwrap()).as_str()
         )));
     }
 
-    fs::create_dir_all(&dir)
-        .expect(format!("Could not create ./tatum/{}", &template_name).as_str());
+    match from {
+        Some(from) => create_from(&config, &from, &dir)?,
+        None => {
+            fs::create_dir_all(&dir)
+                .unwrap_or_else(|_| panic!("Could not create {}", dir.to_str().unwrap()));
 
-    extract_templates_to(
-        &include_dir!("$CARGO_MANIFEST_DIR/templates/default"),
-        &dir
-    ).expect("Could not copy template `default`");
+            extract_templates_to(builtin_template("default").unwrap(), &dir)
+                .expect("Could not copy template `default`");
+        }
+    }
 
-    println!("Created .tatum/{}", &template_name);
+    println!("Created {}", dir.to_str().unwrap());
     Ok(())
 }
 
-pub fn compile_macros(template_path: String) -> Result<()> {
+pub fn compile_macros(template: Option<String>, dry_run: bool) -> Result<()> {
+    let config = Config::load(&std::env::current_dir()?)?;
+    let template_path = config.resolve_template(template)?;
+
     // attempt to read file
-    let path = format!("{}/katex-macros.js", &template_path);
-    let content = fs::read_to_string(path)
+    let js_path = Path::new(&template_path).join("katex-macros.js");
+    let katex_macros = fs::read_to_string(&js_path)
         .with_context(|| err("Could not read katex macros"))?;
+    let macros = parse_katex_macros(&katex_macros)?;
 
-    // strip off into a json
-    let json_str = content
-        .replace("window.katexMacros = ", "")
-        .replace(";", "")
-        .trim()
-        .to_string();
+    for unknown in &macros.unknown {
+        println!("Macro has unknown format: {}", unknown);
+    }
+    for duplicate in &macros.duplicates {
+        println!("{}", warn(duplicate));
+    }
 
-    // read string to json
-    let macros: Value = serde_json::from_str(&json_str).unwrap();
-
-    // open macros file to write to
-    let macros_path = format!("{}/macros.tex", &template_path);
-    let mut file = File::create(macros_path)
-        .expect("Could not create macros file");
-
-    // loop through json
-    for (k, v) in macros.as_object().unwrap() {
-
-        // handle cases of string and array
-        match v {
-            // string
-            Value::String(s) => {
-                let command = format!("\\newcommand{{{}}}{{{}}}\n", k, s);
-                file.write_all(command.as_bytes())
-                    .expect(format!("Could not write macro {} -> {} (no args)", k, s).as_str());
-                println!("Macro created: {} -> {} (no args)", k, s);
+    for m in &macros.macros {
+        let note = match (m.definition(), m.command.is_some()) {
+            (Definition::NewCommand, _) => String::new(),
+            (_, true) => " (command set in katex-macros.js)".to_string(),
+            (Definition::RenewCommand, _) => {
+                format!(" ({} is already defined, using \\renewcommand)", m.name)
             }
-            // array
-            Value::Array(arr) if arr.len() == 2 => {
-                if let (Some(body), Some(args)) = (arr[0].as_str(), arr[1].as_u64()) {
-                    let command = format!("\\newcommand{{{}}}[{}]{{{}}}\n", k, args, body);
-                    file.write_all(command.as_bytes())
-                        .expect(format!("Could not write macro {} -> {} ({} args)", k, body, args).as_str());
-                    println!("Macro created: {} -> {} ({} args)", k, body, args);
-                }
+            (Definition::Replace, _) => {
+                format!(" ({} may be defined by a package, replacing it)", m.name)
             }
-            _ => {
-                println!("Macro: {} has unknown format: {}", k, v);
+            (Definition::MathOperator { .. }, _) => " (using \\DeclareMathOperator)".to_string(),
+            (Definition::ProvideCommand, _) => String::new(),
+        };
+        println!("Macro created: {} -> {} ({} args){}", m.name, m.tex_body(), m.args, note.yellow());
+    }
+
+    // show what changes instead of silently overwriting a macros file
+    let tex = macros_to_tex(&macros.macros);
+    let macros_path = Path::new(&template_path).join("macros.tex");
+    if let Ok(old) = fs::read_to_string(&macros_path) {
+        if old == tex {
+            println!("{} is up to date", macros_path.to_string_lossy());
+            return Ok(());
+        }
+        println!("Changes to {}:", macros_path.to_string_lossy());
+        print_diff(&old, &tex);
+
+        if dry_run {
+            println!("{}", "Dry run, macros.tex was not written".yellow());
+            return Ok(());
+        }
+
+        // exports use a hand-written macros.tex instead of katex-macros.js, so don't lose it
+        if !is_generated(&old, &katex_macros) {
+            let ans = Confirm::new("macros.tex was written by hand. Replace it?")
+                .with_default(false)
+                .prompt();
+            if !matches!(ans, Ok(true)) {
+                return Err(anyhow!("{}", "Exiting..".red()));
             }
         }
     }
 
+    fs::write(&macros_path, tex)
+        .with_context(|| err(format!("Could not write {:?}", macros_path).as_str()))?;
+
     println!("Done!");
     Ok(())
 }
 
+/// Imports the macro definitions of a LaTeX file into a template's katex-macros.js
+pub fn import_macros(tex_file: PathBuf, template: Option<String>, dry_run: bool) -> Result<()> {
+    let config = Config::load(&std::env::current_dir()?)?;
+    let template_path = config.resolve_template(template)?;
+
+    let tex = fs::read_to_string(&tex_file)
+        .with_context(|| err(format!("Could not read {:?}", tex_file).as_str()))?;
+    let mut imported = parse_tex_macros(&tex);
+
+    for unsupported in &imported.unsupported {
+        println!("{} {}", "Not imported:".yellow(), unsupported);
+    }
+
+    let js_path = Path::new(&template_path).join("katex-macros.js");
+    let source = if js_path.exists() {
+        fs::read_to_string(&js_path)
+            .with_context(|| err(format!("Could not read {:?}", js_path).as_str()))?
+    } else {
+        "window.katexMacros = {};\n".to_string()
+    };
+    let existing = parse_katex_macros(&source)?;
+    let point = insertion_point(&source)
+        .map_err(|e| anyhow!(err(format!("Could not parse katex-macros.js:{}", e).as_str())))?;
+
+    // a later definition of the same command wins, like in LaTeX
+    let mut seen = Vec::new();
+    imported.macros.reverse();
+    imported.macros.retain(|m| {
+        let first = !seen.contains(&m.name);
+        seen.push(m.name.clone());
+        first
+    });
+    imported.macros.reverse();
+
+    let mut entries = Vec::new();
+    for m in &imported.macros {
+        let same = |e: &Macro| e.body == m.body && e.args == m.args && e.default == m.default;
+        match existing.macros.iter().find(|e| e.name == m.name) {
+            Some(e) if same(e) => {}
+            Some(_) => println!(
+                "{} {} is defined differently in katex-macros.js",
+                "Skipped:".yellow(),
+                m.name
+            ),
+            None if existing.unknown.iter().any(|u| u.starts_with(&format!("{}:", m.name))) => {
+                println!("{} {} is already in katex-macros.js", "Skipped:".yellow(), m.name)
+            }
+            None => {
+                println!("Macro imported: {} -> {} ({} args)", m.name, m.body, m.args);
+                entries.push(katex_entry(m));
+            }
+        }
+    }
+
+    if entries.is_empty() {
+        println!("No new macros to import");
+        return Ok(());
+    }
+
+    // insert the new entries after the last one, keeping the comments and layout of the file
+    let (before, after) = source.split_at(point.offset);
+    let mut updated = before.to_string();
+    if point.needs_comma {
+        updated.push(',');
+    }
+    let indent = source
+        .lines()
+        .find(|line| line.trim_start().starts_with(['"', '\'']))
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .unwrap_or("    ");
+    for entry in &entries {
+        updated.push_str(&format!("\n{}{},", indent, entry));
+    }
+    if point.needs_comma {
+        updated.pop();
+    }
+    if after.starts_with('}') {
+        updated.push('\n');
+    }
+    updated.push_str(after);
+
+    println!("Changes to {}:", js_path.to_string_lossy());
+    print_diff(&source, &updated);
+
+    if dry_run {
+        println!("{}", "Dry run, katex-macros.js was not written".yellow());
+        return Ok(());
+    }
+
+    fs::write(&js_path, updated)
+        .with_context(|| err(format!("Could not write {:?}", js_path).as_str()))?;
+
+    println!("Imported {} macro(s)", entries.len());
+    Ok(())
+}
+
 // Convert to latex
 pub fn to_latex(
     in_file_path: String, 
-    template_path: String, 
+    template: Option<String>, 
     out_file_path: Option<String>,
-    parent: bool
+    parent: bool,
+    exec: Option<bool>,
+    slides: bool
 ) -> Result<()> {
 
     let md_path = Path::new(in_file_path.as_str());
@@ -190,17 +316,14 @@ pub fn to_latex(
         return Err(anyhow!(err_no_md_file(md_path)));
     }
 
+    let mut config = Config::load(md_path)?;
+    let template_path = config.resolve_template(template)?;
+    config.set_exec(exec);
+
     // Determine output .tex path
-    let output_dir = md_path.parent().unwrap_or_else(|| Path::new("."));
     let tex_output_path = match out_file_path {
-        None => {
-            output_dir.join(
-                md_path.file_stem().expect("No file stem found")
-            ).with_extension("tex")
-        }
-        Some(s) => {
-            PathBuf::from(&s)
-        }
+        None => default_output(md_path, config.output.latex.as_deref(), "tex"),
+        Some(s) => PathBuf::from(&s),
     };
 
     // if output path exists, ask user if they want to overwrite
@@ -213,46 +336,64 @@ pub fn to_latex(
         create_parent_directories(&tex_output_path);
     }
 
-    // Determine macros.tex path
-    let macros_path = format!("{}/macros.tex", template_path);
-    if !Path::new(&macros_path).exists() {
-        return Err(anyhow!(err_no_macro_tex(template_path)));
-    }
+    build_latex(md_path, &template_path, &tex_output_path, &config, slides)?;
 
-    // Determine header.tex path
-    let header_path = format!("{}/header.tex", template_path);
-    if !Path::new(&header_path).exists() {
-        return Err(anyhow!(err_no_header_tex(template_path)));
-    }
+    println!("Conversion to latex completed. TEX file: {:?}", tex_output_path);
+
+    Ok(())
+}
+
+/// Converts `md_path` to a standalone `.tex` file at `tex_output_path` with pandoc.
+///
+/// Like `build_pdf`, this does not prompt before overwriting or create parent directories.
+pub fn build_latex(
+    md_path: &Path,
+    template_path: &str,
+    tex_output_path: &Path,
+    config: &Config,
+    slides: bool
+) -> Result<()> {
+    // Rendered headers are written to a build directory
+    let build_dir = tempfile::Builder::new()
+        .prefix("tatum-")
+        .tempdir()
+        .with_context(|| err("Could not create build directory"))?;
+    let mut headers = latex_headers(md_path, template_path, config, build_dir.path(), slides)?;
+    let (markdown_path, document_header, pandoc_args) =
+        latex_markdown(md_path, template_path, config, build_dir.path(), slides)?;
+    headers.extend(document_header);
 
     // Run pandoc conversion command
-    let status = Command::new("pandoc")
-        .arg(md_path)
+    let output = Command::new("pandoc")
+        .arg(&markdown_path)
         .arg("-s") // standalone flag
         .arg("-o") // output flag
-        .arg(&tex_output_path)
-        .arg("-H") // header flag
-        .arg(macros_path)
-        .arg("-H") // second header flag
-        .arg(header_path)
-        .status()?; // Waits for command to finish
+        .arg(tex_output_path)
+        .args(headers.iter().flat_map(|header| [Path::new("-H"), header])) // header flags
+        .args(&pandoc_args)
+        .output() // Waits for command to finish
+        .with_context(|| err("Could not run pandoc. Is it installed?"))?;
     
     // If the pandoc command failed
-    if !status.success() {
-        return Err(anyhow!(err_pandoc_fails(&status)));
+    if !output.status.success() {
+        return Err(PandocError {
+            status: output.status,
+            log: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }.into());
     }
 
-    println!("Conversion to latex completed. TEX file: {:?}", tex_output_path);
-
     Ok(())
 }
 
 // Convert to pdf
 pub fn to_pdf(
     in_file_path: String, 
-    template_path: String,
+    template: Option<String>,
     out_file_path: Option<String>,
-    parent: bool
+    parent: bool,
+    keep_intermediate: bool,
+    exec: Option<bool>,
+    slides: bool
 ) -> Result<()> {
 
     let md_path = Path::new(in_file_path.as_str());
@@ -262,17 +403,14 @@ pub fn to_pdf(
         return Err(anyhow!(err_no_md_file(md_path)));
     }
 
+    let mut config = Config::load(md_path)?;
+    let template_path = config.resolve_template(template)?;
+    config.set_exec(exec);
+
     // Determine output .pdf path
-    let output_dir = md_path.parent().unwrap_or_else(|| Path::new("."));
     let pdf_output_path = match out_file_path {
-        None => {
-            output_dir.join(
-                md_path.file_stem().expect("No file stem found")
-            ).with_extension("pdf")
-        }
-        Some(s) => {
-            PathBuf::from(&s)
-        }
+        None => default_output(md_path, config.output.pdf.as_deref(), "pdf"),
+        Some(s) => PathBuf::from(&s),
     };
 
     // if output file exists, ask user if they want to overwrite
@@ -285,81 +423,781 @@ pub fn to_pdf(
         create_parent_directories(&pdf_output_path);
     }
 
-    // Determine macros.tex path
-    let macros_path_str = format!("{}/macros.tex", &template_path);
-    let macros_path = Path::new(&macros_path_str);
+    build_pdf(md_path, &template_path, &pdf_output_path, &config, keep_intermediate, slides)?;
 
-    // Ensure the macros.tex file exists
-    if !macros_path.exists() {
-        return Err(anyhow!(err_no_macro_tex(template_path)));
-    }
+    println!("Conversion to pdf completed. PDF file: {:?}", pdf_output_path);
 
-    // Determine header.tex path
-    let header_path_str = format!("{}/header.tex", &template_path);
-    let header_path = Path::new(&header_path_str);
+    Ok(())
+}
 
-    // Ensure the header.tex file exists
-    if !header_path.exists() {
-        return Err(anyhow!(err_no_header_tex(template_path)));
-    }
+/// Runs the pdf pipeline for `md_path`, writing the result to `pdf_output_path`.
+///
+/// Pandoc first converts the markdown to a standalone `.tex` file in a build directory, which is
+/// then compiled with the configured LaTeX engine. Splitting the two steps means the LaTeX log can be read back and
+/// its errors mapped to the markdown source, returned as a `LatexError`.
+///
+/// Unlike `to_pdf`, this does not prompt before overwriting or create parent directories, so it
+/// can also be used to build previews. With `keep_intermediate`, the `.tex` and `.log` files are
+/// copied next to the output pdf. With `slides`, the document becomes Beamer slides.
+pub fn build_pdf(
+    md_path: &Path,
+    template_path: &str,
+    pdf_output_path: &Path,
+    config: &Config,
+    keep_intermediate: bool,
+    slides: bool
+) -> Result<()> {
 
-    // Use absolute path as we are changing directories
-    let abs_macros_path = fs::canonicalize(&macros_path)?;
-    let abs_header_path = fs::canonicalize(&header_path)?;
+    // Pandoc and LaTeX are run from the directory of the markdown file so relative images resolve
+    let work_dir = match md_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
 
     // Handle the pdf output path separetly as the path may not exist yet
     let abs_pdf_output_path = if pdf_output_path.is_absolute() {
-        pdf_output_path.clone()
+        pdf_output_path.to_path_buf()
     } else {
-        std::env::current_dir()?.join(&pdf_output_path)
+        std::env::current_dir()?.join(pdf_output_path)
     };
 
+    // Intermediate files are named after the output, e.g. notes.tex and notes.log
+    let build_dir = tempfile::Builder::new()
+        .prefix("tatum-")
+        .tempdir()
+        .with_context(|| err("Could not create build directory"))?;
+    let job_name = pdf_output_path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("document");
+    let tex_path = build_dir.path().join(format!("{}.tex", job_name));
+    let log_path = build_dir.path().join(format!("{}.log", job_name));
+
+    // Absolute paths, as we are changing directories
+    let mut headers = latex_headers(md_path, template_path, config, build_dir.path(), slides)?;
+    let (markdown_path, document_header, pandoc_args) =
+        latex_markdown(md_path, template_path, config, build_dir.path(), slides)?;
+    headers.extend(document_header);
+
     // Run pandoc conversion command
-    let status = Command::new("pandoc")
-        .arg(md_path.file_name().unwrap()) // get filename
+    let output = Command::new("pandoc")
+        .arg(&markdown_path)
+        .arg("-s") // standalone flag
         .arg("-o") // output flag
-        .arg(abs_pdf_output_path) // get filename
-        .arg("--pdf-engine=pdflatex") // specify pdf engine
-        .arg("-H") // header flag
-        .arg(abs_macros_path) // macros path (absolute)
-        .arg("-H") // second header flag
-        .arg(abs_header_path) // header path (absolute)
-        .current_dir(output_dir)
-        .status()?;
+        .arg(&tex_path) // intermediate tex file
+        .args(headers.iter().flat_map(|header| [Path::new("-H"), header])) // header flags
+        .args(&pandoc_args)
+        .current_dir(work_dir)
+        .output()
+        .with_context(|| err("Could not run pandoc. Is it installed?"))?;
+
+    // If the pandoc command failed, keep its log for the caller
+    if !output.status.success() {
+        return Err(PandocError {
+            status: output.status,
+            log: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }.into());
+    }
+
+    let result = run_latex(config.pdf_engine(), work_dir, &tex_path, build_dir.path(), job_name);
+
+    if keep_intermediate {
+        for (from, ext) in [(&tex_path, "tex"), (&log_path, "log")] {
+            if from.exists() {
+                let to = abs_pdf_output_path.with_extension(ext);
+                fs::copy(from, &to)
+                    .with_context(|| err(format!("Could not keep {:?}", to).as_str()))?;
+                println!("{}", format!("Kept intermediate file {:?}", to).yellow());
+            }
+        }
+    }
+
+    // Map LaTeX errors back to the markdown source
+    if let Err(mut e) = result {
+        if let Some(latex) = e.downcast_mut::<LatexError>() {
+            let tex = fs::read_to_string(&tex_path).unwrap_or_default();
+            let markdown = fs::read_to_string(md_path).unwrap_or_default();
+            locate_in_markdown(&mut latex.diagnostics, &tex, &markdown);
+            latex.file = md_path.to_string_lossy().into_owned();
+        }
+        return Err(e);
+    }
+
+    fs::copy(build_dir.path().join(format!("{}.pdf", job_name)), &abs_pdf_output_path)
+        .with_context(|| err(format!("Could not write {:?}", abs_pdf_output_path).as_str()))?;
+
+    Ok(())
+}
+
+/// Formats pandoc writes from the same markdown, with numbers and references as plain text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentFormat {
+    /// Microsoft Word, with math as native Word equations
+    Docx,
+    /// OpenDocument Text, with math as MathML objects
+    Odt,
+    /// EPUB3 for e-readers, with math as MathML
+    Epub,
+}
+
+impl DocumentFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            DocumentFormat::Docx => "docx",
+            DocumentFormat::Odt => "odt",
+            DocumentFormat::Epub => "epub",
+        }
+    }
+
+    /// The arguments styling the output with the files of a template: its `reference.docx` or
+    /// `reference.odt` for word processors, and its `style.css` for EPUB
+    fn style_args(self, template_path: &Path) -> Result<Vec<OsString>> {
+        let (file, option) = match self {
+            DocumentFormat::Docx => ("reference.docx", "--reference-doc="),
+            DocumentFormat::Odt => ("reference.odt", "--reference-doc="),
+            DocumentFormat::Epub => ("style.css", "--css="),
+        };
+
+        let path = template_path.join(file);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let mut arg = OsString::from(option);
+        arg.push(fs::canonicalize(path)?);
+        Ok(vec![arg])
+    }
+}
+
+// Convert to docx, odt or epub
+pub fn to_document(
+    format: DocumentFormat,
+    in_file_path: String,
+    template: Option<String>,
+    out_file_path: Option<String>,
+    parent: bool,
+    exec: Option<bool>
+) -> Result<()> {
+
+    let md_path = Path::new(in_file_path.as_str());
+
+    // Ensure the markdown file exists
+    if !md_path.exists() {
+        return Err(anyhow!(err_no_md_file(md_path)));
+    }
+
+    let mut config = Config::load(md_path)?;
+    let template_path = config.resolve_template(template)?;
+    config.set_exec(exec);
+
+    // Determine output path
+    let output_dir = match format {
+        DocumentFormat::Docx => config.output.docx.as_deref(),
+        DocumentFormat::Odt => config.output.odt.as_deref(),
+        DocumentFormat::Epub => config.output.epub.as_deref(),
+    };
+    let output_path = match out_file_path {
+        None => default_output(md_path, output_dir, format.extension()),
+        Some(s) => PathBuf::from(&s),
+    };
+
+    // if output file exists, ask user if they want to overwrite
+    if output_path.exists() {
+        notify_overwrite()?;
+    }
+
+    // if -p flag is on, try creating parent directories
+    if parent {
+        create_parent_directories(&output_path);
+    }
+
+    build_document(md_path, &template_path, &output_path, &config, format)?;
+
+    println!(
+        "Conversion to {} completed. {} file: {:?}",
+        format.extension(),
+        format.extension().to_uppercase(),
+        output_path
+    );
+
+    Ok(())
+}
+
+/// Converts `md_path` to a Word, OpenDocument or EPUB file at `output_path` with pandoc, styled
+/// by the template's `reference.docx`, `reference.odt` or `style.css`.
+///
+/// EPUBs get a table of contents made from the headings, and are titled after the document when
+/// the front matter has no `title`. Images are embedded by pandoc in every format.
+///
+/// Like `build_pdf`, this does not prompt before overwriting or create parent directories.
+pub fn build_document(
+    md_path: &Path,
+    template_path: &str,
+    output_path: &Path,
+    config: &Config,
+    format: DocumentFormat
+) -> Result<()> {
+
+    // Pandoc is run from the directory of the markdown file so relative images resolve
+    let work_dir = match md_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let abs_output_path = if output_path.is_absolute() {
+        output_path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(output_path)
+    };
+
+    let build_dir = tempfile::Builder::new()
+        .prefix("tatum-")
+        .tempdir()
+        .with_context(|| err("Could not create build directory"))?;
+    let (markdown_path, mut pandoc_args) =
+        document_markdown(md_path, template_path, config, build_dir.path())?;
+    pandoc_args.extend(format.style_args(Path::new(template_path))?);
+
+    if format == DocumentFormat::Epub {
+        pandoc_args.extend(epub_args(md_path)?);
+    }
+
+    // Run pandoc conversion command
+    let output = Command::new("pandoc")
+        .arg(&markdown_path)
+        .arg("-s") // standalone flag
+        .arg("-o") // output flag
+        .arg(&abs_output_path)
+        .args(&pandoc_args)
+        .current_dir(work_dir)
+        .output()
+        .with_context(|| err("Could not run pandoc. Is it installed?"))?;
 
     // If the pandoc command failed
-    if !status.success() {
-        return Err(anyhow!(err_pandoc_fails(&status)));
+    if !output.status.success() {
+        return Err(PandocError {
+            status: output.status,
+            log: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }.into());
     }
 
-    println!("Conversion to pdf completed. PDF file: {:?}", pdf_output_path);
+    Ok(())
+}
+
+/// The arguments making an EPUB 3 with MathML and a table of contents, titled after the document
+/// when its front matter has no `title`
+fn epub_args(md_path: &Path) -> Result<Vec<OsString>> {
+    let mut args: Vec<OsString> = ["--to=epub3", "--mathml", "--toc"].map(OsString::from).into();
+    let meta = parse_front_matter(&fs::read_to_string(md_path)?)?;
+    if !meta.contains_key("title") {
+        args.push(format!("--metadata=title:{}", document_title(md_path)).into());
+    }
+    Ok(args)
+}
+
+/// Writes the markdown given to pandoc for a Word, OpenDocument or EPUB file to `build_dir`,
+/// prepared like the LaTeX one, but with numbers and references written out as text, theorem
+/// containers and callouts as styled divs and quotes and diagrams as images.
+///
+/// The template's KaTeX macros are defined at the top, so that pandoc expands them before turning
+/// the math into native equations or MathML. Also returns the arguments pandoc needs to number sections
+/// and format citations.
+fn document_markdown(
+    md_path: &Path,
+    template_path: &str,
+    config: &Config,
+    build_dir: &Path
+) -> Result<(PathBuf, Vec<OsString>)> {
+    let source = fs::read_to_string(md_path)
+        .with_context(|| err(format!("Could not read {:?}", md_path).as_str()))?;
+    let included = includes::expand(md_path, &source)?;
+    let markdown =
+        exec::run_blocks(md_path, &included.source, &config.exec, config.exec_enabled());
+    let meta = parse_front_matter(&markdown)?;
+    let numbering = Numbering::for_document(Path::new(template_path), &meta)?;
+    let theorems = Theorems::load(Path::new(template_path), numbering)?;
+
+    let referenced = CrossRefs::collect(&markdown, numbering).to_word_markdown(&markdown);
+    let with_theorems = theorems.to_word_markdown(&referenced);
+    let with_callouts = callouts::to_word_markdown(&with_theorems);
+    let converted = diagrams::to_image_markdown(md_path, &with_callouts, config.pdf_engine());
+
+    let mut args: Vec<OsString> = numbering_args(numbering).iter().map(OsString::from).collect();
+    if let Some(bibliography) = Bibliography::for_document(md_path, Path::new(template_path), &meta)? {
+        citations::warn_unknown_keys(md_path, &source, &included.files, &bibliography);
+        args.extend(citations::pandoc_args(&bibliography, Path::new(template_path), &meta)?);
+    }
+
+    // the definitions go right after the front matter, which must stay at the top
+    let body = split_front_matter(&converted).1;
+    let front_matter = &converted[..converted.len() - body.len()];
+    let mut definitions = String::new();
+    if Path::new(template_path).join("katex-macros.js").exists() {
+        for m in read_katex_macros(template_path)?.macros {
+            definitions.push_str(&format!("{}\n\n", m.to_tex()));
+        }
+    }
+
+    let path = build_dir.join(md_path.file_name().unwrap_or("document.md".as_ref()));
+    fs::write(&path, format!("{}\n{}{}", front_matter, definitions, body))
+        .with_context(|| err("Could not write the markdown for pandoc"))?;
+
+    Ok((fs::canonicalize(path)?, args))
+}
+
+// Convert to a .typ file, and with `pdf` compile it with typst
+pub fn to_typst(
+    in_file_path: String,
+    template: Option<String>,
+    out_file_path: Option<String>,
+    parent: bool,
+    exec: Option<bool>,
+    pdf: bool
+) -> Result<()> {
+
+    let md_path = Path::new(in_file_path.as_str());
+
+    // Ensure the markdown file exists
+    if !md_path.exists() {
+        return Err(anyhow!(err_no_md_file(md_path)));
+    }
+
+    let mut config = Config::load(md_path)?;
+    let template_path = config.resolve_template(template)?;
+    config.set_exec(exec);
+
+    // Determine output path
+    let typ_path = match out_file_path {
+        None => default_output(md_path, config.output.typst.as_deref(), "typ"),
+        Some(s) => PathBuf::from(&s),
+    };
+
+    // if output file exists, ask user if they want to overwrite, before writing either of them
+    let pdf_path = pdf.then(|| typ_path.with_extension("pdf"));
+    if typ_path.exists() || pdf_path.as_ref().is_some_and(|path| path.exists()) {
+        notify_overwrite()?;
+    }
+
+    // if -p flag is on, try creating parent directories
+    if parent {
+        create_parent_directories(&typ_path);
+    }
+
+    build_typst(md_path, &template_path, &typ_path, &config)?;
+    println!("Conversion to typst completed. Typst file: {:?}", typ_path);
+
+    if let Some(pdf_path) = pdf_path {
+        compile_typst(&typ_path, &pdf_path)?;
+        println!("PDF file: {:?}", pdf_path);
+    }
+
+    Ok(())
+}
+
+/// Converts `md_path` to a Typst file at `typ_path`, without pandoc.
+///
+/// The markdown is prepared as for the other exports, with theorem containers and callouts as
+/// Typst blocks and diagrams as images, and its math is translated to Typst math with the
+/// template's KaTeX macros expanded. The template's `header.typ`, if it has one, is rendered with
+/// the template variables and the front matter and placed after the document settings. Citations
+/// are left to Typst, which reads BibTeX bibliographies only.
+///
+/// Like `build_pdf`, this does not prompt before overwriting or create parent directories.
+pub fn build_typst(
+    md_path: &Path,
+    template_path: &str,
+    typ_path: &Path,
+    config: &Config
+) -> Result<()> {
+    let template_dir = Path::new(template_path);
+    let source = fs::read_to_string(md_path)
+        .with_context(|| err(format!("Could not read {:?}", md_path).as_str()))?;
+    let included = includes::expand(md_path, &source)?;
+    let markdown =
+        exec::run_blocks(md_path, &included.source, &config.exec, config.exec_enabled());
+    let meta = parse_front_matter(&markdown)?;
+    let numbering = Numbering::for_document(template_dir, &meta)?;
+    let theorems = Theorems::load(template_dir, numbering)?;
+
+    let with_theorems = theorems.to_typst_markdown(&markdown);
+    let with_callouts = callouts::to_typst_markdown(&with_theorems);
+    let converted = diagrams::to_image_markdown(md_path, &with_callouts, config.pdf_engine());
+    let body = split_front_matter(&converted).1;
+    let refs = CrossRefs::collect(body, numbering);
+
+    let macros = if template_dir.join("katex-macros.js").exists() {
+        read_katex_macros(template_path)?.macros
+    } else {
+        Vec::new()
+    };
+
+    let mut bibliography = Bibliography::for_document(md_path, template_dir, &meta)?;
+    if let Some(b) = &bibliography {
+        citations::warn_unknown_keys(md_path, &source, &included.files, b);
+        if b.files.iter().any(|file| file.extension().is_some_and(|ext| ext == "json")) {
+            println!(
+                "{}",
+                warn("Typst only reads BibTeX bibliographies, citations are left as text")
+            );
+            bibliography = None;
+        }
+    }
+
+    // paths in the .typ file are relative to it
+    let md_dir = fs::canonicalize(md_path)?.parent().map(Path::to_path_buf).unwrap_or_default();
+    let out_dir = match typ_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => fs::canonicalize(dir)
+            .with_context(|| err(format!("Could not find the directory of {:?}", typ_path).as_str()))?,
+        _ => std::env::current_dir()?,
+    };
+
+    let document =
+        typst::Document::new(body, &refs, &macros, bibliography.as_ref(), &md_dir, &out_dir)
+            .to_typst();
+
+    let header_path = template_dir.join("header.typ");
+    let header = if header_path.exists() {
+        let header = fs::read_to_string(&header_path)
+            .with_context(|| err("Could not read header.typ"))?;
+        let vars = load_vars(template_dir, config.project_root.as_deref())?;
+        Some(render_header(&header, &vars, &meta))
+    } else {
+        None
+    };
+
+    let mut out = typst::preamble(&meta, numbering, header.as_deref());
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(&document);
+
+    if let Some(bibliography) = &bibliography {
+        let template_config = TemplateConfig::load(template_dir)?;
+        let csl = match meta.get("csl").and_then(Value::as_str) {
+            Some(csl) => Some(md_dir.join(csl)),
+            None => template_config.csl.map(|csl| template_dir.join(csl)),
+        };
+        let title = meta
+            .get("reference-section-title")
+            .and_then(Value::as_str)
+            .unwrap_or("References");
+        out.push('\n');
+        out.push_str(&typst::bibliography(
+            bibliography,
+            template_config.citation_style,
+            csl.as_deref(),
+            title,
+            &out_dir,
+        ));
+    }
 
+    fs::write(typ_path, out)
+        .with_context(|| err(format!("Could not write {:?}", typ_path).as_str()))?;
     Ok(())
 }
 
-pub async fn render_all(template: String, parent: bool) -> Result<()> {
+/// Compiles a `.typ` file to a PDF with the typst CLI. Its root is the root of the file system, as
+/// images and bibliographies may be outside of the directory of the `.typ` file
+pub fn compile_typst(typ_path: &Path, pdf_path: &Path) -> Result<()> {
+    let typ_path = fs::canonicalize(typ_path)?;
+    let root = typ_path.ancestors().last().unwrap_or(Path::new("/"));
+
+    let output = Command::new("typst")
+        .arg("compile")
+        .arg("--root")
+        .arg(root)
+        .arg(&typ_path)
+        .arg(pdf_path)
+        .output()
+        .with_context(|| err("Could not run typst. Is it installed?"))?;
+
+    if !output.status.success() {
+        return Err(anyhow!(err(format!(
+            "typst could not compile {:?}:\n{}",
+            typ_path,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ).as_str())));
+    }
+
+    Ok(())
+}
+
+/// Returns the absolute paths of the files passed to pandoc with `-H`: the template's header.tex
+/// rendered with the template variables and the document's front matter, then its macro
+/// definitions.
+///
+/// The macros come after the header so that the packages it loads define their commands first,
+/// and macros replacing them with `\renewcommand` find them defined.
+///
+/// Slides use the template's beamer.tex instead, if it has one, as a header.tex written for
+/// articles rarely works with Beamer. Both are written to `build_dir`.
+fn latex_headers(
+    md_path: &Path,
+    template_path: &str,
+    config: &Config,
+    build_dir: &Path,
+    slides: bool
+) -> Result<Vec<PathBuf>> {
+
+    // generated every time, unless the template has a hand-written macros.tex
+    let macros_path = build_dir.join("macros.tex");
+    fs::write(&macros_path, macros_preamble(Path::new(template_path))?)
+        .with_context(|| err("Could not write macros.tex"))?;
+
+    // Determine header.tex path
+    let header_name = if slides { "beamer.tex" } else { "header.tex" };
+    let header_path = Path::new(template_path).join(header_name);
+    if slides && !header_path.exists() {
+        return Ok(vec![fs::canonicalize(macros_path)?]);
+    }
+    if !header_path.exists() {
+        return Err(anyhow!(err_no_header_tex(template_path.to_string())));
+    }
+
+    let header = fs::read_to_string(&header_path)
+        .with_context(|| err(format!("Could not read {}", header_name).as_str()))?;
+    let vars = load_vars(Path::new(template_path), config.project_root.as_deref())?;
+    let meta = parse_front_matter(&fs::read_to_string(md_path)?)?;
+
+    let rendered_header_path = build_dir.join("header.tex");
+    fs::write(&rendered_header_path, render_header(&header, &vars, &meta))
+        .with_context(|| err("Could not write header.tex"))?;
+
+    Ok(vec![fs::canonicalize(rendered_header_path)?, fs::canonicalize(macros_path)?])
+}
+
+/// Writes the markdown given to pandoc to `build_dir`, with includes resolved, the output of code
+/// blocks marked with `{exec}` inserted, equations, figures and tables numbered as in the HTML
+/// output, references turned into LaTeX, theorem containers into amsthm environments, callouts
+/// into tcolorbox boxes and diagrams into TikZ pictures or PDFs.
+///
+/// Also returns the preamble these need, if any, and the arguments pandoc needs to number sections
+/// and format citations like the HTML output, and to write Beamer slides with `slides`.
+fn latex_markdown(
+    md_path: &Path,
+    template_path: &str,
+    config: &Config,
+    build_dir: &Path,
+    slides: bool
+) -> Result<(PathBuf, Option<PathBuf>, Vec<OsString>)> {
+    let source = fs::read_to_string(md_path)
+        .with_context(|| err(format!("Could not read {:?}", md_path).as_str()))?;
+    let included = includes::expand(md_path, &source)?;
+    let markdown =
+        exec::run_blocks(md_path, &included.source, &config.exec, config.exec_enabled());
+    let meta = parse_front_matter(&markdown)?;
+    let numbering = Numbering::for_document(Path::new(template_path), &meta)?;
+    let theorems = Theorems::load(Path::new(template_path), numbering)?;
+
+    let referenced = CrossRefs::collect(&markdown, numbering).to_latex_markdown(&markdown);
+    let with_theorems = theorems.to_latex_markdown(&referenced);
+    let with_callouts = callouts::to_latex_markdown(&with_theorems);
+    let (converted, diagram_preamble) = diagrams::to_latex_markdown(md_path, &with_callouts);
+
+    let mut args: Vec<OsString> = numbering_args(numbering).iter().map(OsString::from).collect();
+    if slides {
+        args.extend(slides::BEAMER_ARGS.iter().map(OsString::from));
+    }
+    if let Some(bibliography) = Bibliography::for_document(md_path, Path::new(template_path), &meta)? {
+        citations::warn_unknown_keys(md_path, &source, &included.files, &bibliography);
+        args.extend(citations::pandoc_args(&bibliography, Path::new(template_path), &meta)?);
+    }
+
+    let mut preamble = String::new();
+    if numbering == Numbering::Section {
+        preamble.push_str(SECTION_PREAMBLE);
+    }
+    if with_theorems != referenced {
+        preamble.push_str(&theorems.latex_preamble());
+    }
+    if with_callouts != with_theorems {
+        preamble.push_str(&callouts::latex_preamble());
+    }
+    preamble.push_str(&diagram_preamble);
+
+    let path = build_dir.join(md_path.file_name().unwrap_or("document.md".as_ref()));
+    fs::write(&path, converted)
+        .with_context(|| err("Could not write the markdown for pandoc"))?;
+
+    let header = if preamble.is_empty() {
+        None
+    } else {
+        let header = build_dir.join("document.tex");
+        fs::write(&header, preamble).with_context(|| err("Could not write document.tex"))?;
+        Some(fs::canonicalize(header)?)
+    };
+
+    Ok((fs::canonicalize(path)?, header, args))
+}
+
+/// Sections are numbered when the numbers of equations, figures and tables include them
+fn numbering_args(numbering: Numbering) -> &'static [&'static str] {
+    match numbering {
+        Numbering::Document => &[],
+        Numbering::Section => &["--number-sections"],
+    }
+}
+
+/// Compiles `tex_path` with the LaTeX `engine`, writing all output files to `build_dir`.
+///
+/// LaTeX is rerun while it asks for it, e.g. to resolve `\pageref{LastPage}` in a header.
+fn run_latex(
+    engine: &str,
+    work_dir: &Path,
+    tex_path: &Path,
+    build_dir: &Path,
+    job_name: &str
+) -> Result<()> {
+    const MAX_RUNS: usize = 3;
+
+    let log_path = build_dir.join(format!("{}.log", job_name));
+
+    for _ in 0..MAX_RUNS {
+        let output = Command::new(engine)
+            .arg("-interaction=nonstopmode") // never wait for input on errors
+            .arg("-halt-on-error")
+            .arg(format!("-output-directory={}", build_dir.to_string_lossy()))
+            .arg(format!("-jobname={}", job_name))
+            .arg(tex_path)
+            .current_dir(work_dir)
+            .output()
+            .with_context(|| err(format!("Could not run {}. Is it installed?", engine).as_str()))?;
+
+        // The log file has the full error context, stdout is a fallback
+        let log = fs::read_to_string(&log_path)
+            .unwrap_or_else(|_| String::from_utf8_lossy(&output.stdout).into_owned());
+
+        if !output.status.success() {
+            return Err(LatexError {
+                file: String::new(),
+                diagnostics: parse_latex_log(&log),
+                log,
+            }.into());
+        }
+
+        if !log.contains("Rerun to get") && !log.contains("Rerun LaTeX") {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn render_all(
+    template: Option<String>,
+    parent: bool,
+    exec: Option<bool>
+) -> Result<()> {
+
+    let config = Config::load(&std::env::current_dir()?)?;
+    let project_dir = config.project_dir()?;
+    let project_root = project_dir.parent().unwrap_or_else(|| Path::new("."));
+    let template = config.resolve_template(template)?;
 
     // read render-list.json
-    let render_list = fs::read_to_string(".tatum/render-list.json")
+    let render_list = fs::read_to_string(project_dir.join("render-list.json"))
         .with_context(|| err("Could not read .tatum/render-list.json"))?;
 
     // read string to json
     let files: Value = serde_json::from_str(&render_list).unwrap();
 
-    // call render on each file
+    // call render on each file, with paths relative to the project root
     for (src, dest) in files.as_object().unwrap() {
         let dest = dest.as_str().unwrap();
         
         to_html(
-            PathBuf::from(&src), 
-            Some(PathBuf::from(&dest)), 
-            template.clone(),
-            parent 
+            project_root.join(src), 
+            Some(project_root.join(dest)), 
+            Some(template.clone()),
+            parent,
+            exec,
+            false
         ).await
          .map(|_| println!("Rendered {} to {}", src, dest))
-         .map_err(|e| eprintln!("{}", e.to_string()))
+         .map_err(|e| eprintln!("{}", e))
          .ok();
     }
     
     Ok(())
 }
+
+/// Returns the default output path of an export: the markdown file with its extension replaced,
+/// placed in the configured output directory if there is one.
+fn default_output(md_path: &Path, output_dir: Option<&Path>, extension: &str) -> PathBuf {
+    let file_name = Path::new(md_path.file_name().expect("No file name found"))
+        .with_extension(extension);
+
+    match output_dir {
+        Some(dir) => dir.join(file_name),
+        None => md_path.with_file_name(file_name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_style_args() {
+        let template = tempfile::tempdir().unwrap();
+        for format in [DocumentFormat::Docx, DocumentFormat::Odt, DocumentFormat::Epub] {
+            assert!(format.style_args(template.path()).unwrap().is_empty());
+        }
+
+        for file in ["reference.docx", "reference.odt", "style.css"] {
+            fs::write(template.path().join(file), "").unwrap();
+        }
+        let dir = template.path().canonicalize().unwrap();
+        let arg = |option: &str, file: &str| {
+            let mut arg = OsString::from(option);
+            arg.push(dir.join(file));
+            vec![arg]
+        };
+        assert_eq!(
+            DocumentFormat::Docx.style_args(template.path()).unwrap(),
+            arg("--reference-doc=", "reference.docx")
+        );
+        assert_eq!(
+            DocumentFormat::Odt.style_args(template.path()).unwrap(),
+            arg("--reference-doc=", "reference.odt")
+        );
+        assert_eq!(
+            DocumentFormat::Epub.style_args(template.path()).unwrap(),
+            arg("--css=", "style.css")
+        );
+    }
+
+    #[test]
+    fn test_macros_follow_header() {
+        let template = tempfile::tempdir().unwrap();
+        fs::write(template.path().join("header.tex"), "\\usepackage{physics}\n").unwrap();
+        let js = "{ \"\\\\abs\": [\"|#1|\", 1] }";
+        fs::write(template.path().join("katex-macros.js"), js).unwrap();
+        let md_path = template.path().join("notes.md");
+        fs::write(&md_path, "# Notes\n").unwrap();
+
+        let build_dir = tempfile::tempdir().unwrap();
+        let template_path = template.path().to_str().unwrap();
+        let headers =
+            latex_headers(&md_path, template_path, &Config::default(), build_dir.path(), false)
+                .unwrap();
+        let contents: Vec<_> = headers.iter().map(|h| fs::read_to_string(h).unwrap()).collect();
+        assert_eq!(contents.len(), 2);
+        assert_eq!(contents[0], "\\usepackage{physics}\n");
+        assert!(contents[1].contains("\\renewcommand{\\abs}"), "{}", contents[1]);
+    }
+
+    #[test]
+    fn test_epub_args() {
+        let dir = tempfile::tempdir().unwrap();
+        let md_path = dir.path().join("notes.md");
+        let base = ["--to=epub3", "--mathml", "--toc"].map(OsString::from);
+
+        fs::write(&md_path, "# Linear Algebra\n").unwrap();
+        let mut expected = base.to_vec();
+        expected.push("--metadata=title:Linear Algebra".into());
+        assert_eq!(epub_args(&md_path).unwrap(), expected);
+
+        // pandoc reads the title from the front matter itself
+        fs::write(&md_path, "---\ntitle: Notes\n---\n# Linear Algebra\n").unwrap();
+        assert_eq!(epub_args(&md_path).unwrap(), base.to_vec());
+    }
+}
@@ -0,0 +1,473 @@
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::fs;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use anyhow::{Context, Result, anyhow};
+use colored::*;
+
+use crate::citations::CitationStyle;
+use crate::crossref::Numbering;
+use crate::fmt::Bullet;
+use crate::theorems::TheoremConfig;
+use crate::utils::err;
+
+/// Name of the directory holding templates and project files
+pub const TATUM_DIR: &str = ".tatum";
+
+/// Settings read from `~/.config/tatum/config.toml` and the project's `.tatum/config.toml`.
+///
+/// Every field is optional. Values from the project config take precedence over the global one,
+/// and command line arguments take precedence over both.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Template used when `-t` is not given, either a path or the name of a template
+    pub template: Option<String>,
+
+    /// LaTeX engine used to build PDFs, e.g. `pdflatex`, `xelatex` or `lualatex`
+    pub pdf_engine: Option<String>,
+
+    pub serve: ServeConfig,
+
+    pub output: OutputConfig,
+
+    pub exec: ExecConfig,
+
+    pub book: BookConfig,
+
+    pub fmt: FmtConfig,
+
+    /// The directory containing the nearest `.tatum` directory, if any
+    #[serde(skip)]
+    pub project_root: Option<PathBuf>,
+
+    /// `.tatum` directories searched for named templates, nearest first
+    #[serde(skip)]
+    pub template_dirs: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServeConfig {
+    pub port: Option<u16>,
+    pub address: Option<String>,
+}
+
+/// Directories exported files are written to when no output file is given.
+/// Defaults to the directory of the markdown file.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    pub html: Option<PathBuf>,
+    pub latex: Option<PathBuf>,
+    pub pdf: Option<PathBuf>,
+    pub docx: Option<PathBuf>,
+    pub odt: Option<PathBuf>,
+    pub epub: Option<PathBuf>,
+    pub typst: Option<PathBuf>,
+}
+
+/// Running of code blocks marked with `{exec}`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct ExecConfig {
+    /// Whether blocks are run at all. Defaults to false, as blocks can run any command.
+    /// `--exec` and `--no-exec` override it for a single command
+    pub enabled: Option<bool>,
+    /// Seconds a block may run before it is stopped. Defaults to 10
+    pub timeout: Option<u64>,
+    /// Command running the code of each language, given the path of a file holding the code,
+    /// e.g. `python = ["python3", "-u"]`
+    pub interpreters: BTreeMap<String, Vec<String>>,
+}
+
+/// Chapters combined by `tatum book` when no `SUMMARY.md` is given
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct BookConfig {
+    pub title: Option<String>,
+    /// Markdown files in reading order, relative to the project root
+    pub chapters: Vec<PathBuf>,
+}
+
+/// Rules `tatum fmt` normalizes markdown with
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct FmtConfig {
+    /// Marker of bullet list items, `-`, `*` or `+`. Defaults to `-`
+    pub bullet: Option<Bullet>,
+    /// Whether `Title` underlined with `===` or `---` becomes `# Title`. Defaults to true
+    pub atx_headings: Option<bool>,
+    /// Whether the cells of tables are padded so their columns line up. Defaults to true
+    pub align_tables: Option<bool>,
+    /// Width paragraphs are rewrapped to, or 0 to put each paragraph on one line. Line breaks are
+    /// kept when it is not set
+    pub wrap: Option<usize>,
+}
+
+impl Config {
+    /// Loads the global config, then the config of the project `start` is in.
+    ///
+    /// `start` may be a file or a directory. The project is found by searching upward from it for
+    /// a `.tatum` directory.
+    pub fn load(start: &Path) -> Result<Config> {
+        Config::load_from(start, dirs::home_dir().as_deref())
+    }
+
+    /// Loads the configs like `load`, with `home` in place of the user's home directory
+    pub fn load_from(start: &Path, home: Option<&Path>) -> Result<Config> {
+        let start = absolute(start);
+        let start = if start.is_dir() {
+            start
+        } else {
+            start.parent().map(Path::to_path_buf).unwrap_or(start)
+        };
+
+        let mut config = Config::default();
+
+        if let Some(home) = home {
+            let global = home.join(".config").join("tatum").join("config.toml");
+            if global.exists() {
+                config.merge(Config::read(&global, home)?);
+            }
+        }
+
+        let template_dirs = find_tatum_dirs(&start, home);
+        let project_root = template_dirs
+            .first()
+            .and_then(|dir| dir.parent())
+            .map(Path::to_path_buf);
+
+        if let Some(root) = &project_root {
+            let project = root.join(TATUM_DIR).join("config.toml");
+            if project.exists() {
+                config.merge(Config::read(&project, root)?);
+            }
+        }
+
+        config.project_root = project_root;
+        config.template_dirs = template_dirs;
+        Ok(config)
+    }
+
+    /// Reads a single config file, resolving relative paths in it against `base`.
+    fn read(path: &Path, base: &Path) -> Result<Config> {
+        let content = fs::read_to_string(path)
+            .with_context(|| err(format!("Could not read {:?}", path).as_str()))?;
+
+        let mut config: Config = toml::from_str(&content)
+            .map_err(|e| anyhow!(err(format!("Invalid config {:?}:\n{}", path, e).as_str())))?;
+
+        // Template names are kept as is, only paths are resolved
+        if let Some(template) = &config.template {
+            if is_path_like(template) {
+                config.template = Some(base.join(expand_home(template)).to_string_lossy().into_owned());
+            }
+        }
+
+        let output = &mut config.output;
+        for dir in [
+            &mut output.html,
+            &mut output.latex,
+            &mut output.pdf,
+            &mut output.docx,
+            &mut output.odt,
+            &mut output.epub,
+            &mut output.typst,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *dir = base.join(expand_home(&dir.to_string_lossy()));
+        }
+        for chapter in &mut config.book.chapters {
+            *chapter = base.join(expand_home(&chapter.to_string_lossy()));
+        }
+
+        Ok(config)
+    }
+
+    /// Overrides the values in `self` with those set in `other`
+    fn merge(&mut self, other: Config) {
+        self.template = other.template.or(self.template.take());
+        self.pdf_engine = other.pdf_engine.or(self.pdf_engine.take());
+        self.serve.port = other.serve.port.or(self.serve.port);
+        self.serve.address = other.serve.address.or(self.serve.address.take());
+        self.output.html = other.output.html.or(self.output.html.take());
+        self.output.latex = other.output.latex.or(self.output.latex.take());
+        self.output.pdf = other.output.pdf.or(self.output.pdf.take());
+        self.output.docx = other.output.docx.or(self.output.docx.take());
+        self.output.odt = other.output.odt.or(self.output.odt.take());
+        self.output.epub = other.output.epub.or(self.output.epub.take());
+        self.output.typst = other.output.typst.or(self.output.typst.take());
+        self.exec.enabled = other.exec.enabled.or(self.exec.enabled);
+        self.exec.timeout = other.exec.timeout.or(self.exec.timeout);
+        self.exec.interpreters.extend(other.exec.interpreters);
+        self.book.title = other.book.title.or(self.book.title.take());
+        self.fmt.bullet = other.fmt.bullet.or(self.fmt.bullet);
+        self.fmt.atx_headings = other.fmt.atx_headings.or(self.fmt.atx_headings);
+        self.fmt.align_tables = other.fmt.align_tables.or(self.fmt.align_tables);
+        self.fmt.wrap = other.fmt.wrap.or(self.fmt.wrap);
+        if !other.book.chapters.is_empty() {
+            self.book.chapters = other.book.chapters;
+        }
+    }
+
+    /// Returns the path of the template to use.
+    ///
+    /// `arg` is the value of `-t`, falling back to the configured template. Either can be the bare
+    /// name of a template in one of the `.tatum` directories, or a path to a template directory.
+    /// A value with a path separator is always a path. Without one, a named template is preferred,
+    /// and an existing directory is used when there is none of that name.
+    pub fn resolve_template(&self, arg: Option<String>) -> Result<String> {
+        let template = arg.or_else(|| self.template.clone()).ok_or_else(|| {
+            anyhow!(err(
+                "No template given. Pass `-t <TEMPLATE>` or set `template` in .tatum/config.toml"
+            ))
+        })?;
+
+        if !is_path_like(&template) {
+            if let Some(dir) = self.find_template(&template) {
+                return Ok(dir.to_string_lossy().into_owned());
+            }
+        }
+        let path = expand_home(&template);
+        if path.is_dir() {
+            return Ok(path.to_string_lossy().into_owned());
+        }
+
+        let searched: Vec<_> = self
+            .template_dirs
+            .iter()
+            .map(|dir| format!("  {}", dir.to_string_lossy()))
+            .collect();
+
+        Err(anyhow!(err(format!(
+            "Template `{}` not found. Searched:\n{}",
+            template,
+            searched.join("\n")
+        ).as_str())))
+    }
+
+    /// Finds a template by name in the discovered `.tatum` directories
+    pub fn find_template(&self, name: &str) -> Option<PathBuf> {
+        if !is_template_name(name) {
+            return None;
+        }
+
+        self.template_dirs
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|dir| dir.is_dir())
+    }
+
+    /// Returns the `.tatum` directory of the current project
+    pub fn project_dir(&self) -> Result<PathBuf> {
+        self.project_root
+            .as_ref()
+            .map(|root| root.join(TATUM_DIR))
+            .ok_or_else(|| anyhow!(format!(
+                "{} .tatum/ directory doesn't exist.\n{}",
+                "ERROR:".red().bold(),
+                "Please run `tatum init` first".yellow()
+            )))
+    }
+
+    /// Whether code blocks marked with `{exec}` are run
+    pub fn exec_enabled(&self) -> bool {
+        self.exec.enabled.unwrap_or(false)
+    }
+
+    /// Applies the `--exec` or `--no-exec` flag of a command, which wins over the config files
+    pub fn set_exec(&mut self, exec: Option<bool>) {
+        if exec.is_some() {
+            self.exec.enabled = exec;
+        }
+    }
+
+    /// Path of the engine used to compile LaTeX to PDF
+    pub fn pdf_engine(&self) -> &str {
+        self.pdf_engine.as_deref().unwrap_or("pdflatex")
+    }
+}
+
+/// Settings of a single template, read from `template.toml` in the template directory
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct TemplateConfig {
+    /// Template this one inherits from, either a sibling template name, a path relative to this
+    /// template, or a built-in template. Its `*.html` files are available as `<name>/<file>`
+    pub extends: Option<String>,
+
+    /// Whether equations, figures and tables are numbered through the document or per section.
+    /// Documents can override it with `numbering` in their front matter
+    pub numbering: Numbering,
+
+    /// Theorem environments added to or changed from the built-in ones, by name
+    pub theorems: BTreeMap<String, TheoremConfig>,
+
+    /// BibTeX or CSL-JSON file cited by documents without `bibliography` in their front matter,
+    /// relative to the template
+    pub bibliography: Option<PathBuf>,
+
+    /// How citations are formatted in the HTML output, `author-date` or `numeric`
+    pub citation_style: CitationStyle,
+
+    /// CSL style pandoc formats citations with in exports, relative to the template
+    pub csl: Option<PathBuf>,
+}
+
+impl TemplateConfig {
+    /// Reads the `template.toml` of a template, or the defaults if there is none
+    pub fn load(template_path: &Path) -> Result<TemplateConfig> {
+        let path = template_path.join("template.toml");
+        if !path.exists() {
+            return Ok(TemplateConfig::default());
+        }
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| err(format!("Could not read {:?}", path).as_str()))?;
+
+        toml::from_str(&content)
+            .map_err(|e| anyhow!(err(format!("Invalid template config {:?}:\n{}", path, e).as_str())))
+    }
+}
+
+/// Loads the variables shared by header.tex and page.html, e.g. `name` and `student_id`.
+///
+/// They are read from the template's `vars.toml`, with values in the project's `.tatum/vars.toml`
+/// taking precedence.
+pub fn load_vars(template_path: &Path, project_root: Option<&Path>) -> Result<Map<String, Value>> {
+    let mut vars = Map::new();
+
+    let files = [
+        Some(template_path.join("vars.toml")),
+        project_root.map(|root| root.join(TATUM_DIR).join("vars.toml")),
+    ];
+
+    for path in files.into_iter().flatten().filter(|path| path.exists()) {
+        let content = fs::read_to_string(&path)
+            .with_context(|| err(format!("Could not read {:?}", path).as_str()))?;
+        let table: toml::Table = toml::from_str(&content)
+            .map_err(|e| anyhow!(err(format!("Invalid variables file {:?}:\n{}", path, e).as_str())))?;
+
+        if let Value::Object(map) = serde_json::to_value(table)? {
+            vars.extend(map);
+        }
+    }
+
+    Ok(vars)
+}
+
+/// Returns every `.tatum` directory from `start` upwards, followed by the one in `home`
+pub fn find_tatum_dirs(start: &Path, home: Option<&Path>) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = start
+        .ancestors()
+        .map(|dir| dir.join(TATUM_DIR))
+        .filter(|dir| dir.is_dir())
+        .collect();
+
+    if let Some(home) = home.map(|home| home.join(TATUM_DIR)) {
+        if home.is_dir() && !dirs.contains(&home) {
+            dirs.push(home);
+        }
+    }
+
+    dirs
+}
+
+/// Whether a template value refers to a path rather than a template name, which it does when
+/// it contains a path separator
+pub fn is_path_like(value: &str) -> bool {
+    value.contains('/') || value.contains('\\')
+}
+
+/// Whether `name` can name a template directory, rather than being a path, `.` or `..`
+pub fn is_template_name(name: &str) -> bool {
+    !is_path_like(name)
+        && matches!(Path::new(name).components().collect::<Vec<_>>()[..], [Component::Normal(_)])
+}
+
+/// Expands a leading `~` to the home directory
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ if path == "~" => dirs::home_dir().unwrap_or_default(),
+        _ => PathBuf::from(path),
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()
+            .map(|cwd| cwd.join(path))
+            .unwrap_or_else(|_| path.to_path_buf())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project_config_overrides_global() {
+        let mut config: Config = toml::from_str(
+            "template = \"default\"\npdf_engine = \"xelatex\"\n[serve]\nport = 8000\n",
+        )
+        .unwrap();
+        let project: Config = toml::from_str("template = \"bluetot\"\n[serve]\naddress = \"0.0.0.0\"\n").unwrap();
+
+        config.merge(project);
+
+        assert_eq!(config.template.as_deref(), Some("bluetot"));
+        assert_eq!(config.pdf_engine(), "xelatex");
+        assert_eq!(config.serve.port, Some(8000));
+        assert_eq!(config.serve.address.as_deref(), Some("0.0.0.0"));
+    }
+
+    #[test]
+    fn test_resolve_named_template() {
+        let root = tempfile::tempdir().unwrap();
+        let home = root.path().join("home");
+        let tatum = root.path().join(TATUM_DIR);
+        fs::create_dir_all(tatum.join("notes")).unwrap();
+        fs::create_dir_all(home.join(TATUM_DIR).join("shared")).unwrap();
+        fs::create_dir_all(root.path().join("module/week1")).unwrap();
+
+        let config = Config::load_from(&root.path().join("module/week1"), Some(&home)).unwrap();
+
+        assert_eq!(config.project_root.as_deref(), Some(root.path()));
+        assert_eq!(
+            config.resolve_template(Some("notes".into())).unwrap(),
+            tatum.join("notes").to_string_lossy()
+        );
+        assert_eq!(
+            config.resolve_template(Some("shared".into())).unwrap(),
+            home.join(TATUM_DIR).join("shared").to_string_lossy()
+        );
+        assert!(config.resolve_template(Some("missing".into())).is_err());
+
+        // directories without a separator are used when no template has their name
+        assert_eq!(config.resolve_template(Some(".".into())).unwrap(), ".");
+        assert_eq!(config.resolve_template(Some("src".into())).unwrap(), "src");
+        let path = tatum.join("notes").to_string_lossy().into_owned();
+        assert_eq!(config.resolve_template(Some(path.clone())).unwrap(), path);
+    }
+
+    #[test]
+    fn test_global_config_is_read_from_home() {
+        let root = tempfile::tempdir().unwrap();
+        let home = root.path().join("home");
+        fs::create_dir_all(home.join(".config/tatum")).unwrap();
+        fs::write(home.join(".config/tatum/config.toml"), "template = \"notes\"\n").unwrap();
+
+        let config = Config::load_from(root.path(), Some(&home)).unwrap();
+        assert_eq!(config.template.as_deref(), Some("notes"));
+
+        let config = Config::load_from(root.path(), None).unwrap();
+        assert!(config.template.is_none());
+    }
+}
@@ -0,0 +1,765 @@
+use std::ops::Range;
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use pulldown_cmark::{CowStr, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::config::TemplateConfig;
+use crate::utils::{err, escape_html};
+
+/// How equations, figures and tables are numbered
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Numbering {
+    /// 1, 2, 3, ... through the whole document
+    #[default]
+    Document,
+    /// 1.1, 1.2, 2.1, ... restarting at every top level heading
+    Section,
+}
+
+impl Numbering {
+    /// The numbering of a document, set with `numbering` in its front matter or the template's
+    /// template.toml
+    pub fn for_document(template_path: &Path, meta: &Map<String, Value>) -> Result<Numbering> {
+        match meta.get("numbering").and_then(Value::as_str) {
+            Some("document") => Ok(Numbering::Document),
+            Some("section") => Ok(Numbering::Section),
+            Some(other) => Err(anyhow!(err(format!(
+                "Unknown numbering `{}` in front matter, expected `document` or `section`",
+                other
+            ).as_str()))),
+            None => Ok(TemplateConfig::load(template_path)?.numbering),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Equation,
+    Figure,
+    Table,
+}
+
+/// Whether `@id` is a cross-reference rather than a citation
+pub fn is_reference(id: &str) -> bool {
+    Kind::from_id(id).is_some()
+}
+
+impl Kind {
+    /// The kind referenced by `@eq:...`, `@fig:...` and `@tbl:...`
+    fn from_id(id: &str) -> Option<Kind> {
+        match id.split_once(':')?.0 {
+            "eq" => Some(Kind::Equation),
+            "fig" => Some(Kind::Figure),
+            "tbl" => Some(Kind::Table),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Equation => "Equation",
+            Kind::Figure => "Figure",
+            Kind::Table => "Table",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A numbered equation, figure or table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub kind: Kind,
+    /// The label, e.g. `eq:energy`. Figures and tables are numbered without one
+    pub id: Option<String>,
+    pub number: String,
+    /// Where the math, the image or the table caption is in the source
+    pub range: Range<usize>,
+    /// Where the `{#id}` attribute following it is, if any
+    pub attribute: Option<Range<usize>>,
+}
+
+/// The numbered elements of a document
+#[derive(Debug, Default)]
+pub struct CrossRefs {
+    pub targets: Vec<Target>,
+}
+
+impl CrossRefs {
+    /// Numbers the labelled display math, figures and captioned tables of a document.
+    ///
+    /// Only labelled equations are numbered, as LaTeX does for `$$` math. Figures are images
+    /// alone in a paragraph with alt text, and tables are numbered when they have a pandoc style
+    /// `: Caption` paragraph directly before or after them.
+    pub fn collect(source: &str, numbering: Numbering) -> CrossRefs {
+        let events: Vec<_> = Parser::new_ext(source, Options::all()).into_offset_iter().collect();
+
+        let mut section = 0;
+        let mut counters = [0; 3];
+        let mut targets = Vec::new();
+
+        let next = |kind: Kind, section: usize, counters: &mut [usize; 3]| {
+            counters[kind.index()] += 1;
+            match numbering {
+                Numbering::Document => counters[kind.index()].to_string(),
+                Numbering::Section => format!("{}.{}", section, counters[kind.index()]),
+            }
+        };
+
+        for (index, (event, range)) in events.iter().enumerate() {
+            match event {
+                Event::Start(Tag::Heading { level: HeadingLevel::H1, .. }) => {
+                    section += 1;
+                    if numbering == Numbering::Section {
+                        counters = [0; 3];
+                    }
+                }
+                Event::DisplayMath(math) => {
+                    let attribute = attribute(source, range.end, true);
+                    let id = match &attribute {
+                        Some((id, _)) => Some(id.clone()),
+                        None => math_label(math).map(|(id, _)| id),
+                    };
+                    if let Some(id) = id {
+                        targets.push(Target {
+                            kind: Kind::Equation,
+                            number: next(Kind::Equation, section, &mut counters),
+                            id: Some(id),
+                            range: range.clone(),
+                            attribute: attribute.map(|(_, range)| range),
+                        });
+                    }
+                }
+                Event::Start(Tag::Paragraph) => {
+                    if let Some((image, attribute)) = figure(source, &events[index..]) {
+                        targets.push(Target {
+                            kind: Kind::Figure,
+                            number: next(Kind::Figure, section, &mut counters),
+                            id: attribute.as_ref().map(|(id, _)| id.clone()),
+                            range: image,
+                            attribute: attribute.map(|(_, range)| range),
+                        });
+                    } else if is_table_caption(source, &events, index) {
+                        let attribute = caption_attribute(&source[range.clone()])
+                            .map(|(id, r)| (id, range.start + r.start..range.start + r.end));
+                        targets.push(Target {
+                            kind: Kind::Table,
+                            number: next(Kind::Table, section, &mut counters),
+                            id: attribute.as_ref().map(|(id, _)| id.clone()),
+                            range: range.clone(),
+                            attribute: attribute.map(|(_, range)| range),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        CrossRefs { targets }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Target> {
+        self.targets.iter().find(|target| target.id.as_deref() == Some(id))
+    }
+
+    /// The element of `kind` whose math, image or caption starts at `start`
+    pub fn at(&self, kind: Kind, start: usize) -> Option<&Target> {
+        self.targets.iter().find(|target| target.kind == kind && target.range.start == start)
+    }
+
+    /// Numbers the elements of the parsed document and turns references into links
+    pub fn to_html<'a>(&self, source: &'a str) -> Vec<Event<'a>> {
+        let attributes: Vec<_> = self.targets.iter().filter_map(|t| t.attribute.clone()).collect();
+
+        let mut events = Vec::new();
+        let mut in_caption = false;
+        let mut strip_caption = false;
+        let mut in_code = false;
+
+        for (event, range) in parse(source) {
+            match event {
+                Event::Start(Tag::CodeBlock(_)) => {
+                    in_code = true;
+                    events.push(event);
+                }
+                Event::End(TagEnd::CodeBlock) => {
+                    in_code = false;
+                    events.push(event);
+                }
+                Event::DisplayMath(math) => match self.at(Kind::Equation, range.start) {
+                    Some(target) => {
+                        let id = html_id(target.id.as_deref());
+                        let number = format!("({})", target.number);
+                        let math = self.resolve_math(&without_label(&math));
+                        events.push(Event::InlineHtml(
+                            format!("<span class=\"equation\"{}>", id).into(),
+                        ));
+                        events.push(Event::DisplayMath(math.into()));
+                        events.push(Event::InlineHtml(
+                            format!("<span class=\"equation-number\">{}</span></span>", number)
+                                .into(),
+                        ));
+                    }
+                    None => events.push(Event::DisplayMath(self.resolve_math(&math).into())),
+                },
+                Event::InlineMath(math) => {
+                    events.push(Event::InlineMath(self.resolve_math(&math).into()))
+                }
+                Event::Start(Tag::Image { .. }) => {
+                    let target = self.at(Kind::Figure, range.start);
+                    if let Some(id) = target.and_then(|t| t.id.as_ref()) {
+                        let anchor = format!("<span{}></span>", html_id(Some(id)));
+                        events.push(Event::InlineHtml(anchor.into()));
+                    }
+                    events.push(event);
+                    // the caption of the figure is made from the alt text
+                    if let Some(target) = target {
+                        events.push(Event::Text(format!("Figure {}: ", target.number).into()));
+                    }
+                }
+                Event::Start(Tag::Paragraph) => match self.at(Kind::Table, range.start) {
+                    Some(target) => {
+                        let id = html_id(target.id.as_deref());
+                        events.push(Event::Html(format!("<p class=\"table-caption\"{}>", id).into()));
+                        events.push(Event::Text(format!("Table {}: ", target.number).into()));
+                        in_caption = true;
+                        strip_caption = true;
+                    }
+                    None => events.push(event),
+                },
+                Event::End(TagEnd::Paragraph) if in_caption => {
+                    events.push(Event::Html("</p>\n".into()));
+                    in_caption = false;
+                }
+                Event::Text(text) if !in_code => {
+                    let mut text = without_attributes(source, text, &range, &attributes);
+                    if strip_caption {
+                        text = caption_text(&text).to_string();
+                        strip_caption = false;
+                    }
+                    events.extend(self.resolve_text(&text));
+                }
+                event => events.push(event),
+            }
+        }
+
+        events
+    }
+
+    /// Splits text at references, turning them into links
+    fn resolve_text<'a>(&self, text: &str) -> Vec<Event<'a>> {
+        let mut events = Vec::new();
+        let mut last = 0;
+
+        for reference in references(text) {
+            if reference.range.start > last {
+                events.push(Event::Text(text[last..reference.range.start].to_string().into()));
+            }
+            let html = match self.get(&reference.id) {
+                Some(target) => format!(
+                    "<a class=\"xref\" href=\"#{}\">{}</a>",
+                    escape_html(&reference.id),
+                    reference.style.format(target.kind, &target.number)
+                ),
+                None => format!(
+                    "<span class=\"xref-missing\" title=\"Unknown reference {}\">??</span>",
+                    escape_html(&reference.id)
+                ),
+            };
+            events.push(Event::InlineHtml(html.into()));
+            last = reference.range.end;
+        }
+
+        if last < text.len() || events.is_empty() {
+            events.push(Event::Text(text[last..].to_string().into()));
+        }
+        events
+    }
+
+    /// Replaces `\ref` and `\eqref` in math with the numbers, as KaTeX has no references
+    pub fn resolve_math(&self, math: &str) -> String {
+        let mut out = String::new();
+        let mut last = 0;
+        for reference in references(math).into_iter().filter(|r| r.style != Style::Named) {
+            out.push_str(&math[last..reference.range.start]);
+            let number = self.get(&reference.id).map(|t| t.number.as_str()).unwrap_or("??");
+            match reference.style {
+                Style::Eqref => out.push_str(&format!("\\text{{({})}}", number)),
+                _ => out.push_str(&format!("\\text{{{}}}", number)),
+            }
+            last = reference.range.end;
+        }
+        out.push_str(&math[last..]);
+        out
+    }
+
+    /// Rewrites a document for pandoc so the LaTeX output has the same numbers and labels.
+    ///
+    /// Labelled display math becomes an `equation` environment tagged with its number, and
+    /// `@eq:...` references become `\eqref`. Figures and table captions keep their `{#id}`,
+    /// which pandoc turns into labels itself.
+    pub fn to_latex_markdown(&self, source: &str) -> String {
+        let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+
+        for target in self.targets.iter().filter(|t| t.kind == Kind::Equation) {
+            let math = &source[target.range.clone()];
+            let math = math.trim_start_matches('$').trim_end_matches('$');
+            let math = without_label(math);
+            let id = target.id.as_deref().unwrap_or_default();
+            let latex = format!(
+                "\\begin{{equation}}\\tag{{{}}}\\label{{{}}}{}\\end{{equation}}",
+                target.number, id, math
+            );
+            let end = target.attribute.as_ref().map_or(target.range.end, |a| a.end);
+            edits.push((target.range.start..end, raw_latex(&latex)));
+        }
+
+        let mut in_code = false;
+        for (event, range) in parse(source) {
+            match event {
+                Event::Start(Tag::CodeBlock(_)) => in_code = true,
+                Event::End(TagEnd::CodeBlock) => in_code = false,
+                _ => {}
+            }
+            if in_code || !matches!(event, Event::Text(_)) {
+                continue;
+            }
+            let text = &source[range.clone()];
+            for reference in references(text).into_iter().filter(|r| r.style == Style::Named) {
+                let Some(kind) = Kind::from_id(&reference.id) else { continue };
+                let command = if kind == Kind::Equation { "eqref" } else { "ref" };
+                let latex = format!("{}~\\{}{{{}}}", kind.name(), command, reference.id);
+                let start = range.start + reference.range.start;
+                edits.push((start..range.start + reference.range.end, raw_latex(&latex)));
+            }
+        }
+
+        apply_edits(source, edits)
+    }
+
+    /// Rewrites a document for pandoc's Word and OpenDocument writers, which have no references.
+    ///
+    /// As in the HTML output, labelled equations get their number, figures and table captions a
+    /// `Figure 1: ` or `Table 1: ` prefix, and references become their text. `{#id}` attributes
+    /// are kept, so pandoc still writes bookmarks for them.
+    pub fn to_word_markdown(&self, source: &str) -> String {
+        let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+        let mut in_code = false;
+
+        for (event, range) in parse(source) {
+            match event {
+                Event::Start(Tag::CodeBlock(_)) => in_code = true,
+                Event::End(TagEnd::CodeBlock) => in_code = false,
+                Event::DisplayMath(math) => {
+                    let (math, end) = match self.at(Kind::Equation, range.start) {
+                        Some(target) => (
+                            format!(
+                                "{}\\qquad\\text{{({})}}",
+                                self.resolve_math(&without_label(&math)),
+                                target.number
+                            ),
+                            target.attribute.as_ref().map_or(range.end, |a| a.end),
+                        ),
+                        None => (self.resolve_math(&math), range.end),
+                    };
+                    edits.push((range.start..end, format!("$${}$$", math)));
+                }
+                Event::InlineMath(math) => {
+                    let resolved = self.resolve_math(&math);
+                    if resolved != *math {
+                        edits.push((range, format!("${}$", resolved)));
+                    }
+                }
+                Event::Start(Tag::Image { .. }) => {
+                    if let Some(target) = self.at(Kind::Figure, range.start) {
+                        // after the `![` of the alt text
+                        let start = range.start + 2;
+                        edits.push((start..start, format!("Figure {}: ", target.number)));
+                    }
+                }
+                Event::Start(Tag::Paragraph) => {
+                    if let Some(target) = self.at(Kind::Table, range.start) {
+                        let text = &source[range.clone()];
+                        let start = range.start + text.len() - caption_text(text).len();
+                        edits.push((start..start, format!("Table {}: ", target.number)));
+                    }
+                }
+                Event::Text(_) if !in_code => {
+                    let text = &source[range.clone()];
+                    for reference in references(text) {
+                        let replacement = match self.get(&reference.id) {
+                            Some(target) => reference.style.format(target.kind, &target.number),
+                            None => "??".to_string(),
+                        };
+                        let start = range.start + reference.range.start;
+                        edits.push((start..range.start + reference.range.end, replacement));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        apply_edits(source, edits)
+    }
+}
+
+/// Replaces the ranges of `source` in `edits`, skipping those overlapping an earlier one
+pub fn apply_edits(source: &str, mut edits: Vec<(Range<usize>, String)>) -> String {
+    edits.sort_by_key(|(range, _)| range.start);
+    let mut out = String::new();
+    let mut last = 0;
+    for (range, replacement) in edits {
+        if range.start < last {
+            continue;
+        }
+        out.push_str(&source[last..range.start]);
+        out.push_str(&replacement);
+        last = range.end;
+    }
+    out.push_str(&source[last..]);
+    out
+}
+
+/// LaTeX preamble numbering figures and tables within sections, as in the HTML
+pub const SECTION_PREAMBLE: &str =
+    "\\numberwithin{figure}{section}\n\\numberwithin{table}{section}\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// `@eq:energy`, shown as "Equation (1)"
+    Named,
+    /// `\ref{eq:energy}`, shown as "1"
+    Ref,
+    /// `\eqref{eq:energy}`, shown as "(1)"
+    Eqref,
+}
+
+impl Style {
+    pub fn format(self, kind: Kind, number: &str) -> String {
+        match (self, kind) {
+            (Style::Named, Kind::Equation) => format!("{} ({})", kind.name(), number),
+            (Style::Named, _) => format!("{} {}", kind.name(), number),
+            (Style::Ref, _) => number.to_string(),
+            (Style::Eqref, _) => format!("({})", number),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Reference {
+    pub id: String,
+    pub style: Style,
+    pub range: Range<usize>,
+}
+
+/// Finds `@eq:...`, `@fig:...`, `@tbl:...`, `\ref{...}` and `\eqref{...}` in text
+pub fn references(text: &str) -> Vec<Reference> {
+    let mut references = Vec::new();
+    let bytes = text.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'@' && (i == 0 || !bytes[i - 1].is_ascii_alphanumeric()) {
+            let len = text[i + 1..]
+                .find(|c: char| !(c.is_alphanumeric() || "_-:.".contains(c)))
+                .unwrap_or(text.len() - i - 1);
+            // trailing punctuation ends the sentence rather than the label
+            let id = text[i + 1..i + 1 + len].trim_end_matches(['.', ':']);
+            if Kind::from_id(id).is_some() && !id.ends_with(':') {
+                let end = i + 1 + id.len();
+                let id = id.to_string();
+                references.push(Reference { id, style: Style::Named, range: i..end });
+                i = end;
+                continue;
+            }
+        }
+
+        if bytes[i] == b'\\' {
+            for (command, style) in [("\\ref{", Style::Ref), ("\\eqref{", Style::Eqref)] {
+                if !text[i..].starts_with(command) {
+                    continue;
+                }
+                if let Some(close) = text[i + command.len()..].find('}') {
+                    let id = &text[i + command.len()..i + command.len() + close];
+                    let end = i + command.len() + close + 1;
+                    references.push(Reference { id: id.trim().to_string(), style, range: i..end });
+                    i = end - 1;
+                }
+                break;
+            }
+        }
+        i += 1;
+    }
+
+    references
+}
+
+/// The id of a `{#id}` attribute and where it is in the source
+pub type Attribute = (String, Range<usize>);
+
+/// Parses a `{#id ...}` attribute at `offset`, returning the id and the attribute's range.
+/// Spaces before it are skipped if `space` is set.
+fn attribute(source: &str, offset: usize, space: bool) -> Option<Attribute> {
+    let rest = &source[offset..];
+    let trimmed = if space { rest.trim_start_matches([' ', '\t']) } else { rest };
+    let start = offset + rest.len() - trimmed.len();
+    let close = trimmed.strip_prefix('{')?.find(['}', '\n'])?;
+    let inner = &trimmed[1..close + 1];
+    if !trimmed[close + 1..].starts_with('}') {
+        return None;
+    }
+    let id = inner.split_whitespace().find_map(|part| part.strip_prefix('#'))?;
+    Some((id.to_string(), if space { offset..start + close + 2 } else { start..start + close + 2 }))
+}
+
+/// Removes the `\label{...}` of math, which is replaced by the anchor or `equation` environment
+pub fn without_label(math: &str) -> String {
+    match math_label(math) {
+        Some((_, label)) => format!("{}{}", &math[..label.start], &math[label.end..]),
+        None => math.to_string(),
+    }
+}
+
+/// Finds a `\label{...}` in math, returning the label and its range
+fn math_label(math: &str) -> Option<Attribute> {
+    let start = math.find("\\label{")?;
+    let close = math[start..].find('}')?;
+    let id = math[start + "\\label{".len()..start + close].trim().to_string();
+    Some((id, start..start + close + 1))
+}
+
+/// Checks whether the paragraph starting at `events[0]` is only an image with alt text, which
+/// pandoc turns into a figure. Returns the image's range and its attribute.
+fn figure(
+    source: &str,
+    events: &[(Event, Range<usize>)],
+) -> Option<(Range<usize>, Option<Attribute>)> {
+    let (Event::Start(Tag::Image { .. }), image) = events.get(1)? else { return None };
+    let end = events.iter().position(|(event, _)| matches!(event, Event::End(TagEnd::Image)))?;
+    let has_alt = events[2..end]
+        .iter()
+        .any(|(event, _)| matches!(event, Event::Text(text) if !text.trim().is_empty()));
+
+    let attribute = attribute(source, image.end, false);
+    let rest_start = attribute.as_ref().map_or(image.end, |(_, range)| range.end);
+    let (_, paragraph) = &events[0];
+    if !source[rest_start..paragraph.end].trim().is_empty() || !has_alt {
+        return None;
+    }
+    Some((image.clone(), attribute))
+}
+
+/// Whether the paragraph at `events[index]` is a `: Caption` or `Table: Caption` next to a table
+fn is_table_caption(source: &str, events: &[(Event, Range<usize>)], index: usize) -> bool {
+    let (_, range) = &events[index];
+    let text = &source[range.clone()];
+    if caption_text(text).len() == text.len() {
+        return false;
+    }
+
+    let after_table = index > 0 && matches!(events[index - 1].0, Event::End(TagEnd::Table));
+    let before_table = events[index..]
+        .iter()
+        .position(|(event, _)| matches!(event, Event::End(TagEnd::Paragraph)))
+        .and_then(|end| events.get(index + end + 1))
+        .is_some_and(|(event, _)| matches!(event, Event::Start(Tag::Table(_))));
+    after_table || before_table
+}
+
+/// Strips the `: ` or `Table: ` starting a table caption
+pub fn caption_text(text: &str) -> &str {
+    let trimmed = text.trim_start();
+    trimmed
+        .strip_prefix("Table:")
+        .or_else(|| trimmed.strip_prefix(':'))
+        .map(str::trim_start)
+        .unwrap_or(text)
+}
+
+/// Finds the `{#tbl:...}` attribute at the end of a caption paragraph
+pub fn caption_attribute(paragraph: &str) -> Option<Attribute> {
+    let trimmed = paragraph.trim_end();
+    let open = trimmed.rfind('{')?;
+    let (id, range) = attribute(trimmed, open, false)?;
+    (range.end == trimmed.len()).then_some((id, range))
+}
+
+/// Removes the parts of a text event that are attributes of an equation, figure or table
+pub fn without_attributes(
+    source: &str,
+    text: CowStr,
+    range: &Range<usize>,
+    attributes: &[Range<usize>],
+) -> String {
+    let overlapping: Vec<_> = attributes
+        .iter()
+        .filter(|a| a.start < range.end && a.end > range.start)
+        .collect();
+    if overlapping.is_empty() {
+        return text.to_string();
+    }
+
+    let mut out = String::new();
+    let mut last = range.start;
+    for attribute in overlapping {
+        out.push_str(&source[last..attribute.start.max(range.start)]);
+        last = attribute.end.min(range.end);
+    }
+    out.push_str(&source[last..range.end]);
+    out.trim_end().to_string()
+}
+
+/// Parses a document into events with their source ranges, joining adjacent text
+pub fn parse(source: &str) -> Vec<(Event<'_>, Range<usize>)> {
+    merge_text(Parser::new_ext(source, Options::all()).into_offset_iter())
+}
+
+/// Joins adjacent text events, as the parser may split text at backslashes and brackets
+fn merge_text<'a>(
+    events: impl Iterator<Item = (Event<'a>, Range<usize>)>,
+) -> Vec<(Event<'a>, Range<usize>)> {
+    let mut merged: Vec<(Event<'a>, Range<usize>)> = Vec::new();
+    for (event, range) in events {
+        if let (Event::Text(text), Some((Event::Text(previous), previous_range))) =
+            (&event, merged.last_mut())
+        {
+            if previous_range.end == range.start {
+                *previous = format!("{}{}", previous, text).into();
+                previous_range.end = range.end;
+                continue;
+            }
+        }
+        merged.push((event, range));
+    }
+    merged
+}
+
+/// Wraps LaTeX in a pandoc raw inline, so it is passed through unchanged
+fn raw_latex(latex: &str) -> String {
+    let mut fence = "`".to_string();
+    while latex.contains(fence.as_str()) {
+        fence.push('`');
+    }
+    format!("{}{}{}{{=latex}}", fence, latex, fence)
+}
+
+fn html_id(id: Option<&str>) -> String {
+    match id {
+        Some(id) => format!(" id=\"{}\"", escape_html(id)),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOC: &str = "\
+# Motion
+
+$$
+F = ma
+$$ {#eq:newton}
+
+![A falling apple](apple.png){#fig:apple}
+
+# Energy
+
+$$E = mc^2 \\label{eq:energy}$$
+
+See @eq:energy, @fig:apple and \\eqref{eq:newton}.
+
+| a | b |
+|---|---|
+| 1 | 2 |
+
+: Results {#tbl:results}
+";
+
+    #[test]
+    fn test_collect() {
+        let refs = CrossRefs::collect(DOC, Numbering::Section);
+        let numbers: Vec<_> = refs
+            .targets
+            .iter()
+            .map(|t| (t.kind, t.id.as_deref().unwrap(), t.number.as_str()))
+            .collect();
+        assert_eq!(numbers, [
+            (Kind::Equation, "eq:newton", "1.1"),
+            (Kind::Figure, "fig:apple", "1.1"),
+            (Kind::Equation, "eq:energy", "2.1"),
+            (Kind::Table, "tbl:results", "2.1"),
+        ]);
+
+        let refs = CrossRefs::collect(DOC, Numbering::Document);
+        assert_eq!(refs.get("eq:energy").unwrap().number, "2");
+    }
+
+    #[test]
+    fn test_to_html() {
+        let refs = CrossRefs::collect(DOC, Numbering::Document);
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, refs.to_html(DOC).into_iter());
+
+        assert!(html.contains("<span class=\"equation-number\">(2)</span>"));
+        assert!(html.contains("<a class=\"xref\" href=\"#eq:energy\">Equation (2)</a>"));
+        assert!(html.contains("<a class=\"xref\" href=\"#fig:apple\">Figure 1</a>"));
+        assert!(html.contains("<a class=\"xref\" href=\"#eq:newton\">(1)</a>"));
+        assert!(html.contains("alt=\"Figure 1: A falling apple\""));
+        assert!(html.contains(
+            "<p class=\"table-caption\" id=\"tbl:results\">Table 1: Results</p>"
+        ));
+        assert!(!html.contains("{#"));
+        assert!(!html.contains("\\label"));
+    }
+
+    #[test]
+    fn test_to_latex_markdown() {
+        let refs = CrossRefs::collect(DOC, Numbering::Document);
+        let markdown = refs.to_latex_markdown(DOC);
+
+        assert!(markdown.contains(
+            "`\\begin{equation}\\tag{2}\\label{eq:energy}E = mc^2 \\end{equation}`{=latex}"
+        ));
+        assert!(markdown.contains("`Equation~\\eqref{eq:energy}`{=latex}"));
+        assert!(markdown.contains("`Figure~\\ref{fig:apple}`{=latex}"));
+        assert!(markdown.contains("![A falling apple](apple.png){#fig:apple}"));
+        assert!(!markdown.contains("{#eq:newton}"));
+    }
+
+    #[test]
+    fn test_to_word_markdown() {
+        let refs = CrossRefs::collect(DOC, Numbering::Document);
+        let markdown = refs.to_word_markdown(DOC);
+
+        assert!(markdown.contains("$$E = mc^2 \\qquad\\text{(2)}$$"));
+        assert!(markdown.contains("See Equation (2), Figure 1 and (1)."));
+        assert!(markdown.contains(": Table 1: Results {#tbl:results}"));
+        assert!(markdown.contains("![Figure 1: A falling apple](apple.png){#fig:apple}"));
+        assert!(!markdown.contains("@eq:") && !markdown.contains("\\label"));
+    }
+
+    #[test]
+    fn test_references_in_code_are_kept() {
+        let doc = "$$\nF = ma\n$$ {#eq:newton}\n\nSee @eq:newton.\n\n\
+                   ```python\n@property\ndef f(self): return \"@eq:newton\"\n```\n\n\
+                   Write `@eq:newton` for a reference.\n";
+        let refs = CrossRefs::collect(doc, Numbering::Document);
+
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, refs.to_html(doc).into_iter());
+        assert!(html.contains("See <a class=\"xref\" href=\"#eq:newton\">Equation (1)</a>."));
+        assert!(html.contains("@property\ndef f(self): return \"@eq:newton\"\n</code>"));
+        assert!(html.contains("<code>@eq:newton</code>"));
+
+        let markdown = refs.to_latex_markdown(doc);
+        assert!(markdown.contains("See `Equation~\\eqref{eq:newton}`{=latex}."));
+        assert!(markdown.contains("```python\n@property\ndef f(self): return \"@eq:newton\"\n```"));
+        assert!(markdown.contains("Write `@eq:newton` for a reference."));
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use pulldown_cmark::{CodeBlockKind, Event, Tag, TagEnd};
+
+use crate::crossref;
+use crate::latex_log::parse_latex_log;
+use crate::utils::{cache_dir, warn};
+
+/// The tool a fenced code block is drawn with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    /// ```` ```dot ```` or ```` ```graphviz ````, drawn by Graphviz's `dot`
+    Graphviz,
+    /// ```` ```tikz ````, compiled by the LaTeX engine and converted by `dvisvgm`
+    Tikz,
+}
+
+impl Kind {
+    /// The kind of diagram a code block draws, from the first word of its info string
+    fn from_info(info: &str) -> Option<Kind> {
+        match info.split_whitespace().next()? {
+            "dot" | "graphviz" => Some(Kind::Graphviz),
+            "tikz" => Some(Kind::Tikz),
+            _ => None,
+        }
+    }
+}
+
+/// A diagram block of a document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagram {
+    pub kind: Kind,
+    pub source: String,
+}
+
+/// Finds the diagram blocks among the events of a document, in order
+pub fn find(events: &[Event]) -> Vec<Diagram> {
+    let mut diagrams = Vec::new();
+    let mut current: Option<Diagram> = None;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                current = Kind::from_info(info).map(|kind| Diagram { kind, source: String::new() });
+            }
+            Event::Text(text) => {
+                if let Some(diagram) = &mut current {
+                    diagram.source.push_str(text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => diagrams.extend(current.take()),
+            _ => {}
+        }
+    }
+
+    diagrams
+}
+
+/// Replaces the diagram blocks among `events` with the HTML drawn for each, in the order `find`
+/// returned them
+pub fn replace_blocks<'a>(events: Vec<Event<'a>>, html: Vec<String>) -> Vec<Event<'a>> {
+    let mut html = html.into_iter();
+    let mut out = Vec::with_capacity(events.len());
+    let mut in_diagram = false;
+
+    for event in events {
+        match &event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info)))
+                if Kind::from_info(info).is_some() =>
+            {
+                in_diagram = true;
+                out.extend(html.next().map(|html| Event::Html(html.into())));
+            }
+            Event::End(TagEnd::CodeBlock) if in_diagram => in_diagram = false,
+            _ if in_diagram => {}
+            _ => out.push(event),
+        }
+    }
+
+    out
+}
+
+impl Diagram {
+    /// Draws the diagram as SVG to be inlined in a page, reusing the copy cached for the same
+    /// source. TikZ pictures are compiled with the LaTeX `engine`.
+    ///
+    /// Errors are short messages meant to be shown in place of the diagram.
+    pub fn to_svg(&self, engine: &str) -> Result<String, String> {
+        let hash = self.hash(engine);
+        let path = cached_path(hash, "svg")?;
+        if let Ok(svg) = fs::read_to_string(&path) {
+            return Ok(svg);
+        }
+
+        let svg = match self.kind {
+            Kind::Graphviz => String::from_utf8_lossy(&run_dot(&self.source, "svg")?).into_owned(),
+            Kind::Tikz => self.tikz_svg(engine)?,
+        };
+        let svg = inline_svg(&svg, &format!("diagram-{:016x}-", hash));
+
+        // a failed write only means drawing the diagram again next time
+        if fs::create_dir_all(path.parent().unwrap()).is_ok() {
+            let _ = fs::write(&path, &svg);
+        }
+        Ok(svg)
+    }
+
+    /// Draws a Graphviz diagram as a PDF for LaTeX to include, returning the path of the cached
+    /// copy
+    fn to_pdf(&self) -> Result<PathBuf, String> {
+        let path = cached_path(self.hash(""), "pdf")?;
+        if !path.exists() {
+            let pdf = run_dot(&self.source, "pdf")?;
+            fs::create_dir_all(path.parent().unwrap())
+                .and_then(|_| fs::write(&path, pdf))
+                .map_err(|e| format!("Could not cache the diagram: {}", e))?;
+        }
+        Ok(path)
+    }
+
+    fn hash(&self, engine: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.kind.hash(&mut hasher);
+        self.source.hash(&mut hasher);
+        if self.kind == Kind::Tikz {
+            engine.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Compiles the picture to DVI, or XDV with XeLaTeX, and converts that to SVG
+    fn tikz_svg(&self, engine: &str) -> Result<String, String> {
+        let dir = tempfile::Builder::new()
+            .prefix("tatum-tikz-")
+            .tempdir()
+            .map_err(|e| format!("Could not create build directory: {}", e))?;
+        let (preamble, picture) = split_tikz(&self.source);
+        let document = format!(
+            "\\documentclass[tikz]{{standalone}}\n{}\\begin{{document}}\n{}\\end{{document}}\n",
+            preamble.concat(),
+            picture
+        );
+        fs::write(dir.path().join("diagram.tex"), document)
+            .map_err(|e| format!("Could not write the picture: {}", e))?;
+
+        let is_xelatex = Path::new(engine)
+            .file_stem()
+            .is_some_and(|name| name.to_string_lossy().contains("xelatex"));
+        let (dvi_arg, dvi) = if is_xelatex {
+            ("-no-pdf", "diagram.xdv")
+        } else {
+            ("-output-format=dvi", "diagram.dvi")
+        };
+
+        let output = Command::new(engine)
+            .args(["-interaction=nonstopmode", "-halt-on-error", dvi_arg, "diagram.tex"])
+            .current_dir(dir.path())
+            .output()
+            .map_err(|e| not_installed(engine, e))?;
+        if !output.status.success() {
+            let log = fs::read_to_string(dir.path().join("diagram.log")).unwrap_or_default();
+            return Err(parse_latex_log(&log)
+                .first()
+                .map(|diagnostic| diagnostic.message.clone())
+                .unwrap_or_else(|| format!("{} failed to draw the picture", engine)));
+        }
+
+        let output = Command::new("dvisvgm")
+            .args(["--no-fonts", "--exact-bbox", "--output=diagram.svg", dvi])
+            .current_dir(dir.path())
+            .output()
+            .map_err(|e| not_installed("dvisvgm", e))?;
+        if !output.status.success() {
+            return Err(first_line(&output.stderr, "dvisvgm failed to convert the picture"));
+        }
+
+        fs::read_to_string(dir.path().join("diagram.svg"))
+            .map_err(|e| format!("Could not read the picture: {}", e))
+    }
+}
+
+/// Where a drawn diagram is cached, by the hash of its source
+fn cached_path(hash: u64, extension: &str) -> Result<PathBuf, String> {
+    let name = format!("{:016x}.{}", hash, extension);
+    Ok(cache_dir().map_err(|e| e.to_string())?.join("diagrams").join(name))
+}
+
+/// Runs `dot` on a graph, returning its output in `format`
+fn run_dot(source: &str, format: &str) -> Result<Vec<u8>, String> {
+    let mut child = Command::new("dot")
+        .arg(format!("-T{}", format))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| not_installed("dot", e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(source.as_bytes())
+            .map_err(|e| format!("Could not run dot: {}", e))?;
+    }
+
+    let output = child.wait_with_output().map_err(|e| format!("Could not run dot: {}", e))?;
+    if !output.status.success() {
+        return Err(first_line(&output.stderr, "dot failed to draw the graph"));
+    }
+    Ok(output.stdout)
+}
+
+fn not_installed(program: &str, e: std::io::Error) -> String {
+    match e.kind() {
+        ErrorKind::NotFound => format!("{} is not installed", program),
+        _ => format!("Could not run {}: {}", program, e),
+    }
+}
+
+/// The first line a tool printed to stderr, or `fallback` if it printed nothing
+fn first_line(stderr: &[u8], fallback: &str) -> String {
+    String::from_utf8_lossy(stderr)
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or(fallback)
+        .to_string()
+}
+
+/// Drops the XML declaration of an SVG file and prefixes its ids, so that those of several
+/// diagrams in a page do not clash
+fn inline_svg(svg: &str, prefix: &str) -> String {
+    let svg = &svg[svg.find("<svg").unwrap_or(0)..];
+    svg.replace(" id=\"", &format!(" id=\"{}", prefix))
+        .replace(" id='", &format!(" id='{}", prefix))
+        .replace("href=\"#", &format!("href=\"#{}", prefix))
+        .replace("href='#", &format!("href='#{}", prefix))
+        .replace("url(#", &format!("url(#{}", prefix))
+}
+
+/// Splits a TikZ block into its `\usepackage` and `\usetikzlibrary` lines, which belong in the
+/// preamble, and the picture, wrapped in a `tikzpicture` unless it has one
+fn split_tikz(source: &str) -> (Vec<String>, String) {
+    let (preamble, body): (Vec<&str>, Vec<&str>) = source.split_inclusive('\n').partition(|line| {
+        let line = line.trim_start();
+        line.starts_with("\\usepackage") || line.starts_with("\\usetikzlibrary")
+    });
+
+    let preamble = preamble.iter().map(|line| format!("{}\n", line.trim())).collect();
+    let mut picture = body.concat();
+    if !picture.ends_with('\n') {
+        picture.push('\n');
+    }
+    if !picture.contains("\\begin{tikzpicture}") {
+        picture = format!("\\begin{{tikzpicture}}\n{}\\end{{tikzpicture}}\n", picture);
+    }
+    (preamble, picture)
+}
+
+/// Turns the diagram blocks of a document into what LaTeX draws natively: TikZ pictures are
+/// passed through as raw LaTeX and Graphviz graphs included as PDFs drawn by `dot`.
+///
+/// Also returns the preamble the TikZ pictures need. Graphs that cannot be drawn are left as code
+/// blocks, with a warning.
+pub fn to_latex_markdown(md_path: &Path, source: &str) -> (String, String) {
+    let mut preamble: Vec<String> = Vec::new();
+    let mut has_tikz = false;
+
+    let out = replace_diagrams(md_path, source, |diagram| match diagram.kind {
+        Kind::Tikz => {
+            let (lines, picture) = split_tikz(&diagram.source);
+            has_tikz = true;
+            for line in lines {
+                if !preamble.contains(&line) {
+                    preamble.push(line);
+                }
+            }
+            Ok(format!("```{{=latex}}\n\\begin{{center}}\n{}\\end{{center}}\n```", picture))
+        }
+        Kind::Graphviz => {
+            diagram.to_pdf().map(|path| format!("![](<{}>)", path.to_string_lossy()))
+        }
+    });
+
+    let preamble = if has_tikz {
+        format!("\\usepackage{{tikz}}\n{}", preamble.concat())
+    } else {
+        String::new()
+    };
+    (out, preamble)
+}
+
+/// Turns the diagram blocks of a document into images of the SVG drawn for the HTML output, which
+/// pandoc embeds in Word and OpenDocument files. Diagrams that cannot be drawn are left as code
+/// blocks, with a warning.
+pub fn to_image_markdown(md_path: &Path, source: &str, engine: &str) -> String {
+    replace_diagrams(md_path, source, |diagram| {
+        diagram.to_svg(engine)?;
+        let path = cached_path(diagram.hash(engine), "svg")?;
+        Ok(format!("![](<{}>)", path.to_string_lossy()))
+    })
+}
+
+/// Replaces each diagram block of a document with the markdown `draw` returns for it, keeping the
+/// block and warning when it fails
+fn replace_diagrams(
+    md_path: &Path,
+    source: &str,
+    mut draw: impl FnMut(&Diagram) -> Result<String, String>,
+) -> String {
+    let events = crossref::parse(source);
+    let mut out = String::with_capacity(source.len());
+    let mut last = 0;
+
+    let mut i = 0;
+    while i < events.len() {
+        let (Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))), range) = &events[i] else {
+            i += 1;
+            continue;
+        };
+        let Some(kind) = Kind::from_info(info) else {
+            i += 1;
+            continue;
+        };
+
+        let mut diagram = Diagram { kind, source: String::new() };
+        while let Some((event, _)) = events.get(i + 1) {
+            i += 1;
+            match event {
+                Event::Text(text) => diagram.source.push_str(text),
+                _ => break,
+            }
+        }
+
+        let replacement = match draw(&diagram) {
+            Ok(replacement) => replacement,
+            Err(message) => {
+                let line = source[..range.start].matches('\n').count() + 1;
+                let location = format!("{}:{}", md_path.to_string_lossy(), line);
+                println!("{}", warn(&format!("{}: {}", location, message)));
+                i += 1;
+                continue;
+            }
+        };
+
+        out.push_str(&source[last..range.start]);
+        out.push_str(&replacement);
+        if source[range.clone()].ends_with('\n') {
+            out.push('\n');
+        }
+        last = range.end;
+        i += 1;
+    }
+    out.push_str(&source[last..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_tikz() {
+        let (preamble, picture) = split_tikz("\\usetikzlibrary{automata}\n\\node[state] {q};\n");
+        assert_eq!(preamble, ["\\usetikzlibrary{automata}\n"]);
+        assert_eq!(picture, "\\begin{tikzpicture}\n\\node[state] {q};\n\\end{tikzpicture}\n");
+
+        let svg = inline_svg("<?xml?>\n<svg><path id='g0'/><use xlink:href='#g0'/></svg>", "d-");
+        assert_eq!(svg, "<svg><path id='d-g0'/><use xlink:href='#d-g0'/></svg>");
+    }
+
+    #[test]
+    fn test_tikz_to_latex_markdown() {
+        let source = "Text\n\n```tikz\n\\usetikzlibrary{automata}\n\\draw (0,0) -- (1,1);\n```\n";
+        let (markdown, preamble) = to_latex_markdown(Path::new("notes.md"), source);
+        assert_eq!(
+            markdown,
+            "Text\n\n```{=latex}\n\\begin{center}\n\\begin{tikzpicture}\n\\draw (0,0) -- (1,1);\n\
+             \\end{tikzpicture}\n\\end{center}\n```\n"
+        );
+        assert_eq!(preamble, "\\usepackage{tikz}\n\\usetikzlibrary{automata}\n");
+    }
+}
@@ -0,0 +1,9 @@
+use askama::Template;
+
+/// Page shown in place of a document when rendering it fails.
+#[derive(Debug, Template)]
+#[template(path = "render-error.html")]
+pub struct RenderErrorTemplate {
+    pub title: String,
+    pub message: String,
+}
@@ -0,0 +1,315 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use pulldown_cmark::{CodeBlockKind, Event, Tag};
+use serde::{Deserialize, Serialize};
+
+use crate::config::ExecConfig;
+use crate::crossref;
+use crate::utils::{cache_dir, warn};
+
+/// Seconds a block may run when the config does not set `exec.timeout`
+const DEFAULT_TIMEOUT: u64 = 10;
+
+/// Commands for languages the config has no interpreter for
+const DEFAULT_INTERPRETERS: &[(&str, &[&str])] = &[
+    ("python", &["python3"]),
+    ("py", &["python3"]),
+    ("bash", &["bash"]),
+    ("sh", &["sh"]),
+    ("javascript", &["node"]),
+    ("js", &["node"]),
+    ("ruby", &["ruby"]),
+    ("r", &["Rscript"]),
+];
+
+/// What a block printed, and whether it saved an image to `$TATUM_IMAGE`
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+struct Output {
+    text: String,
+    image: bool,
+}
+
+/// A code block marked with `{exec}`, e.g. ```` ```python {exec} ````
+#[derive(Debug, Clone, PartialEq)]
+struct Block {
+    language: String,
+    code: String,
+}
+
+/// Runs the code blocks of a document marked with `{exec}` and inserts what each printed below
+/// it, as an `output` code block, followed by the image it saved to the path in `$TATUM_IMAGE`,
+/// if any.
+///
+/// Blocks run in the directory of the document, and are stopped after the configured timeout.
+/// Results are cached by the code and interpreter of the block, so unchanged blocks are not run
+/// again. With `run` false, the blocks are only shown.
+pub fn run_blocks(md_path: &Path, source: &str, config: &ExecConfig, run: bool) -> String {
+    let dir = md_path.parent().unwrap_or(Path::new("."));
+    let events = crossref::parse(source);
+    let mut out = String::with_capacity(source.len());
+    let mut last = 0;
+
+    let mut i = 0;
+    while i < events.len() {
+        let (Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))), range) = &events[i] else {
+            i += 1;
+            continue;
+        };
+        let Some(mut block) = Block::from_info(info) else {
+            i += 1;
+            continue;
+        };
+        while let Some((Event::Text(text), _)) = events.get(i + 1) {
+            block.code.push_str(text);
+            i += 1;
+        }
+        i += 1;
+
+        // the block itself is kept, without the marker
+        let text = &source[range.clone()];
+        let first_line = text.find('\n').unwrap_or(text.len());
+        out.push_str(&source[last..range.start]);
+        out.push_str(&text[..first_line].replacen(" {exec}", "", 1).replacen("{exec}", "", 1));
+        out.push_str(&text[first_line..]);
+        last = range.end;
+
+        if !run {
+            continue;
+        }
+
+        let line = source[..range.start].matches('\n').count() + 1;
+        let location = format!("{}:{}", md_path.to_string_lossy(), line);
+        let output = match block.run(dir, config) {
+            Ok(output) => output,
+            Err(message) => {
+                println!("{}", warn(&format!("{}: {}", location, message)));
+                continue;
+            }
+        };
+
+        // each result is a paragraph of its own below the block
+        let mut results = String::new();
+        if !output.text.is_empty() {
+            let fence = fence_for(&output.text);
+            let newline = if output.text.ends_with('\n') { "" } else { "\n" };
+            results.push_str(&format!("\n\n{}output\n{}{}{}", fence, output.text, newline, fence));
+        }
+        if let (true, Ok(image)) = (output.image, block.cached_path(dir, config, "png")) {
+            results.push_str(&format!("\n\n![](<{}>)", image.to_string_lossy()));
+        }
+        if out.ends_with('\n') {
+            results = results.replacen('\n', "", 1);
+        }
+        out.push_str(&results);
+    }
+    out.push_str(&source[last..]);
+
+    out
+}
+
+impl Block {
+    /// The block of a fenced code block's info string, if it is marked to be run
+    fn from_info(info: &str) -> Option<Block> {
+        let mut words = info.split_whitespace();
+        let language = words.next().filter(|word| *word != "{exec}")?;
+        words.any(|word| word == "{exec}").then(|| Block {
+            language: language.to_lowercase(),
+            code: String::new(),
+        })
+    }
+
+    /// The command running the block, from the config or the defaults
+    fn interpreter(&self, config: &ExecConfig) -> Option<Vec<String>> {
+        config.interpreters.get(&self.language).cloned().or_else(|| {
+            DEFAULT_INTERPRETERS
+                .iter()
+                .find(|(language, _)| *language == self.language)
+                .map(|(_, command)| command.iter().map(|part| part.to_string()).collect())
+        })
+    }
+
+    /// Where the results of the block are cached, by its code, interpreter and directory
+    fn cached_path(
+        &self,
+        dir: &Path,
+        config: &ExecConfig,
+        extension: &str,
+    ) -> Result<PathBuf, String> {
+        let mut hasher = DefaultHasher::new();
+        self.language.hash(&mut hasher);
+        self.code.hash(&mut hasher);
+        self.interpreter(config).hash(&mut hasher);
+        dir.hash(&mut hasher);
+        let name = format!("{:016x}.{}", hasher.finish(), extension);
+        Ok(cache_dir().map_err(|e| e.to_string())?.join("exec").join(name))
+    }
+
+    /// Runs the block, or returns its cached output
+    fn run(&self, dir: &Path, config: &ExecConfig) -> Result<Output, String> {
+        let command = self
+            .interpreter(config)
+            .filter(|command| !command.is_empty())
+            .ok_or_else(|| format!("No interpreter for `{}` in [exec.interpreters]", self.language))?;
+
+        let cached = self.cached_path(dir, config, "json")?;
+        if let Some(output) = fs::read_to_string(&cached)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+        {
+            return Ok(output);
+        }
+
+        let image = self.cached_path(dir, config, "png")?;
+        fs::create_dir_all(cached.parent().unwrap())
+            .map_err(|e| format!("Could not create the cache directory: {}", e))?;
+        let _ = fs::remove_file(&image);
+
+        let mut script = tempfile::Builder::new()
+            .prefix("tatum-exec-")
+            .tempfile()
+            .map_err(|e| format!("Could not write the code to run: {}", e))?;
+        script
+            .write_all(self.code.as_bytes())
+            .map_err(|e| format!("Could not write the code to run: {}", e))?;
+
+        let mut process = Command::new(&command[0]);
+        process
+            .args(&command[1..])
+            .arg(script.path())
+            .current_dir(dir)
+            .env("TATUM_IMAGE", &image)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // a group of its own, so that processes started by the block are stopped with it
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut process, 0);
+        let mut child = process
+            .spawn()
+            .map_err(|e| format!("Could not run {}: {}", command[0], e))?;
+
+        // read while the block runs, so it cannot block on a full pipe
+        let stdout = read_in_background(child.stdout.take());
+        let stderr = read_in_background(child.stderr.take());
+
+        let timeout = Duration::from_secs(config.timeout.unwrap_or(DEFAULT_TIMEOUT));
+        let start = Instant::now();
+        let timed_out = loop {
+            match child.try_wait() {
+                Ok(Some(_)) => break false,
+                Ok(None) if start.elapsed() > timeout => {
+                    stop(&mut child);
+                    break true;
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(20)),
+                Err(e) => return Err(format!("Could not run {}: {}", command[0], e)),
+            }
+        };
+
+        let mut text = stdout.join().unwrap_or_default();
+        text.push_str(&stderr.join().unwrap_or_default());
+        if timed_out {
+            // not cached, so that the block is given another chance
+            text.push_str(&format!("Stopped after {} s\n", timeout.as_secs()));
+            return Ok(Output { text, image: image.exists() });
+        }
+
+        let output = Output { text, image: image.exists() };
+        if let Ok(json) = serde_json::to_string(&output) {
+            let _ = fs::write(&cached, json);
+        }
+        Ok(output)
+    }
+}
+
+/// Stops a block that ran out of time, along with the processes it started
+fn stop(child: &mut Child) {
+    #[cfg(unix)]
+    let _ = Command::new("kill")
+        .args(["-s", "KILL", "--"])
+        .arg(format!("-{}", child.id()))
+        .status();
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    })
+}
+
+/// A backtick fence longer than any run of backticks in `text`
+fn fence_for(text: &str) -> String {
+    let mut fence = "```".to_string();
+    while text.contains(fence.as_str()) {
+        fence.push('`');
+    }
+    fence
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_run_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("data.txt"), "from the note's folder\n").unwrap();
+        let md_path = dir.path().join("note.md");
+        let source = "```sh {exec}\ncat data.txt\n```\n\n```sh\necho not run\n```\n";
+
+        let config = ExecConfig::default();
+        assert_eq!(
+            run_blocks(&md_path, source, &config, true),
+            "```sh\ncat data.txt\n```\n\n```output\nfrom the note's folder\n```\n\n\
+             ```sh\necho not run\n```\n"
+        );
+        assert_eq!(
+            run_blocks(&md_path, source, &config, false),
+            "```sh\ncat data.txt\n```\n\n```sh\necho not run\n```\n"
+        );
+    }
+
+    #[test]
+    fn test_not_run_without_config_or_flag() {
+        let dir = tempfile::tempdir().unwrap();
+        let md_path = dir.path().join("note.md");
+        let source = "```sh {exec}\ntouch ran\n```\n";
+
+        let mut config = Config::default();
+        config.set_exec(None);
+        assert_eq!(
+            run_blocks(&md_path, source, &config.exec, config.exec_enabled()),
+            "```sh\ntouch ran\n```\n"
+        );
+        assert!(!dir.path().join("ran").exists());
+
+        config.set_exec(Some(true));
+        run_blocks(&md_path, source, &config.exec, config.exec_enabled());
+        assert!(dir.path().join("ran").exists());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ExecConfig { timeout: Some(0), ..ExecConfig::default() };
+        let block = Block { language: "sh".into(), code: "sleep 5\n".into() };
+
+        let start = Instant::now();
+        let output = block.run(dir.path(), &config).unwrap();
+        assert_eq!(output.text, "Stopped after 0 s\n");
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,497 @@
+use std::fs;
+use std::ops::Range;
+use std::path::PathBuf;
+use anyhow::{Context, Result, anyhow};
+use colored::*;
+use pulldown_cmark::{Alignment, Event, Options, Parser, Tag, TagEnd};
+use serde::Deserialize;
+
+use crate::config::{Config, FmtConfig};
+use crate::crossref::apply_edits;
+use crate::front_matter::split_front_matter;
+use crate::utils::{err, err_no_md_file, print_diff};
+
+/// The marker of bullet list items
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Bullet {
+    #[default]
+    #[serde(rename = "-")]
+    Dash,
+    #[serde(rename = "*")]
+    Star,
+    #[serde(rename = "+")]
+    Plus,
+}
+
+impl Bullet {
+    fn marker(self) -> u8 {
+        match self {
+            Bullet::Dash => b'-',
+            Bullet::Star => b'*',
+            Bullet::Plus => b'+',
+        }
+    }
+}
+
+/// Formats markdown files in place with the rules of their project's config. With `check`, the
+/// files are left as they are and the changes are printed instead, failing if there are any
+pub fn fmt(files: Vec<PathBuf>, check: bool) -> Result<()> {
+    let mut unformatted = 0;
+
+    for file in &files {
+        if !file.exists() {
+            return Err(anyhow!(err_no_md_file(file)));
+        }
+        let source = fs::read_to_string(file)
+            .with_context(|| err(format!("Could not read {:?}", file).as_str()))?;
+        let rules = Config::load(file)?.fmt;
+        let formatted = format_markdown(&source, &rules);
+        if formatted == source {
+            continue;
+        }
+
+        unformatted += 1;
+        if check {
+            println!("{} {}", "Would reformat".yellow().bold(), file.to_string_lossy());
+            print_diff(&source, &formatted);
+        } else {
+            fs::write(file, &formatted)
+                .with_context(|| err(format!("Could not write {:?}", file).as_str()))?;
+            println!("Formatted {}", file.to_string_lossy());
+        }
+    }
+
+    if check && unformatted > 0 {
+        return Err(anyhow!(err(
+            format!("{} of {} file(s) need formatting", unformatted, files.len()).as_str()
+        )));
+    }
+    if unformatted == 0 {
+        println!("{} {} file(s) already formatted", "✓".green().bold(), files.len());
+    }
+    Ok(())
+}
+
+/// Rewrites a document in a consistent style: one bullet marker, `#` headings, tables with
+/// aligned columns, paragraphs wrapped to one width and no runs of blank lines.
+///
+/// Only the syntax around the content changes. The front matter, code, HTML and math are left
+/// as they are, and paragraphs are never broken inside inline math or code. Headings, tables and
+/// paragraphs inside lists and quotes keep their layout, as rewriting them would need their
+/// indentation.
+pub fn format_markdown(source: &str, rules: &FmtConfig) -> String {
+    let body = split_front_matter(source).1;
+    let front_matter = &source[..source.len() - body.len()];
+    let events: Vec<_> = Parser::new_ext(body, Options::all()).into_offset_iter().collect();
+
+    let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+    // lists, quotes and footnotes the current event is in
+    let mut depth = 0;
+    // whether the marker of each open list's items is changed
+    let mut lists: Vec<bool> = Vec::new();
+    let mut verbatim: Vec<Range<usize>> = Vec::new();
+
+    for (index, (event, range)) in events.iter().enumerate() {
+        match event {
+            Event::Start(Tag::List(start)) => {
+                // a list next to another one is only separate because its marker differs
+                let follows_list = index > 0
+                    && matches!(events[index - 1].0, Event::End(TagEnd::List(false)));
+                let followed_by_list = start.is_none()
+                    && matches!(
+                        events.get(list_end(&events, index) + 1),
+                        Some((Event::Start(Tag::List(None)), _))
+                    );
+                lists.push(start.is_none() && !follows_list && !followed_by_list);
+                depth += 1;
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                depth -= 1;
+            }
+            Event::Start(Tag::BlockQuote(_)) | Event::Start(Tag::FootnoteDefinition(_)) => {
+                depth += 1
+            }
+            Event::End(TagEnd::BlockQuote) | Event::End(TagEnd::FootnoteDefinition) => depth -= 1,
+            Event::Start(Tag::Item) if lists.last() == Some(&true) => {
+                let marker = rules.bullet.unwrap_or_default().marker();
+                // the range of nested items may start with some of their indentation
+                let item = &body[range.clone()];
+                let at = range.start + item.len() - item.trim_start_matches([' ', '\t']).len();
+                let current = body.as_bytes()[at];
+                if matches!(current, b'-' | b'*' | b'+') && current != marker {
+                    edits.push((at..at + 1, (marker as char).to_string()));
+                }
+            }
+            Event::Start(Tag::Heading { level, .. })
+                if depth == 0 && rules.atx_headings.unwrap_or(true) =>
+            {
+                let text = body[range.clone()].trim_end();
+                let heading = atx_heading(text, *level as usize);
+                if heading != text {
+                    edits.push((range.start..range.start + text.len(), heading));
+                }
+            }
+            Event::Start(Tag::Table(alignments))
+                if depth == 0 && rules.align_tables.unwrap_or(true) =>
+            {
+                let text = body[range.clone()].trim_end();
+                let table = align_table(text, alignments);
+                if table != text {
+                    edits.push((range.start..range.start + text.len(), table));
+                }
+            }
+            Event::Start(Tag::Paragraph) if depth == 0 => {
+                let Some(width) = rules.wrap else { continue };
+                let end = events[index..]
+                    .iter()
+                    .position(|(event, _)| matches!(event, Event::End(TagEnd::Paragraph)))
+                    .map_or(events.len(), |end| index + end);
+                let text = body[range.clone()].trim_end();
+                if let Some(wrapped) = wrap(text, range.start, &events[index + 1..end], width) {
+                    if wrapped != text {
+                        edits.push((range.start..range.start + text.len(), wrapped));
+                    }
+                }
+            }
+            Event::Start(Tag::CodeBlock(_))
+            | Event::Start(Tag::HtmlBlock)
+            | Event::Start(Tag::MetadataBlock(_))
+            | Event::DisplayMath(_) => verbatim.push(range.clone()),
+            _ => {}
+        }
+    }
+
+    verbatim.extend(dollar_math_blocks(body, &verbatim));
+    edits.extend(blank_line_runs(body, &verbatim));
+    let body = apply_edits(body, edits);
+
+    let mut out = format!("{}{}", front_matter, body);
+    if !body.trim().is_empty() {
+        out.truncate(out.trim_end_matches('\n').len());
+        out.push('\n');
+    }
+    out
+}
+
+/// Returns the index of the event ending the list started at `start`
+fn list_end(events: &[(Event, Range<usize>)], start: usize) -> usize {
+    let mut open = 0;
+    for (index, (event, _)) in events.iter().enumerate().skip(start) {
+        match event {
+            Event::Start(Tag::List(_)) => open += 1,
+            Event::End(TagEnd::List(_)) => {
+                open -= 1;
+                if open == 0 {
+                    return index;
+                }
+            }
+            _ => {}
+        }
+    }
+    events.len()
+}
+
+/// Writes a heading as `## Title`, with the level it has in `text`, which may be underlined or
+/// have closing `#`s
+fn atx_heading(text: &str, level: usize) -> String {
+    let hashes = "#".repeat(level);
+    let content = if text.trim_start().starts_with('#') {
+        let content = text.trim().trim_start_matches('#').trim_end();
+        let unclosed = content.trim_end_matches('#');
+        if unclosed.is_empty() || unclosed.ends_with([' ', '\t']) {
+            unclosed.trim().to_string()
+        } else {
+            content.trim().to_string()
+        }
+    } else {
+        // the underline is the last line, and the title may span the others
+        let lines: Vec<_> = text.lines().collect();
+        let title = &lines[..lines.len().saturating_sub(1)];
+        title.iter().map(|line| line.trim()).collect::<Vec<_>>().join(" ")
+    };
+
+    if content.is_empty() {
+        hashes
+    } else {
+        format!("{} {}", hashes, content)
+    }
+}
+
+/// Pads the cells of a table so its columns line up, writing each row with outer pipes
+fn align_table(text: &str, alignments: &[Alignment]) -> String {
+    let rows: Vec<Vec<&str>> = text.lines().map(cells).collect();
+    let columns = alignments.len();
+
+    let mut widths = vec![3; columns];
+    for row in rows.iter().enumerate().filter(|(i, _)| *i != 1).map(|(_, row)| row) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut out = Vec::with_capacity(rows.len());
+    for (i, row) in rows.iter().enumerate() {
+        let mut line = String::from("|");
+        if i == 1 {
+            for (alignment, width) in alignments.iter().zip(&widths) {
+                let delimiter = match alignment {
+                    Alignment::None => "-".repeat(*width),
+                    Alignment::Left => format!(":{}", "-".repeat(width - 1)),
+                    Alignment::Right => format!("{}:", "-".repeat(width - 1)),
+                    Alignment::Center => format!(":{}:", "-".repeat(width - 2)),
+                };
+                line.push_str(&format!(" {} |", delimiter));
+            }
+        } else {
+            for (column, width) in widths.iter().enumerate() {
+                let cell = row.get(column).copied().unwrap_or_default();
+                let padding = width - cell.chars().count();
+                let (left, right) = match alignments[column] {
+                    Alignment::Right => (padding, 0),
+                    Alignment::Center => (padding / 2, padding - padding / 2),
+                    _ => (0, padding),
+                };
+                line.push_str(&format!(" {}{}{} |", " ".repeat(left), cell, " ".repeat(right)));
+            }
+            // cells past the header's are not shown, but are kept
+            for cell in row.iter().skip(columns) {
+                line.push_str(&format!(" {} |", cell));
+            }
+        }
+        out.push(line);
+    }
+    out.join("\n")
+}
+
+/// Splits a table row at the pipes that are not escaped, trimming the cells
+fn cells(line: &str) -> Vec<&str> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = match line.strip_suffix('|') {
+        Some(rest) if !rest.ends_with('\\') => rest,
+        _ => line,
+    };
+
+    let mut cells = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\\' => escaped = !escaped,
+            '|' if !escaped => {
+                cells.push(line[start..i].trim());
+                start = i + 1;
+            }
+            _ => escaped = false,
+        }
+    }
+    cells.push(line[start..].trim());
+    cells
+}
+
+/// Rewraps a paragraph to `width` columns, or onto one line if `width` is 0, given its text at
+/// `offset` and its inline events.
+///
+/// Returns `None` for paragraphs whose line breaks matter: those with hard breaks, HTML or display
+/// math, those holding `:::` container fences or `{{#include}}` lines, and those with a `$` that
+/// is not part of inline math.
+fn wrap(
+    text: &str,
+    offset: usize,
+    inlines: &[(Event, Range<usize>)],
+    width: usize,
+) -> Option<String> {
+    let mut atomic = Vec::new();
+    for (event, range) in inlines {
+        match event {
+            Event::HardBreak | Event::Html(_) | Event::InlineHtml(_) | Event::DisplayMath(_) => {
+                return None
+            }
+            Event::InlineMath(_) | Event::Code(_) => {
+                atomic.push(range.start - offset..range.end - offset)
+            }
+            _ => {}
+        }
+    }
+    let fenced = |line: &str| {
+        let line = line.trim_start();
+        line.starts_with(":::") || line.starts_with("{{")
+    };
+    if text.lines().any(fenced) {
+        return None;
+    }
+    // a `$` outside of the math found may be math the parser did not take as such, e.g. `$$`
+    // blocks with blank lines
+    let dollar = text.match_indices('$').map(|(i, _)| i);
+    if dollar.clone().any(|i| !atomic.iter().any(|range| range.contains(&i))) {
+        return None;
+    }
+
+    // words are split at whitespace outside of math and code
+    let mut words: Vec<Range<usize>> = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        let inside = atomic.iter().any(|range| range.start < i && i < range.end);
+        if c.is_whitespace() && !inside {
+            if let Some(start) = start.take() {
+                words.push(start..i);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(start) = start {
+        words.push(start..text.len());
+    }
+
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    for word in words {
+        let word = &text[word];
+        let length = line.chars().count() + 1 + word.chars().count();
+        if line.is_empty() {
+            line.push_str(word);
+        } else if width > 0 && length > width && !starts_block(word) {
+            lines.push(std::mem::take(&mut line));
+            line.push_str(word);
+        } else {
+            line.push(' ');
+            line.push_str(word);
+        }
+    }
+    lines.push(line);
+    Some(lines.join("\n"))
+}
+
+/// Whether a line starting with `word` would start a heading, list, quote, fence or other block
+fn starts_block(word: &str) -> bool {
+    let digits = word.find(|c: char| !c.is_ascii_digit()).unwrap_or(word.len());
+    word.starts_with(['#', '>', '|', '<'])
+        || ["```", "~~~", "$$"].iter().any(|fence| word.starts_with(fence))
+        || (word.starts_with('[') && word.contains("]:"))
+        || word.chars().all(|c| "-+*_=".contains(c))
+        || (digits > 0 && word[digits..].starts_with(['.', ')']))
+}
+
+/// `$$` math blocks outside of `verbatim` blocks, from the line opening them to the one closing
+/// them. The parser does not take blocks with blank lines as math, but KaTeX and pandoc do
+fn dollar_math_blocks(body: &str, verbatim: &[Range<usize>]) -> Vec<Range<usize>> {
+    let mut blocks = Vec::new();
+    let mut open: Option<usize> = None;
+    let mut offset = 0;
+
+    for line in body.split_inclusive('\n') {
+        let range = offset..offset + line.len();
+        offset += line.len();
+        if verbatim.iter().any(|v| v.start < range.end && range.start < v.end) {
+            continue;
+        }
+        let trimmed = line.trim();
+        match open {
+            Some(start) if trimmed.contains("$$") => {
+                blocks.push(start..range.end);
+                open = None;
+            }
+            None if trimmed.starts_with("$$") && trimmed[2..].find("$$").is_none() => {
+                open = Some(range.start)
+            }
+            _ => {}
+        }
+    }
+    blocks
+}
+
+/// Edits turning each run of blank lines outside of `verbatim` blocks into a single one
+fn blank_line_runs(body: &str, verbatim: &[Range<usize>]) -> Vec<(Range<usize>, String)> {
+    let mut edits = Vec::new();
+    let mut run: Option<Range<usize>> = None;
+    let mut offset = 0;
+
+    for line in body.split_inclusive('\n') {
+        let range = offset..offset + line.len();
+        offset += line.len();
+        let protected = verbatim.iter().any(|v| v.start < range.end && range.start < v.end);
+        if line.trim().is_empty() && !protected {
+            run = Some(run.map_or(range.clone(), |run| run.start..range.end));
+            continue;
+        }
+        if let Some(run) = run.take() {
+            if body[run.clone()].matches('\n').count() > 1 {
+                edits.push((run, "\n".to_string()));
+            }
+        }
+    }
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_markdown() {
+        let source = "---\ntitle:   Notes\n---\n\nIntro\n=====\n\n\n\n## Setup ##\n\n\
+                      * one\n* two\n    + nested\n\n\
+                      | a | b |\n|:-|:-:|\n| long cell | $x$ |\n\n\
+                      ```\n* code\n\n\n```\n";
+        let formatted = format_markdown(source, &FmtConfig::default());
+        assert_eq!(
+            formatted,
+            "---\ntitle:   Notes\n---\n\n# Intro\n\n## Setup\n\n\
+             - one\n- two\n    - nested\n\n\
+             | a         |  b  |\n| :-------- | :-: |\n| long cell | $x$ |\n\n\
+             ```\n* code\n\n\n```\n"
+        );
+        assert_eq!(format_markdown(&formatted, &FmtConfig::default()), formatted);
+        assert_eq!(parse(&formatted), parse(source));
+    }
+
+    #[test]
+    fn test_adjacent_lists_stay_separate() {
+        let rules = FmtConfig::default();
+        for source in [
+            "* a\n* b\n\n- c\n- d\n",
+            "- a\n- b\n\n* c\n* d\n",
+            "* a\n    + x\n    * y\n* b\n\n+ c\n",
+        ] {
+            let formatted = format_markdown(source, &rules);
+            assert_eq!(parse(&formatted), parse(source), "{:?} became {:?}", source, formatted);
+        }
+
+        // lists apart from each other are still rewritten
+        assert_eq!(format_markdown("* a\n\ntext\n\n* b\n", &rules), "- a\n\ntext\n\n- b\n");
+    }
+
+    fn parse(source: &str) -> Vec<Event<'_>> {
+        Parser::new_ext(source, Options::all()).collect()
+    }
+
+    #[test]
+    fn test_math_blocks_are_kept() {
+        let rules = FmtConfig::default();
+        let source = "$$\na\n\n\n\nb\n$$\n";
+        assert_eq!(format_markdown(source, &rules), source);
+
+        let source = "Text\n\n\n$$x$$\n\n\n$$\n\\begin{aligned}\n\n\na\n\\end{aligned} $$\n";
+        assert_eq!(
+            format_markdown(source, &rules),
+            "Text\n\n$$x$$\n\n$$\n\\begin{aligned}\n\n\na\n\\end{aligned} $$\n"
+        );
+    }
+
+    #[test]
+    fn test_wrap() {
+        let rules = FmtConfig { wrap: Some(20), ..FmtConfig::default() };
+        let source = "Some text with $a + b = c$ and `a b c` in it - 1. done\n";
+        assert_eq!(
+            format_markdown(source, &rules),
+            "Some text with\n$a + b = c$ and\n`a b c` in it - 1.\ndone\n"
+        );
+
+        // line breaks of containers and hard breaks are kept
+        let source = "::: theorem\nA very long statement here.\n:::\n\nLine one  \nline two\n\n\
+                      $$\nx   =   1\n\n$$\n";
+        assert_eq!(format_markdown(source, &rules), source);
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs;
+use std::path::Path;
+use serde_json::{Map, Value};
+use anyhow::{Result, anyhow};
+
+use crate::utils::err;
+
+/// Splits a markdown document into its YAML front matter and the rest of the document.
+///
+/// Front matter is a block at the very start of the file delimited by `---` lines. The line
+/// closing it may also be `...`, as pandoc allows.
+pub fn split_front_matter(source: &str) -> (Option<&str>, &str) {
+    let Some(rest) = source
+        .strip_prefix("---\n")
+        .or_else(|| source.strip_prefix("---\r\n"))
+    else {
+        return (None, source);
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        if trimmed == "---" || trimmed == "..." {
+            return (Some(&rest[..offset]), &rest[offset + line.len()..]);
+        }
+        offset += line.len();
+    }
+
+    (None, source)
+}
+
+/// Parses the YAML front matter of a markdown document into a map.
+/// Documents without front matter give an empty map.
+pub fn parse_front_matter(source: &str) -> Result<Map<String, Value>> {
+    let Some(yaml) = split_front_matter(source).0 else {
+        return Ok(Map::new());
+    };
+
+    if yaml.trim().is_empty() {
+        return Ok(Map::new());
+    }
+
+    let value: Value = serde_yaml::from_str(yaml)
+        .map_err(|e| anyhow!(err(format!("Invalid front matter: {}", e).as_str())))?;
+
+    match value {
+        Value::Object(map) => Ok(map),
+        Value::Null => Ok(Map::new()),
+        _ => Err(anyhow!(err("Front matter must be a set of `key: value` pairs"))),
+    }
+}
+
+/// The title of a markdown file: the one in its front matter, or its first heading, or its file
+/// name
+pub fn document_title(path: &Path) -> String {
+    let source = fs::read_to_string(path).unwrap_or_default();
+    let meta = parse_front_matter(&source).unwrap_or_default();
+    if let Some(title) = meta.get("title").and_then(Value::as_str) {
+        return title.to_string();
+    }
+
+    split_front_matter(&source)
+        .1
+        .lines()
+        .find_map(|line| line.strip_prefix("# "))
+        .map(|heading| heading.trim().to_string())
+        .unwrap_or_else(|| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_front_matter() {
+        let source = "---\ntitle: Notes\n---\n# Heading\n";
+        assert_eq!(split_front_matter(source), (Some("title: Notes\n"), "# Heading\n"));
+
+        // a thematic break later on is not front matter
+        let source = "# Heading\n---\n";
+        assert_eq!(split_front_matter(source), (None, source));
+    }
+
+    #[test]
+    fn test_parse_front_matter() {
+        let meta = parse_front_matter("---\ntitle: Notes\nweek: 3\n...\nBody\n").unwrap();
+        assert_eq!(meta["title"], "Notes");
+        assert_eq!(meta["week"], 3);
+
+        assert!(parse_front_matter("No front matter").unwrap().is_empty());
+    }
+}
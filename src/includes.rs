@@ -0,0 +1,286 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result, anyhow};
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+use crate::front_matter::split_front_matter;
+use crate::render::join_and_canonicalize;
+use crate::utils::err;
+
+/// How deeply markdown files may include each other
+const MAX_DEPTH: usize = 16;
+
+/// A document with its includes resolved
+#[derive(Debug, Default, Clone)]
+pub struct Included {
+    pub source: String,
+    /// Every file included, directly or by an included file
+    pub files: Vec<PathBuf>,
+}
+
+/// Lines of an included file, counted from 1. Either end may be left open
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct LineRange {
+    start: Option<usize>,
+    end: Option<usize>,
+}
+
+/// Replaces the includes of a document with the files they name, relative to the file they are
+/// in:
+///
+/// - `{{#include notes.md}}` includes a file. Markdown files have their own includes resolved
+///   and their front matter left out
+/// - `{{#include src/main.rs:10:40}}` includes lines 10 to 40. `:10` is only line 10, `:10:`
+///   runs to the end of the file and `::40` starts at its beginning
+/// - a code block opened with ```` ```rust file=src/main.rs lines=10-40 ```` gets the lines of
+///   the file as its content
+///
+/// Includes written in code blocks or inline code are left as they are, so notes can show them.
+pub fn expand(md_path: &Path, source: &str) -> Result<Included> {
+    let md_path = md_path.canonicalize().unwrap_or_else(|_| md_path.to_path_buf());
+    let mut included = Included::default();
+    let mut stack = vec![md_path.clone()];
+    included.source = expand_file(&md_path, source, 1, &mut stack, &mut included.files)?;
+    Ok(included)
+}
+
+/// Resolves the includes of `source`, which starts at `first_line` of the file at `path`
+fn expand_file(
+    path: &Path,
+    source: &str,
+    first_line: usize,
+    stack: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> Result<String> {
+    let mut out = String::with_capacity(source.len());
+    let code = code_ranges(source);
+    let in_code = |offset: usize| code.iter().any(|range| range.contains(&offset));
+    let mut lines = source.split_inclusive('\n').enumerate();
+    let mut line_start = 0;
+
+    while let Some((i, line)) = lines.next() {
+        let location = format!("{}:{}", path.to_string_lossy(), first_line + i);
+        let offset = line_start;
+        line_start += line.len();
+
+        // the opening line of a block is in its range too, so only blocks started before count
+        let in_block = code.iter().any(|range| range.start < offset && offset < range.end);
+        if let Some(fence) = CodeInclude::parse(line).filter(|_| !in_block) {
+            // the content of the block is replaced, up to its closing fence
+            for (_, inner) in lines.by_ref() {
+                line_start += inner.len();
+                let inner = inner.trim();
+                let is_fence = inner.trim_start_matches(fence.char).is_empty();
+                if is_fence && inner.len() >= fence.marker.len() {
+                    break;
+                }
+            }
+
+            let text = read_include(path, &fence.file, &location, files)?.1;
+            let text = select_lines(&text, fence.lines);
+            out.push_str(&fence.open);
+            out.push_str(&text);
+            if !text.is_empty() && !text.ends_with('\n') {
+                out.push('\n');
+            }
+            out.push_str(&fence.marker);
+            out.push('\n');
+            continue;
+        }
+
+        let mut rest = line;
+        while let Some(start) = rest.find("{{#include ") {
+            let Some(end) = rest[start..].find("}}").map(|end| start + end) else { break };
+            if in_code(offset + line.len() - rest.len() + start) {
+                out.push_str(&rest[..end + 2]);
+                rest = &rest[end + 2..];
+                continue;
+            }
+            out.push_str(&rest[..start]);
+
+            let (file, lines) = parse_spec(rest[start + "{{#include ".len()..end].trim());
+            let (target, text) = read_include(path, file, &location, files)?;
+            let mut text = select_lines(&text, lines);
+
+            if target.extension().is_some_and(|ext| ext == "md") {
+                if stack.contains(&target) {
+                    let cycle: Vec<_> = stack
+                        .iter()
+                        .chain([&target])
+                        .map(|file| file.to_string_lossy().into_owned())
+                        .collect();
+                    return Err(anyhow!(err(format!(
+                        "{}: Files include each other: {}",
+                        location,
+                        cycle.join(" -> ")
+                    ).as_str())));
+                }
+                if stack.len() > MAX_DEPTH {
+                    return Err(anyhow!(err(format!(
+                        "{}: Includes are nested more than {} deep",
+                        location, MAX_DEPTH
+                    ).as_str())));
+                }
+
+                stack.push(target.clone());
+                let body = split_front_matter(&text).1;
+                let first_line = text[..text.len() - body.len()].matches('\n').count() + 1;
+                text = expand_file(&target, body, first_line, stack, files)?;
+                stack.pop();
+            }
+
+            out.push_str(text.strip_suffix('\n').unwrap_or(&text));
+            rest = &rest[end + 2..];
+        }
+        out.push_str(rest);
+    }
+
+    Ok(out)
+}
+
+/// The byte ranges of the code blocks and inline code of `source`
+fn code_ranges(source: &str) -> Vec<Range<usize>> {
+    Parser::new_ext(source, Options::all())
+        .into_offset_iter()
+        .filter(|(event, _)| matches!(event, Event::Code(_) | Event::Start(Tag::CodeBlock(_))))
+        .map(|(_, range)| range)
+        .collect()
+}
+
+/// Reads a file named by an include in `path`, returning its canonical path and content
+fn read_include(
+    path: &Path,
+    file: &str,
+    location: &str,
+    files: &mut Vec<PathBuf>,
+) -> Result<(PathBuf, String)> {
+    let target = join_and_canonicalize(file, path.to_path_buf())?;
+    let text = fs::read_to_string(&target).with_context(|| {
+        err(format!("{}: Could not include {:?}", location, target).as_str())
+    })?;
+
+    if !files.contains(&target) {
+        files.push(target.clone());
+    }
+    Ok((target, text))
+}
+
+/// Splits `src/main.rs:10:40` into the file and its lines
+fn parse_spec(spec: &str) -> (&str, LineRange) {
+    let number = |part: &str| part.parse::<usize>().ok();
+    let is_range = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+
+    let parts: Vec<&str> = spec.rsplitn(3, ':').collect();
+    match parts.as_slice() {
+        [end, start, file] if is_range(start) && is_range(end) => {
+            (file, LineRange { start: number(start), end: number(end) })
+        }
+        [line, _, ..] if !line.is_empty() && is_range(line) => {
+            let file = &spec[..spec.len() - line.len() - 1];
+            (file, LineRange { start: number(line), end: number(line) })
+        }
+        _ => (spec, LineRange::default()),
+    }
+}
+
+/// The given lines of `text`, keeping their line endings
+fn select_lines(text: &str, lines: LineRange) -> String {
+    let start = lines.start.unwrap_or(1).max(1);
+    text.split_inclusive('\n')
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(n, _)| *n >= start && lines.end.is_none_or(|end| *n <= end))
+        .map(|(_, line)| line)
+        .collect()
+}
+
+/// A code block opened with `file=` in its info string
+#[derive(Debug, PartialEq)]
+struct CodeInclude {
+    /// The opening line without `file=` and `lines=`
+    open: String,
+    /// The backticks or tildes opening the block
+    marker: String,
+    char: char,
+    file: String,
+    lines: LineRange,
+}
+
+impl CodeInclude {
+    fn parse(line: &str) -> Option<CodeInclude> {
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+        let char = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+        let marker: String = trimmed.chars().take_while(|c| *c == char).collect();
+        if marker.len() < 3 || indent.len() > 3 {
+            return None;
+        }
+
+        let mut file = None;
+        let mut lines = LineRange::default();
+        let mut info = Vec::new();
+        for word in trimmed[marker.len()..].split_whitespace() {
+            if let Some(value) = word.strip_prefix("file=") {
+                file = Some(value.trim_matches('"').to_string());
+            } else if let Some(value) = word.strip_prefix("lines=") {
+                let (start, end) = value.split_once('-').unwrap_or((value, value));
+                lines = LineRange { start: start.parse().ok(), end: end.parse().ok() };
+            } else {
+                info.push(word);
+            }
+        }
+
+        Some(CodeInclude {
+            open: format!("{}{}{}\n", indent, marker, info.join(" ")),
+            marker,
+            char,
+            file: file?,
+            lines,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec() {
+        let range = |start, end| LineRange { start, end };
+        assert_eq!(parse_spec("notes.md"), ("notes.md", range(None, None)));
+        assert_eq!(parse_spec("src/main.rs:10:40"), ("src/main.rs", range(Some(10), Some(40))));
+        assert_eq!(parse_spec("src/main.rs:10"), ("src/main.rs", range(Some(10), Some(10))));
+        assert_eq!(parse_spec("src/main.rs:10:"), ("src/main.rs", range(Some(10), None)));
+        assert_eq!(parse_spec("src/main.rs::40"), ("src/main.rs", range(None, Some(40))));
+        assert_eq!(select_lines("a\nb\nc\nd\n", range(Some(2), Some(3))), "b\nc\n");
+    }
+
+    #[test]
+    fn test_expand() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.md");
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/lib.rs"), "fn a() {}\nfn b() {}\nfn c() {}\n").unwrap();
+        let part = "---\ntitle: Part\n---\nShared {{#include src/lib.rs:2}}\n";
+        fs::write(dir.path().join("part.md"), part).unwrap();
+
+        let source = "# Notes\n\n{{#include part.md}}\n\n```rust file=src/lib.rs lines=2-3\n```\n";
+        let included = expand(&main, source).unwrap();
+        assert_eq!(
+            included.source,
+            "# Notes\n\nShared fn b() {}\n\n```rust\nfn b() {}\nfn c() {}\n```\n"
+        );
+        assert_eq!(included.files.len(), 2);
+
+        // includes shown in code are kept
+        let source = "Write `{{#include part.md}}` for:\n\n````markdown\n{{#include part.md}}\n\
+                      ```rust file=src/lib.rs\n```\n````\n";
+        assert_eq!(expand(&main, source).unwrap().source, source);
+
+        // a file including itself is an error rather than a stack overflow
+        fs::write(dir.path().join("loop.md"), "{{#include loop.md}}\n").unwrap();
+        let error = expand(&main, "{{#include loop.md}}\n").unwrap_err().to_string();
+        assert!(error.contains("Files include each other"));
+    }
+}
@@ -0,0 +1,446 @@
+use serde_json::{Map, Number, Value};
+
+/// An error found while parsing a JavaScript object literal, with its 1-based position
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses the first object literal assigned in a JavaScript file, such as
+/// `window.katexMacros = { ... };`.
+///
+/// This is not a JavaScript parser. It accepts the subset of JavaScript used to write data:
+/// objects, arrays, strings in single, double or back quotes (without interpolation), numbers,
+/// `true`, `false` and `null`, with comments, trailing commas and unquoted keys. Anything before
+/// the first `{` (e.g. the assignment) and after the closing `}` (e.g. a `;`) is ignored.
+///
+/// A key defined again in the same object keeps its last value, as in JavaScript. Where that
+/// happens is returned along with the value.
+pub fn parse_object_literal(source: &str) -> Result<(Value, Vec<ParseError>), ParseError> {
+    let mut parser = Parser::new(source);
+    let value = parser.parse_file()?;
+    Ok((value, parser.duplicates))
+}
+
+/// Where entries can be added to the object of a file read by [`parse_object_literal`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsertionPoint {
+    /// Byte offset just after the last entry and its comma, or after the `{` if it is empty
+    pub offset: usize,
+    /// Whether a `,` has to be written before new entries
+    pub needs_comma: bool,
+}
+
+/// Finds where new entries can be added to the object in `source`, keeping its comments and
+/// formatting
+pub fn insertion_point(source: &str) -> Result<InsertionPoint, ParseError> {
+    let mut parser = Parser::new(source);
+    parser.parse_file()?;
+
+    let (pos, needs_comma) = parser.last_entry.unwrap_or((parser.object_start + 1, false));
+    Ok(InsertionPoint {
+        offset: source
+            .char_indices()
+            .nth(pos)
+            .map(|(offset, _)| offset)
+            .unwrap_or(source.len()),
+        needs_comma,
+    })
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// Nesting depth of the object or array being parsed
+    depth: usize,
+    /// Position of the `{` of the outermost object
+    object_start: usize,
+    /// Position after the last entry of the outermost object, and whether it lacks a comma
+    last_entry: Option<(usize, bool)>,
+    /// Keys defined again in the same object
+    duplicates: Vec<ParseError>,
+}
+
+impl Parser {
+    fn new(source: &str) -> Parser {
+        Parser {
+            chars: source.chars().collect(),
+            pos: 0,
+            depth: 0,
+            object_start: 0,
+            last_entry: None,
+            duplicates: Vec::new(),
+        }
+    }
+
+    fn parse_file(&mut self) -> Result<Value, ParseError> {
+        self.skip_to_object()?;
+        self.object_start = self.pos;
+        let value = self.parse_value()?;
+
+        self.skip_trivia()?;
+        if self.peek() == Some(';') {
+            self.pos += 1;
+        }
+        self.skip_trivia()?;
+        if self.peek().is_some() {
+            return Err(self.error("unexpected content after the object"));
+        }
+
+        Ok(value)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        self.error_at(self.pos, message)
+    }
+
+    fn error_at(&self, pos: usize, message: impl Into<String>) -> ParseError {
+        let before = &self.chars[..pos.min(self.chars.len())];
+        let line = before.iter().filter(|c| **c == '\n').count() + 1;
+        let column = before.iter().rev().take_while(|c| **c != '\n').count() + 1;
+        ParseError {
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+
+    /// Skips whitespace and comments
+    fn skip_trivia(&mut self) -> Result<(), ParseError> {
+        loop {
+            match (self.peek(), self.peek_at(1)) {
+                (Some(c), _) if c.is_whitespace() => self.pos += 1,
+                (Some('/'), Some('/')) => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.pos += 1;
+                    }
+                }
+                (Some('/'), Some('*')) => {
+                    let start = self.pos;
+                    self.pos += 2;
+                    loop {
+                        match (self.peek(), self.peek_at(1)) {
+                            (Some('*'), Some('/')) => {
+                                self.pos += 2;
+                                break;
+                            }
+                            (Some(_), _) => self.pos += 1,
+                            (None, _) => return Err(self.error_at(start, "unterminated comment")),
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Moves to the first `{` outside of a comment, skipping e.g. `window.katexMacros =`
+    fn skip_to_object(&mut self) -> Result<(), ParseError> {
+        loop {
+            self.skip_trivia()?;
+            match self.peek() {
+                Some('{') => return Ok(()),
+                Some(_) => self.pos += 1,
+                None => return Err(self.error("expected an object literal `{ ... }`")),
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char, context: &str) -> Result<(), ParseError> {
+        self.skip_trivia()?;
+        if self.peek() == Some(expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("expected `{}` {}", expected, context)))
+        }
+    }
+
+    fn unexpected(&self, message: &str) -> ParseError {
+        match self.peek() {
+            Some(c) => self.error(format!("{}, found `{}`", message, c)),
+            None => self.error(format!("{}, found end of file", message)),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, ParseError> {
+        self.skip_trivia()?;
+        match self.peek() {
+            Some('{') => self.parse_object(),
+            Some('[') => self.parse_array(),
+            Some(quote @ ('"' | '\'' | '`')) => self.parse_string(quote).map(Value::String),
+            Some(c) if c == '-' || c == '+' || c == '.' || c.is_ascii_digit() => self.parse_number(),
+            Some(c) if is_identifier_start(c) => {
+                let start = self.pos;
+                match self.parse_identifier().as_str() {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    "null" | "undefined" => Ok(Value::Null),
+                    other => Err(self.error_at(
+                        start,
+                        format!("unexpected identifier `{}`, expected a value", other),
+                    )),
+                }
+            }
+            _ => Err(self.unexpected("expected a value")),
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Value, ParseError> {
+        let start = self.pos;
+        self.pos += 1; // {
+        let mut map = Map::new();
+
+        loop {
+            self.skip_trivia()?;
+            match self.peek() {
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(map));
+                }
+                None => return Err(self.error_at(start, "unclosed `{`")),
+                _ => {}
+            }
+
+            let key_pos = self.pos;
+            let key = match self.peek() {
+                Some(quote @ ('"' | '\'' | '`')) => self.parse_string(quote)?,
+                Some(c) if is_identifier_start(c) => self.parse_identifier(),
+                Some(c) if c.is_ascii_digit() => self.parse_identifier(),
+                _ => return Err(self.unexpected("expected a key")),
+            };
+
+            self.expect(':', "after key")?;
+            self.depth += 1;
+            let value = self.parse_value()?;
+            self.depth -= 1;
+
+            if map.insert(key.clone(), value).is_some() {
+                let duplicate = self.error_at(
+                    key_pos,
+                    format!("`{}` is defined again, the last definition is used", key),
+                );
+                self.duplicates.push(duplicate);
+            }
+
+            let value_end = self.pos;
+            self.skip_trivia()?;
+            match self.peek() {
+                Some(',') => {
+                    self.pos += 1;
+                    if self.depth == 0 {
+                        self.last_entry = Some((self.pos, false));
+                    }
+                }
+                Some('}') if self.depth == 0 => self.last_entry = Some((value_end, true)),
+                Some('}') => {}
+                _ => return Err(self.unexpected("expected `,` or `}` after value")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Value, ParseError> {
+        let start = self.pos;
+        self.pos += 1; // [
+        let mut items = Vec::new();
+
+        loop {
+            self.skip_trivia()?;
+            match self.peek() {
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                None => return Err(self.error_at(start, "unclosed `[`")),
+                _ => {}
+            }
+
+            self.depth += 1;
+            items.push(self.parse_value()?);
+            self.depth -= 1;
+
+            self.skip_trivia()?;
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {}
+                _ => return Err(self.unexpected("expected `,` or `]` after value")),
+            }
+        }
+    }
+
+    fn parse_string(&mut self, quote: char) -> Result<String, ParseError> {
+        let start = self.pos;
+        self.pos += 1; // opening quote
+        let mut out = String::new();
+
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(self.error_at(start, "unterminated string"));
+            };
+            self.pos += 1;
+
+            match c {
+                c if c == quote => return Ok(out),
+                '\n' if quote != '`' => {
+                    return Err(self.error_at(start, "unterminated string"));
+                }
+                '$' if quote == '`' && self.peek() == Some('{') => {
+                    return Err(self.error("template literal interpolation is not supported"));
+                }
+                '\\' => {
+                    let Some(escaped) = self.peek() else {
+                        return Err(self.error_at(start, "unterminated string"));
+                    };
+                    self.pos += 1;
+                    match escaped {
+                        'n' => out.push('\n'),
+                        't' => out.push('\t'),
+                        'r' => out.push('\r'),
+                        'b' => out.push('\u{8}'),
+                        'f' => out.push('\u{c}'),
+                        'v' => out.push('\u{b}'),
+                        '0' => out.push('\0'),
+                        'u' => out.push(self.parse_unicode_escape()?),
+                        // line continuation
+                        '\n' => {}
+                        other => out.push(other),
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn parse_unicode_escape(&mut self) -> Result<char, ParseError> {
+        let start = self.pos;
+        let digits: String = if self.peek() == Some('{') {
+            self.pos += 1;
+            let digits: String = self.chars[self.pos..]
+                .iter()
+                .take_while(|c| **c != '}')
+                .collect();
+            self.pos += digits.chars().count() + 1;
+            digits
+        } else {
+            let digits: String = self.chars[self.pos..].iter().take(4).collect();
+            self.pos += digits.chars().count();
+            digits
+        };
+
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error_at(start, "invalid unicode escape"))
+    }
+
+    fn parse_number(&mut self) -> Result<Value, ParseError> {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+' | '_'))
+        {
+            self.pos += 1;
+        }
+
+        let text: String = self.chars[start..self.pos].iter().filter(|c| **c != '_').collect();
+        let text = text.strip_prefix('+').unwrap_or(&text);
+
+        if let Ok(int) = text.parse::<i64>() {
+            return Ok(Value::Number(int.into()));
+        }
+        text.parse::<f64>()
+            .ok()
+            .and_then(Number::from_f64)
+            .map(Value::Number)
+            .ok_or_else(|| self.error_at(start, format!("invalid number `{}`", text)))
+    }
+
+    fn parse_identifier(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '$') {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == '$'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tolerant_syntax() {
+        let source = r#"
+            // Macros shared by the preview and exports
+            window.katexMacros = {
+                "\\R": "\\mathbb{R}", /* reals */
+                '\\set': ['\\{ #1 \\}', 1],
+                "\\semi": "a;b",
+                plain: `\\text{x}`,
+            };
+        "#;
+
+        let (value, _) = parse_object_literal(source).unwrap();
+        assert_eq!(value["\\R"], "\\mathbb{R}");
+        assert_eq!(value["\\set"][0], "\\{ #1 \\}");
+        assert_eq!(value["\\set"][1], 1);
+        assert_eq!(value["\\semi"], "a;b");
+        assert_eq!(value["plain"], "\\text{x}");
+    }
+
+    #[test]
+    fn test_error_position() {
+        let source = "window.katexMacros = {\n    \"\\\\R\" \"\\\\mathbb{R}\"\n};";
+        let e = parse_object_literal(source).unwrap_err();
+        assert_eq!((e.line, e.column), (2, 11));
+        assert!(e.message.contains("expected `:`"));
+
+        let e = parse_object_literal("{\n  \"\\\\R\": \"\\\\mathbb{R}\n}").unwrap_err();
+        assert_eq!((e.line, e.message.as_str()), (2, "unterminated string"));
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let source = "{\n  \"\\\\R\": \"R\",\n  \"\\\\R\": \"\\\\mathbb{R}\"\n}";
+        let (value, duplicates) = parse_object_literal(source).unwrap();
+        assert_eq!(value["\\R"], "\\mathbb{R}");
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!((duplicates[0].line, duplicates[0].column), (3, 3));
+    }
+
+    #[test]
+    fn test_insertion_point() {
+        let source = "window.katexMacros = {\n    \"\\\\R\": \"\\\\mathbb{R}\" // reals\n};\n";
+        let point = insertion_point(source).unwrap();
+        assert_eq!(&source[..point.offset], "window.katexMacros = {\n    \"\\\\R\": \"\\\\mathbb{R}\"");
+        assert!(point.needs_comma);
+
+        let source = "{ \"a\": [1, 2], }";
+        assert_eq!(insertion_point(source).unwrap(), InsertionPoint { offset: 14, needs_comma: false });
+
+        let source = "window.katexMacros = {};";
+        assert_eq!(insertion_point(source).unwrap(), InsertionPoint { offset: 22, needs_comma: false });
+    }
+}
@@ -0,0 +1,258 @@
+/// Commands defined by the LaTeX kernel, amsmath and amssymb, which pandoc's LaTeX template
+/// always loads. Defining any of these with `\newcommand` fails.
+static LATEX_COMMANDS: &[&str] = &[
+    // greek letters
+    "alpha", "beta", "gamma", "delta", "epsilon", "varepsilon", "zeta", "eta", "theta",
+    "vartheta", "iota", "kappa", "varkappa", "lambda", "mu", "nu", "xi", "pi", "varpi", "rho",
+    "varrho", "sigma", "varsigma", "tau", "upsilon", "phi", "varphi", "chi", "psi", "omega",
+    "digamma", "Gamma", "Delta", "Theta", "Lambda", "Xi", "Pi", "Sigma", "Upsilon", "Phi", "Psi",
+    "Omega", "varGamma", "varDelta", "varTheta", "varLambda", "varXi", "varPi", "varSigma",
+    "varUpsilon", "varPhi", "varPsi", "varOmega",
+    // text symbols and accents, most of them a single letter
+    "AA", "aa", "AE", "ae", "OE", "oe", "O", "o", "L", "l", "i", "j", "ss", "H", "b", "c", "d",
+    "k", "r", "t", "u", "v", "P", "S", "dag", "ddag", "copyright", "pounds", "dots", "ldots",
+    "textbackslash", "LaTeX", "TeX", "today",
+    // log-like functions
+    "arccos", "arcsin", "arctan", "arg", "cos", "cosh", "cot", "coth", "csc", "deg", "det",
+    "dim", "exp", "gcd", "hom", "inf", "injlim", "ker", "lg", "lim", "liminf", "limsup", "ln",
+    "log", "max", "min", "Pr", "projlim", "sec", "sin", "sinh", "sup", "tan", "tanh", "varinjlim",
+    "varliminf", "varlimsup", "varprojlim", "bmod", "pmod", "mod", "pod",
+    // big operators
+    "sum", "prod", "coprod", "int", "iint", "iiint", "iiiint", "idotsint", "oint", "bigcup",
+    "bigcap", "bigsqcup", "bigvee", "bigwedge", "bigodot", "bigoplus", "bigotimes", "biguplus",
+    // binary operators and relations
+    "pm", "mp", "times", "div", "cdot", "ast", "star", "circ", "bullet", "oplus", "ominus",
+    "otimes", "oslash", "odot", "cup", "cap", "sqcup", "sqcap", "vee", "wedge", "setminus",
+    "smallsetminus", "wr", "amalg", "uplus", "dagger", "ddagger", "lhd", "rhd", "unlhd", "unrhd",
+    "le", "leq", "ge", "geq", "ne", "neq", "leqslant", "geqslant", "lesssim", "gtrsim", "ll",
+    "gg", "lll", "ggg", "prec", "succ", "preceq", "succeq", "sim", "simeq", "approx", "cong",
+    "equiv", "doteq", "propto", "asymp", "subset", "supset", "subseteq", "supseteq", "subsetneq",
+    "supsetneq", "sqsubset", "sqsupset", "sqsubseteq", "sqsupseteq", "in", "ni", "notin", "owns",
+    "mid", "nmid", "parallel", "nparallel", "perp", "models", "vdash", "dashv", "smile", "frown",
+    "bowtie", "lessgtr", "gtrless", "triangleq", "coloneq", "nleq", "ngeq", "nsim", "ncong",
+    "nsubseteq", "nsupseteq",
+    // arrows
+    "to", "gets", "leftarrow", "rightarrow", "leftrightarrow", "Leftarrow", "Rightarrow",
+    "Leftrightarrow", "longleftarrow", "longrightarrow", "longleftrightarrow", "Longleftarrow",
+    "Longrightarrow", "Longleftrightarrow", "mapsto", "longmapsto", "hookleftarrow",
+    "hookrightarrow", "uparrow", "downarrow", "updownarrow", "Uparrow", "Downarrow",
+    "Updownarrow", "nearrow", "searrow", "swarrow", "nwarrow", "leadsto", "implies", "impliedby",
+    "iff", "xrightarrow", "xleftarrow", "rightleftharpoons", "twoheadrightarrow",
+    "rightharpoonup", "leftharpoonup",
+    // miscellaneous symbols
+    "infty", "partial", "nabla", "forall", "exists", "nexists", "neg", "lnot", "emptyset",
+    "varnothing", "aleph", "beth", "hbar", "hslash", "ell", "wp", "Re", "Im", "angle",
+    "triangle", "square", "blacksquare", "Box", "Diamond", "checkmark", "therefore", "because",
+    "top", "bot", "prime", "complement", "surd", "flat", "natural", "sharp", "clubsuit",
+    "diamondsuit", "heartsuit", "spadesuit", "cdots", "vdots", "ddots", "dotsb", "dotsc",
+    "dotsi", "dotsm", "dotso", "colon", "imath", "jmath", "backslash",
+    // delimiters
+    "langle", "rangle", "lceil", "rceil", "lfloor", "rfloor", "lbrace", "rbrace", "lbrack",
+    "rbrack", "vert", "Vert", "lvert", "rvert", "lVert", "rVert", "left", "right", "middle",
+    "big", "Big", "bigg", "Bigg", "bigl", "bigr", "Bigl", "Bigr", "biggl", "biggr", "Biggl",
+    "Biggr",
+    // constructions and fonts
+    "frac", "dfrac", "tfrac", "cfrac", "genfrac", "binom", "dbinom", "tbinom", "sqrt", "text",
+    "mathrm", "mathbf", "mathit", "mathsf", "mathtt", "mathcal", "mathbb", "mathfrak",
+    "mathnormal", "boldsymbol", "pmb", "operatorname", "overline", "underline", "overbrace",
+    "underbrace", "overrightarrow", "overleftarrow", "hat", "widehat", "tilde", "widetilde",
+    "bar", "vec", "dot", "ddot", "dddot", "acute", "grave", "check", "breve", "mathring",
+    "stackrel", "overset", "underset", "substack", "quad", "qquad", "enspace", "phantom",
+    "hphantom", "vphantom", "displaystyle", "textstyle", "scriptstyle", "limits", "nolimits",
+    "label", "ref", "eqref", "tag", "notag", "nonumber", "intertext", "emph", "textbf", "textit",
+    "texttt", "textrm", "textsf", "textsc", "textup", "textnormal", "underbar", "not", "choose",
+    "over", "atop", "cases", "matrix", "pmatrix", "bmatrix", "begin", "end", "item", "par",
+    "section", "subsection", "paragraph", "caption", "cite", "footnote", "url", "href",
+    "newline", "linebreak", "hline", "hfill", "vfill", "space", "relax", "empty", "hspace",
+    "vspace", "mbox", "hbox", "vbox", "color", "textcolor",
+];
+
+/// Commands defined by packages that are often, but not always, loaded in the header, e.g.
+/// `mathtools`, `physics`, `amsthm` and `bm`. They may or may not already exist.
+static PACKAGE_COMMANDS: &[&str] = &[
+    // mathtools
+    "abs", "norm", "coloneqq", "eqqcolon", "Coloneqq", "mathclap", "mathllap", "mathrlap",
+    "shortintertext", "DeclarePairedDelimiter",
+    // physics
+    "bra", "ket", "braket", "ketbra", "dd", "dv", "pdv", "qty", "order", "grad", "curl",
+    "laplacian", "cross", "vdot", "vb", "va", "vu", "tr", "Tr", "rank", "erf", "Res", "pv",
+    "eval", "comm", "acomm", "expval", "mel", "mqty", "pmqty", "bmqty", "dmat", "imat",
+    // amsthm
+    "qed", "qedhere", "qedsymbol", "proofname",
+    // bm, mathrsfs, siunitx and friends
+    "bm", "mathscr", "si", "SI", "num", "ang", "unit", "degree", "celsius", "lightning",
+];
+
+/// Whether `name` (with or without the leading backslash) is always defined when exporting
+pub fn is_latex_command(name: &str) -> bool {
+    LATEX_COMMANDS.contains(&name.trim_start_matches('\\'))
+}
+
+/// Whether `name` (with or without the leading backslash) is defined by a common package
+pub fn is_package_command(name: &str) -> bool {
+    PACKAGE_COMMANDS.contains(&name.trim_start_matches('\\'))
+}
+
+/// Control words supported by KaTeX in math mode, from its list of supported functions
+static KATEX_COMMANDS: &[&str] = &[
+    // accents
+    "acute", "bar", "breve", "check", "dot", "ddot", "dddot", "ddddot", "grave", "hat", "widehat",
+    "mathring", "tilde", "widetilde", "utilde", "vec", "widecheck", "overleftarrow",
+    "underleftarrow", "overleftharpoon", "overleftrightarrow", "underleftrightarrow", "overline",
+    "underline", "overrightarrow", "underrightarrow", "overrightharpoon", "overgroup",
+    "undergroup", "overbrace", "underbrace", "overlinesegment", "underlinesegment",
+    "Overrightarrow", "underbar",
+    // delimiters and sizes
+    "lparen", "rparen", "lceil", "rceil", "lfloor", "rfloor", "lmoustache", "rmoustache",
+    "lgroup", "rgroup", "ulcorner", "urcorner", "llcorner", "lrcorner", "lbrack", "rbrack",
+    "langle", "rangle", "lang", "rang", "lt", "gt", "vert", "Vert", "lvert", "rvert", "lVert",
+    "rVert", "lbrace", "rbrace", "backslash", "llbracket", "rrbracket", "lBrace", "rBrace",
+    "left", "right", "middle", "big", "Big", "bigg", "Bigg", "bigl", "bigm", "bigr", "Bigl",
+    "Bigm", "Bigr", "biggl", "biggm", "biggr", "Biggl", "Biggm", "Biggr", "begin", "end",
+    // greek and hebrew letters
+    "alpha", "beta", "gamma", "delta", "epsilon", "varepsilon", "zeta", "eta", "theta",
+    "vartheta", "thetasym", "iota", "kappa", "varkappa", "lambda", "mu", "nu", "xi", "omicron",
+    "pi", "varpi", "rho", "varrho", "sigma", "varsigma", "tau", "upsilon", "phi", "varphi", "chi",
+    "psi", "omega", "digamma", "Alpha", "Beta", "Gamma", "Delta", "Epsilon", "Zeta", "Eta",
+    "Theta", "Iota", "Kappa", "Lambda", "Mu", "Nu", "Xi", "Omicron", "Pi", "Rho", "Sigma", "Tau",
+    "Upsilon", "Phi", "Chi", "Psi", "Omega", "varGamma", "varDelta", "varTheta", "varLambda",
+    "varXi", "varPi", "varSigma", "varUpsilon", "varPhi", "varPsi", "varOmega", "aleph", "alef",
+    "alefsym", "beth", "gimel", "daleth",
+    // other letters
+    "imath", "jmath", "eth", "hbar", "hslash", "ell", "nabla", "wp", "weierp", "Game", "Finv",
+    "Bbbk", "Im", "Re", "image", "real", "partial", "cnums", "Complex", "natnums", "N", "R",
+    "Reals", "reals", "Z", "mho", "i", "j", "aa", "AA", "ae", "AE", "oe", "OE", "o", "O", "ss",
+    // layout
+    "cancel", "bcancel", "xcancel", "sout", "phase", "boxed", "fbox", "colorbox", "fcolorbox",
+    "tag", "notag", "nonumber", "label", "ref", "eqref", "overset", "underset", "stackrel",
+    "atop", "above", "choose", "brace", "brack", "binom", "dbinom", "tbinom", "frac", "dfrac",
+    "tfrac", "cfrac", "genfrac", "over", "sqrt", "substack", "hline", "hdashline", "raisebox",
+    "rule", "vcenter", "hbox", "mbox", "llap", "rlap", "clap", "mathllap", "mathrlap", "mathclap",
+    "smash", "phantom", "hphantom", "vphantom", "mathstrut", "newline", "cr", "nobreak",
+    "allowbreak", "relax",
+    // spacing
+    "quad", "qquad", "enspace", "enskip", "thinspace", "medspace", "thickspace", "negthinspace",
+    "negmedspace", "negthickspace", "space", "nobreakspace", "hspace", "hskip", "mskip", "kern",
+    "mkern", "mspace",
+    // logic and sets
+    "forall", "exists", "nexists", "complement", "subset", "supset", "mid", "nmid", "land", "lor",
+    "lnot", "neg", "in", "notin", "ni", "isin", "notni", "emptyset", "empty", "varnothing",
+    "setminus", "smallsetminus", "therefore", "because", "implies", "impliedby", "iff",
+    // macros
+    "def", "gdef", "edef", "xdef", "let", "futurelet", "global", "newcommand", "renewcommand",
+    "providecommand", "long", "char", "mathchoice", "TextOrMath", "expandafter", "noexpand",
+    // big operators
+    "sum", "prod", "coprod", "int", "iint", "iiint", "oint", "oiint", "oiiint", "intop",
+    "smallint", "bigotimes", "bigoplus", "bigodot", "biguplus", "bigvee", "bigwedge", "bigsqcup",
+    "bigcap", "bigcup",
+    // binary operators
+    "amalg", "And", "ast", "barwedge", "bigcirc", "bmod", "boxdot", "boxminus", "boxplus",
+    "boxtimes", "bullet", "Cap", "cap", "cdot", "cdotp", "centerdot", "circ", "circledast",
+    "circledcirc", "circleddash", "Cup", "cup", "curlyvee", "curlywedge", "div", "divideontimes",
+    "dotplus", "doublebarwedge", "doublecap", "doublecup", "gtrdot", "intercal", "leftthreetimes",
+    "ldotp", "lessdot", "lhd", "ltimes", "mod", "mp", "odot", "ominus", "oplus", "oslash",
+    "otimes", "pm", "pmod", "pod", "plusmn", "rhd", "rightthreetimes", "rtimes", "sqcap",
+    "sqcup", "times", "unlhd", "unrhd", "uplus", "vee", "veebar", "wedge", "wr",
+    // functions
+    "arcsin", "arccos", "arctan", "arctg", "arcctg", "arg", "ch", "cos", "cosec", "cosh", "cot",
+    "cotg", "coth", "csc", "ctg", "cth", "deg", "dim", "exp", "hom", "ker", "lg", "ln", "log",
+    "sec", "sin", "sinh", "sh", "tan", "tanh", "tg", "th", "operatorname",
+    "operatornamewithlimits", "det", "gcd", "inf", "lim", "liminf", "limsup", "max", "min",
+    "plim", "Pr", "sup", "injlim", "projlim", "varinjlim", "varliminf", "varlimsup",
+    "varprojlim",
+    // relations
+    "doteqdot", "approx", "approxeq", "asymp", "backepsilon", "backsim", "backsimeq", "between",
+    "bowtie", "bumpeq", "Bumpeq", "circeq", "colonapprox", "Colonapprox", "coloneq", "Coloneq",
+    "coloneqq", "Coloneqq", "colonsim", "Colonsim", "cong", "curlyeqprec", "curlyeqsucc",
+    "dashv", "dblcolon", "doteq", "Doteq", "eqcirc", "eqcolon", "Eqcolon", "eqqcolon", "Eqqcolon",
+    "eqsim", "eqslantgtr", "eqslantless", "equiv", "fallingdotseq", "frown", "ge", "geq", "geqq",
+    "geqslant", "gg", "ggg", "gggtr", "gtrapprox", "gtreqless", "gtreqqless", "gtrless",
+    "gtrsim", "imageof", "Join", "le", "leq", "leqq", "leqslant", "lessapprox", "lesseqgtr",
+    "lesseqqgtr", "lessgtr", "lesssim", "ll", "lll", "llless", "models", "multimap", "origof",
+    "owns", "parallel", "perp", "pitchfork", "prec", "precapprox", "preccurlyeq", "preceq",
+    "precsim", "propto", "risingdotseq", "shortmid", "shortparallel", "sim", "simeq",
+    "smallfrown", "smallsmile", "smile", "sqsubset", "sqsubseteq", "sqsupset", "sqsupseteq",
+    "Subset", "subseteq", "subseteqq", "succ", "succapprox", "succcurlyeq", "succeq", "succsim",
+    "Supset", "supseteq", "supseteqq", "thickapprox", "thicksim", "trianglelefteq", "triangleq",
+    "trianglerighteq", "varpropto", "vartriangle", "vartriangleleft", "vartriangleright",
+    "vcentcolon", "vdash", "vDash", "Vdash", "Vvdash",
+    // negated relations
+    "gnapprox", "gneq", "gneqq", "gnsim", "gvertneqq", "lnapprox", "lneq", "lneqq", "lnsim",
+    "lvertneqq", "ncong", "ne", "neq", "ngeq", "ngeqq", "ngeqslant", "ngtr", "nleq", "nleqq",
+    "nleqslant", "nless", "nparallel", "nprec", "npreceq", "nshortmid", "nshortparallel",
+    "nsim", "nsubseteq", "nsubseteqq", "nsucc", "nsucceq", "nsupseteq", "nsupseteqq",
+    "ntriangleleft", "ntrianglelefteq", "ntriangleright", "ntrianglerighteq", "nvdash",
+    "nvDash", "nVdash", "nVDash", "precnapprox", "precneqq", "precnsim", "subsetneq",
+    "subsetneqq", "succnapprox", "succneqq", "succnsim", "supsetneq", "supsetneqq",
+    "varsubsetneq", "varsubsetneqq", "varsupsetneq", "varsupsetneqq", "not",
+    // arrows
+    "circlearrowleft", "circlearrowright", "curvearrowleft", "curvearrowright", "Darr", "dArr",
+    "darr", "dashleftarrow", "dashrightarrow", "downarrow", "Downarrow", "downdownarrows",
+    "downharpoonleft", "downharpoonright", "gets", "Harr", "hArr", "harr", "hookleftarrow",
+    "hookrightarrow", "Larr", "lArr", "larr", "leadsto", "leftarrow", "Leftarrow",
+    "leftarrowtail", "leftharpoondown", "leftharpoonup", "leftleftarrows", "leftrightarrow",
+    "Leftrightarrow", "leftrightarrows", "leftrightharpoons", "leftrightsquigarrow", "Lleftarrow",
+    "longleftarrow", "Longleftarrow", "longleftrightarrow", "Longleftrightarrow", "longmapsto",
+    "longrightarrow", "Longrightarrow", "looparrowleft", "looparrowright", "Lrarr", "lrArr",
+    "lrarr", "Lsh", "mapsto", "nearrow", "nleftarrow", "nLeftarrow", "nleftrightarrow",
+    "nLeftrightarrow", "nrightarrow", "nRightarrow", "nwarrow", "Rarr", "rArr", "rarr",
+    "restriction", "rightarrow", "Rightarrow", "rightarrowtail", "rightharpoondown",
+    "rightharpoonup", "rightleftarrows", "rightleftharpoons", "rightrightarrows",
+    "rightsquigarrow", "Rrightarrow", "Rsh", "searrow", "swarrow", "to", "twoheadleftarrow",
+    "twoheadrightarrow", "Uarr", "uArr", "uarr", "uparrow", "Uparrow", "updownarrow",
+    "Updownarrow", "upharpoonleft", "upharpoonright", "upuparrows", "xleftarrow", "xrightarrow",
+    "xLeftarrow", "xRightarrow", "xleftrightarrow", "xLeftrightarrow", "xhookleftarrow",
+    "xhookrightarrow", "xtwoheadleftarrow", "xtwoheadrightarrow", "xleftharpoonup",
+    "xrightharpoonup", "xleftharpoondown", "xrightharpoondown", "xleftrightharpoons",
+    "xrightleftharpoons", "xtofrom", "xmapsto", "xlongequal",
+    // symbols and punctuation
+    "dots", "cdots", "ddots", "ldots", "vdots", "dotsb", "dotsc", "dotsi", "dotsm", "dotso",
+    "sdot", "mathellipsis", "infty", "checkmark", "dag", "dagger", "ddag", "ddagger", "Dagger",
+    "angle", "measuredangle", "sphericalangle", "top", "bot", "dollar", "pounds",
+    "mathsterling", "yen", "copyright", "circledR", "circledS", "clubsuit", "clubs",
+    "diamondsuit", "diamonds", "heartsuit", "hearts", "spadesuit", "spades", "maltese", "flat",
+    "natural", "sharp", "diamond", "Diamond", "lozenge", "blacklozenge", "star", "bigstar",
+    "triangle", "triangledown", "blacktriangle", "blacktriangledown", "blacktriangleleft",
+    "blacktriangleright", "square", "Box", "blacksquare", "bigtriangleup", "bigtriangledown",
+    "triangleleft", "triangleright", "surd", "degree", "diagdown", "diagup", "backprime", "prime",
+    "S", "P", "sect", "colon", "KaTeX", "LaTeX", "TeX",
+    // text mode
+    "text", "textrm", "textit", "textbf", "textsf", "texttt", "textnormal", "textup", "textmd",
+    "emph", "textcolor", "textasciitilde", "textbackslash", "textasciicircum", "textbar",
+    "textbardbl", "textbraceleft", "textbraceright", "textdagger", "textdaggerdbl",
+    "textdollar", "textellipsis", "textemdash", "textendash", "textgreater", "textless",
+    "textquotedblleft", "textquotedblright", "textquoteleft", "textquoteright",
+    "textregistered", "textsterling", "textunderscore", "textvisiblespace", "textcircled",
+    "textdegree", "H", "r", "u", "v",
+    // fonts and styles
+    "mathrm", "mathit", "mathbf", "boldsymbol", "bold", "bm", "pmb", "mathbb", "Bbb", "mathcal",
+    "cal", "mathscr", "mathfrak", "frak", "mathsf", "sf", "mathtt", "tt", "mathnormal", "rm",
+    "it", "bf", "mathbin", "mathclose", "mathinner", "mathop", "mathopen", "mathord",
+    "mathpunct", "mathrel", "displaystyle", "textstyle", "scriptstyle", "scriptscriptstyle",
+    "limits", "nolimits", "tiny", "scriptsize", "footnotesize", "small", "normalsize", "large",
+    "Large", "LARGE", "huge", "Huge", "color", "href", "url", "includegraphics", "htmlClass",
+    "htmlId", "htmlStyle", "htmlData", "verb", "ce", "pu",
+];
+
+/// Environments supported by KaTeX
+static KATEX_ENVIRONMENTS: &[&str] = &[
+    "matrix", "pmatrix", "bmatrix", "Bmatrix", "vmatrix", "Vmatrix", "smallmatrix", "matrix*",
+    "pmatrix*", "bmatrix*", "Bmatrix*", "vmatrix*", "Vmatrix*", "array", "darray", "subarray",
+    "cases", "dcases", "rcases", "drcases", "aligned", "alignedat", "gathered", "split",
+    "equation", "equation*", "align", "align*", "alignat", "alignat*", "gather", "gather*", "CD",
+];
+
+/// Whether KaTeX supports the control word `name` (with or without the leading backslash)
+pub fn is_katex_command(name: &str) -> bool {
+    KATEX_COMMANDS.contains(&name.trim_start_matches('\\'))
+}
+
+/// Whether KaTeX supports the environment `name`
+pub fn is_katex_environment(name: &str) -> bool {
+    KATEX_ENVIRONMENTS.contains(&name)
+}
+
+/// All control words supported by KaTeX, without the leading backslash
+pub fn katex_commands() -> impl Iterator<Item = &'static str> {
+    KATEX_COMMANDS.iter().copied()
+}
@@ -0,0 +1,312 @@
+use colored::*;
+
+use crate::utils::err;
+
+/// A problem reported by LaTeX, mapped back to the markdown source where possible
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// The error message as printed by LaTeX, without the leading `!`
+    pub message: String,
+    /// Line in the generated `.tex` file the error was reported at
+    pub tex_line: Option<usize>,
+    /// The input LaTeX had read up to the point of the error
+    pub context: Option<String>,
+    /// Line in the markdown file the error most likely comes from
+    pub source_line: Option<usize>,
+    /// Text of that markdown line
+    pub snippet: Option<String>,
+    /// Suggestion on how to fix common errors
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    /// Formats the diagnostic without colours, e.g. for the error page of the PDF preview
+    pub fn describe(&self, file: &str) -> String {
+        let mut out = match self.source_line {
+            Some(line) => format!("{}:{}: {}", file, line, self.message),
+            None => match self.tex_line {
+                Some(line) => format!("{} (generated .tex line {}): {}", file, line, self.message),
+                None => format!("{}: {}", file, self.message),
+            },
+        };
+        if let Some(snippet) = &self.snippet {
+            out.push_str(&format!("\n    | {}", snippet));
+        }
+        if let Some(hint) = &self.hint {
+            out.push_str(&format!("\n    = hint: {}", hint));
+        }
+        out
+    }
+
+    /// Formats the diagnostic with colours for the terminal
+    pub fn pretty(&self, file: &str) -> String {
+        let location = match (self.source_line, self.tex_line) {
+            (Some(line), _) => format!("{}:{}", file, line),
+            (None, Some(line)) => format!("{} (generated .tex line {})", file, line),
+            (None, None) => file.to_string(),
+        };
+
+        let mut out = format!(
+            "{} {}\n  {} {}",
+            "ERROR:".red().bold(),
+            self.message.bold(),
+            "-->".blue().bold(),
+            location
+        );
+        if let Some(snippet) = self.snippet.as_ref().or(self.context.as_ref()) {
+            out.push_str(&format!("\n   {} {}", "|".blue().bold(), snippet));
+        }
+        if let Some(hint) = &self.hint {
+            out.push_str(&format!("\n   {} {}", "= hint:".yellow().bold(), hint.yellow()));
+        }
+        out
+    }
+}
+
+/// Error returned when the LaTeX engine fails to build a document
+#[derive(Debug)]
+pub struct LatexError {
+    /// Name of the markdown file being built
+    pub file: String,
+    /// The full LaTeX log
+    pub log: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl std::fmt::Display for LatexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.diagnostics.is_empty() {
+            // Nothing recognisable, so show the end of the log where LaTeX stopped
+            let lines: Vec<_> = self.log.lines().collect();
+            let tail = lines[lines.len().saturating_sub(20)..].join("\n");
+            return write!(f, "{}\n{}", err("LaTeX failed to build the PDF"), tail);
+        }
+
+        let diagnostics: Vec<_> = self.diagnostics.iter().map(|d| d.pretty(&self.file)).collect();
+        write!(f, "{}", diagnostics.join("\n\n"))
+    }
+}
+
+impl std::error::Error for LatexError {}
+
+/// Parses the errors out of a LaTeX `.log` file.
+///
+/// Errors start with a line beginning with `!`, and are usually followed by a line of the form
+/// `l.<line> <input read so far>` giving their position in the `.tex` file.
+pub fn parse_latex_log(log: &str) -> Vec<Diagnostic> {
+    let lines: Vec<&str> = log.lines().collect();
+    let mut diagnostics = Vec::new();
+
+    let mut i = 0;
+    while i < lines.len() {
+        let Some(message) = lines[i].strip_prefix("! ") else {
+            i += 1;
+            continue;
+        };
+        let message = message.trim().to_string();
+
+        // Follow-up messages of an error we have already reported
+        if message == "Emergency stop." || message.starts_with("==> Fatal error") {
+            i += 1;
+            continue;
+        }
+
+        // Look ahead for the line number, stopping at the next error
+        let mut tex_line = None;
+        let mut context = None;
+        for line in lines.iter().skip(i + 1).take(12) {
+            if line.starts_with("! ") {
+                break;
+            }
+            if let Some((number, rest)) = line
+                .strip_prefix("l.")
+                .and_then(|rest| rest.split_once(' ').or(Some((rest, ""))))
+            {
+                if let Ok(number) = number.parse::<usize>() {
+                    tex_line = Some(number);
+                    context = Some(rest.trim().to_string()).filter(|c| !c.is_empty());
+                    break;
+                }
+            }
+        }
+
+        let hint = hint_for(&message, context.as_deref());
+        diagnostics.push(Diagnostic {
+            message,
+            tex_line,
+            context,
+            source_line: None,
+            snippet: None,
+            hint,
+        });
+        i += 1;
+    }
+
+    diagnostics
+}
+
+/// Suggests a fix for the most common LaTeX errors
+fn hint_for(message: &str, context: Option<&str>) -> Option<String> {
+    if message.starts_with("Undefined control sequence") {
+        let command = context.and_then(last_control_sequence);
+        return Some(match command {
+            Some(command) => format!(
+                "`{}` is not defined. Add it to katex-macros.js and run `tatum compile-macros`, \
+                 or load the package that provides it in header.tex",
+                command
+            ),
+            None => "A command is not defined. Add it to katex-macros.js and run \
+                     `tatum compile-macros`, or load the package that provides it in header.tex"
+                .to_string(),
+        });
+    }
+
+    if let Some(rest) = message.strip_prefix("LaTeX Error: File `") {
+        if let Some((file, _)) = rest.split_once('\'') {
+            let package = file.trim_end_matches(".sty").trim_end_matches(".cls");
+            return Some(format!(
+                "The LaTeX package `{}` is not installed. Install it, e.g. with `tlmgr install {}`",
+                package, package
+            ));
+        }
+    }
+
+    if message.contains("already defined") {
+        return Some(
+            "A macro clashes with an existing LaTeX command. Set `command: \"renewcommand\"` for it in katex-macros.js"
+                .to_string(),
+        );
+    }
+
+    if message.starts_with("Missing $ inserted") {
+        return Some(
+            "A math-only command was used outside of math mode, or a `$` is unbalanced".to_string(),
+        );
+    }
+
+    if message.starts_with("Missing } inserted")
+        || message.starts_with("Extra }")
+        || message.starts_with("Missing { inserted")
+    {
+        return Some("The braces in this expression are unbalanced".to_string());
+    }
+
+    if message.starts_with("Extra \\right") || message.contains("\\left") {
+        return Some("Every \\left needs a matching \\right".to_string());
+    }
+
+    None
+}
+
+/// Returns the last `\command` in a piece of LaTeX input
+fn last_control_sequence(input: &str) -> Option<String> {
+    let start = input.rfind('\\')?;
+    let name: String = input[start + 1..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic() || *c == '@')
+        .collect();
+    if name.is_empty() {
+        None
+    } else {
+        Some(format!("\\{}", name))
+    }
+}
+
+/// Fills in `source_line` and `snippet` of each diagnostic by finding the input LaTeX was reading
+/// in the markdown source.
+///
+/// `tex` is the generated `.tex` file, used when the log does not include enough context.
+pub fn locate_in_markdown(diagnostics: &mut [Diagnostic], tex: &str, markdown: &str) {
+    let md_lines: Vec<String> = markdown.lines().map(normalize).collect();
+    let tex_lines: Vec<&str> = tex.lines().collect();
+
+    for diagnostic in diagnostics.iter_mut() {
+        let mut candidates = Vec::new();
+        if let Some(context) = &diagnostic.context {
+            candidates.push(normalize(context));
+        }
+        if let Some(line) = diagnostic.tex_line.and_then(|l| tex_lines.get(l.wrapping_sub(1))) {
+            candidates.push(normalize(line));
+        }
+
+        let found = candidates.iter().find_map(|candidate| find_line(&md_lines, candidate));
+        if let Some(index) = found {
+            diagnostic.source_line = Some(index + 1);
+            diagnostic.snippet = markdown.lines().nth(index).map(|l| l.trim().to_string());
+        }
+    }
+}
+
+/// Finds the markdown line containing `fragment`, trying shorter tails of it if needed.
+///
+/// The tail is used as LaTeX reports the input up to the error, so its end is the most relevant.
+fn find_line(md_lines: &[String], fragment: &str) -> Option<usize> {
+    let chars: Vec<char> = fragment.chars().collect();
+    for len in [chars.len(), 40, 20, 10] {
+        if len > chars.len() || len < 4 {
+            continue;
+        }
+        let tail: String = chars[chars.len() - len..].iter().collect();
+        let tail = tail.trim();
+        if tail.len() < 4 {
+            continue;
+        }
+        if let Some(index) = md_lines.iter().position(|line| line.contains(tail)) {
+            return Some(index);
+        }
+    }
+    None
+}
+
+/// Removes the differences pandoc introduces between markdown and LaTeX so lines can be compared
+fn normalize(line: &str) -> String {
+    let line = line
+        .replace("\\(", " ")
+        .replace("\\)", " ")
+        .replace("\\[", " ")
+        .replace("\\]", " ")
+        .replace('$', " ");
+    line.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "\
+(./notes.tex
+! Undefined control sequence.
+l.58 The set \\(x \\in \\Reals
+                        \\)
+! Emergency stop.
+";
+
+    #[test]
+    fn test_parse_latex_log() {
+        let diagnostics = parse_latex_log(LOG);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "Undefined control sequence.");
+        assert_eq!(diagnostics[0].tex_line, Some(58));
+        assert_eq!(diagnostics[0].context.as_deref(), Some("The set \\(x \\in \\Reals"));
+        assert!(diagnostics[0].hint.as_ref().unwrap().contains("`\\Reals`"));
+    }
+
+    #[test]
+    fn test_missing_package_hint() {
+        let log = "! LaTeX Error: File `tikz-cd.sty' not found.\n\nl.12 \\usepackage\n";
+        let diagnostics = parse_latex_log(log);
+        assert!(diagnostics[0].hint.as_ref().unwrap().contains("tlmgr install tikz-cd"));
+    }
+
+    #[test]
+    fn test_locate_in_markdown() {
+        let mut diagnostics = parse_latex_log(LOG);
+        let markdown = "# Sets\n\nSome intro.\n\nThe set $x \\in \\Reals$ is big.\n";
+        locate_in_markdown(&mut diagnostics, "", markdown);
+        assert_eq!(diagnostics[0].source_line, Some(5));
+        assert_eq!(
+            diagnostics[0].snippet.as_deref(),
+            Some("The set $x \\in \\Reals$ is big.")
+        );
+    }
+}
@@ -0,0 +1,750 @@
+use std::fs;
+use std::path::Path;
+use serde_json::Value;
+use anyhow::{Context, Result, anyhow};
+
+use crate::js_object::parse_object_literal;
+use crate::latex_commands::{is_latex_command, is_package_command};
+use crate::utils::err;
+
+/// First line of a generated macros.tex, which marks it as safe to regenerate
+pub const GENERATED_HEADER: &str =
+    "% Generated by tatum from katex-macros.js. Remove this line to maintain the file by hand";
+
+/// Start of the header of every generated macros.tex, whatever the rest of it says
+const GENERATED_PREFIX: &str = "% Generated by tatum";
+
+/// The LaTeX command used to define a macro in macros.tex
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Definition {
+    NewCommand,
+    RenewCommand,
+    ProvideCommand,
+    /// `\providecommand` followed by `\renewcommand`, for commands that may be defined by a
+    /// package. The macro wins either way, like it does in the preview
+    Replace,
+    /// `\DeclareMathOperator`, or `\DeclareMathOperator*` with limits
+    MathOperator { limits: bool },
+}
+
+impl Definition {
+    /// Parses the `command` override of a macro, e.g. `"renewcommand"`
+    fn parse(command: &str) -> Option<Definition> {
+        match command.trim_start_matches('\\') {
+            "newcommand" => Some(Definition::NewCommand),
+            "renewcommand" => Some(Definition::RenewCommand),
+            "providecommand" => Some(Definition::ProvideCommand),
+            "DeclareMathOperator" => Some(Definition::MathOperator { limits: false }),
+            "DeclareMathOperator*" => Some(Definition::MathOperator { limits: true }),
+            _ => None,
+        }
+    }
+}
+
+/// A macro defined in a template's katex-macros.js
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    /// Name including the leading backslash, e.g. `\R`
+    pub name: String,
+    pub body: String,
+    pub args: u64,
+    /// Default value of the first argument, which makes it optional as in
+    /// `\newcommand{\x}[2][default]{...}`
+    pub default: Option<String>,
+    /// Overrides the command chosen by [`Macro::definition`]
+    pub command: Option<Definition>,
+    /// Body used in macros.tex instead of `body`, for macros KaTeX and LaTeX disagree on
+    pub tex: Option<String>,
+}
+
+impl Macro {
+    /// The body used for LaTeX exports
+    pub fn tex_body(&self) -> &str {
+        self.tex.as_deref().unwrap_or(&self.body)
+    }
+
+    /// The command used to define the macro.
+    ///
+    /// Unless overridden, commands that LaTeX already defines are redefined with
+    /// `\renewcommand`, and `\operatorname{...}` bodies become `\DeclareMathOperator`.
+    pub fn definition(&self) -> Definition {
+        if let Some(command) = self.command {
+            return command;
+        }
+
+        if is_latex_command(&self.name) {
+            Definition::RenewCommand
+        } else if is_package_command(&self.name) {
+            Definition::Replace
+        } else if let (0, Some((_, limits))) = (self.args, operator_name(self.tex_body())) {
+            Definition::MathOperator { limits }
+        } else {
+            Definition::NewCommand
+        }
+    }
+
+    /// The LaTeX definition of the macro
+    pub fn to_tex(&self) -> String {
+        let command = match self.definition() {
+            Definition::NewCommand => "newcommand",
+            Definition::RenewCommand => "renewcommand",
+            Definition::ProvideCommand => "providecommand",
+            Definition::Replace => {
+                return format!(
+                    "\\providecommand{{{}}}{{}}\n{}",
+                    self.name,
+                    self.command_tex("renewcommand")
+                );
+            }
+            Definition::MathOperator { limits } => {
+                let text = operator_name(self.tex_body())
+                    .map(|(text, _)| text)
+                    .unwrap_or(self.tex_body());
+                let star = if limits { "*" } else { "" };
+                return format!("\\DeclareMathOperator{}{{{}}}{{{}}}", star, self.name, text);
+            }
+        };
+        self.command_tex(command)
+    }
+
+    fn command_tex(&self, command: &str) -> String {
+        let mut tex = format!("\\{}{{{}}}", command, self.name);
+        if self.args > 0 {
+            tex.push_str(&format!("[{}]", self.args));
+        }
+        if let Some(default) = &self.default {
+            tex.push_str(&format!("[{}]", default));
+        }
+        tex.push_str(&format!("{{{}}}", self.tex_body()));
+        tex
+    }
+}
+
+/// Splits `\operatorname{text}` or `\operatorname*{text}` into `text` and whether it has limits
+fn operator_name(body: &str) -> Option<(&str, bool)> {
+    let rest = body.trim().strip_prefix("\\operatorname")?;
+    let (rest, limits) = match rest.strip_prefix('*') {
+        Some(rest) => (rest, true),
+        None => (rest, false),
+    };
+    let text = rest.trim_start().strip_prefix('{')?.strip_suffix('}')?;
+    (!text.contains(['{', '}'])).then_some((text, limits))
+}
+
+/// The macros of a katex-macros.js file
+#[derive(Debug, Default)]
+pub struct KatexMacros {
+    pub macros: Vec<Macro>,
+    /// Entries that are not in one of the supported forms, formatted for display
+    pub unknown: Vec<String>,
+    /// Macros defined more than once, where the last definition is used, formatted for display
+    pub duplicates: Vec<String>,
+}
+
+/// Reads the katex-macros.js file of a template
+pub fn read_katex_macros(template_path: &str) -> Result<KatexMacros> {
+    let path = Path::new(template_path).join("katex-macros.js");
+    let content = fs::read_to_string(path)
+        .with_context(|| err("Could not read katex macros"))?;
+    parse_katex_macros(&content)
+}
+
+/// Parses the contents of a katex-macros.js file.
+///
+/// Each macro is one of
+/// - `"body"`
+/// - `["body", args]`, optionally with a default for the first argument: `["body", args, "default"]`
+/// - `{ body: "body", args: 2, default: "default" }`, where only `body` is required. Objects can
+///   also set `command` (e.g. `"renewcommand"` or `"DeclareMathOperator"`) and a separate `tex`
+///   body for exports
+pub fn parse_katex_macros(content: &str) -> Result<KatexMacros> {
+    let (json, duplicates) = parse_object_literal(content).map_err(|e| {
+        anyhow!(err(format!("Could not parse katex-macros.js:{}", e).as_str()))
+    })?;
+    let object = json
+        .as_object()
+        .ok_or_else(|| anyhow!(err("katex macros must be an object")))?;
+
+    let mut macros = KatexMacros {
+        duplicates: duplicates.iter().map(|e| format!("katex-macros.js:{}", e)).collect(),
+        ..KatexMacros::default()
+    };
+
+    for (name, value) in object {
+        match parse_macro(name, value) {
+            Some(m) => macros.macros.push(m),
+            None => macros.unknown.push(format!("{}: {}", name, value)),
+        }
+    }
+
+    Ok(macros)
+}
+
+fn parse_macro(name: &str, value: &Value) -> Option<Macro> {
+    let mut command = None;
+    let mut tex = None;
+
+    let (body, args, default) = match value {
+        Value::String(body) => (Some(body.as_str()), None, None),
+        Value::Array(arr) if (1..=3).contains(&arr.len()) => {
+            (arr[0].as_str(), arr.get(1), arr.get(2))
+        }
+        Value::Object(obj) => {
+            if let Some(value) = obj.get("command") {
+                command = Some(Definition::parse(value.as_str()?)?);
+            }
+            if let Some(value) = obj.get("tex") {
+                tex = Some(value.as_str()?.to_string());
+            }
+            (
+                obj.get("body").and_then(Value::as_str),
+                obj.get("args"),
+                obj.get("default"),
+            )
+        }
+        _ => (None, None, None),
+    };
+
+    let args = match args {
+        None => 0,
+        Some(args) => args.as_u64().filter(|n| *n <= 9)?,
+    };
+    let default = match default {
+        None | Some(Value::Null) => None,
+        // the optional argument is always the first one
+        Some(Value::String(default)) if args > 0 => Some(default.clone()),
+        Some(_) => return None,
+    };
+
+    // operators take no arguments
+    if matches!(command, Some(Definition::MathOperator { .. })) && args > 0 {
+        return None;
+    }
+
+    Some(Macro {
+        name: name.to_string(),
+        body: body?.to_string(),
+        args,
+        default,
+        command,
+        tex,
+    })
+}
+
+/// Generates the contents of macros.tex
+pub fn macros_to_tex(macros: &[Macro]) -> String {
+    let mut tex = format!("{}\n", GENERATED_HEADER);
+    for m in macros {
+        tex.push_str(&m.to_tex());
+        tex.push('\n');
+    }
+    tex
+}
+
+/// Whether the contents of a macros.tex file were generated from `katex_macros`, the contents of
+/// the template's katex-macros.js.
+///
+/// Files written before compile-macros added a header are only recognised when they are exactly
+/// what it wrote for the current katex-macros.js. Anything else may have been edited by hand.
+pub fn is_generated(macros_tex: &str, katex_macros: &str) -> bool {
+    macros_tex.starts_with(GENERATED_PREFIX)
+        || (!macros_tex.is_empty()
+            && legacy_macros_tex(katex_macros).is_some_and(|legacy| legacy == macros_tex))
+}
+
+/// What compile-macros wrote before it added a header: a `\newcommand` for each `"body"` and
+/// `["body", args]` entry, in the order of their names
+fn legacy_macros_tex(katex_macros: &str) -> Option<String> {
+    let (json, _) = parse_object_literal(katex_macros).ok()?;
+
+    let mut tex = String::new();
+    for (name, value) in json.as_object()? {
+        match value {
+            Value::String(body) => tex.push_str(&format!("\\newcommand{{{}}}{{{}}}\n", name, body)),
+            Value::Array(arr) if arr.len() == 2 => {
+                if let (Some(body), Some(args)) = (arr[0].as_str(), arr[1].as_u64()) {
+                    tex.push_str(&format!("\\newcommand{{{}}}[{}]{{{}}}\n", name, args, body));
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(tex)
+}
+
+/// Returns the macro definitions of a template used for LaTeX exports.
+///
+/// A macros.tex written by hand, i.e. without the generated header, is used as is. Otherwise the
+/// definitions are generated from katex-macros.js, so an export never uses stale macros.
+pub fn macros_preamble(template_path: &Path) -> Result<String> {
+    let js_path = template_path.join("katex-macros.js");
+    let katex_macros = if js_path.exists() {
+        fs::read_to_string(&js_path).with_context(|| err("Could not read katex macros"))?
+    } else {
+        String::new()
+    };
+
+    let macros_path = template_path.join("macros.tex");
+    if macros_path.exists() {
+        let tex = fs::read_to_string(&macros_path)
+            .with_context(|| err("Could not read macros.tex"))?;
+        if !is_generated(&tex, &katex_macros) {
+            return Ok(tex);
+        }
+    }
+
+    if !js_path.exists() {
+        return Ok(String::new());
+    }
+
+    Ok(macros_to_tex(&parse_katex_macros(&katex_macros)?.macros))
+}
+
+/// The macro definitions found in a LaTeX file
+#[derive(Debug, Default)]
+pub struct TexMacros {
+    pub macros: Vec<Macro>,
+    /// Constructs KaTeX cannot use, with the line they are on
+    pub unsupported: Vec<String>,
+}
+
+/// Commands that can be used in a macro body in LaTeX but not in KaTeX
+static KATEX_UNSUPPORTED: &[&str] = &[
+    "ensuremath", "xspace", "protect", "expandafter", "csname", "ifmmode", "ifx", "else", "fi",
+    "makeatletter", "makeatother", "noexpand", "edef", "gdef", "xdef",
+];
+
+/// Definitions that cannot be turned into KaTeX macros, and how many groups they take
+static UNSUPPORTED_DEFINITIONS: &[(&str, usize)] = &[
+    ("newenvironment", 3),
+    ("renewenvironment", 3),
+    ("newtheorem", 2),
+    ("let", 0),
+    ("DeclarePairedDelimiter", 3),
+    ("NewDocumentCommand", 3),
+    ("RenewDocumentCommand", 3),
+    ("DeclareDocumentCommand", 3),
+];
+
+/// Parses the `\newcommand`, `\renewcommand`, `\providecommand`, `\def` and
+/// `\DeclareMathOperator` definitions of a LaTeX file, e.g. an existing preamble
+pub fn parse_tex_macros(content: &str) -> TexMacros {
+    let mut scanner = TexScanner {
+        chars: strip_tex_comments(content).chars().collect(),
+        pos: 0,
+    };
+    let mut macros = TexMacros::default();
+
+    while let Some(command) = scanner.next_command() {
+        let line = scanner.line();
+        let result = match command.as_str() {
+            "newcommand" | "renewcommand" | "providecommand" | "DeclareRobustCommand" => {
+                scanner.newcommand()
+            }
+            "def" => scanner.def(),
+            "DeclareMathOperator" => scanner.math_operator(),
+            _ => match UNSUPPORTED_DEFINITIONS.iter().find(|(name, _)| *name == command) {
+                Some((_, groups)) => {
+                    // skip the body so definitions inside it are not imported
+                    scanner.skip_star();
+                    scanner.control_sequence();
+                    for _ in 0..*groups {
+                        scanner.optional();
+                        scanner.group();
+                    }
+                    Err(format!("\\{} is not supported by KaTeX", command))
+                }
+                None => continue,
+            },
+        };
+
+        match result {
+            Ok(m) => match unsupported_in(&m) {
+                Some(reason) => macros.unsupported.push(format!("line {}: {}", line, reason)),
+                None => macros.macros.push(m),
+            },
+            Err(reason) => macros.unsupported.push(format!("line {}: {}", line, reason)),
+        }
+    }
+
+    macros
+}
+
+/// Why KaTeX cannot use a macro imported from LaTeX, if it cannot
+fn unsupported_in(m: &Macro) -> Option<String> {
+    if m.name.contains('@') {
+        return Some(format!("{} uses `@`, which is internal to LaTeX packages", m.name));
+    }
+
+    let mut scanner = TexScanner {
+        chars: m.body.chars().collect(),
+        pos: 0,
+    };
+    while let Some(command) = scanner.next_command() {
+        if KATEX_UNSUPPORTED.contains(&command.as_str()) {
+            return Some(format!("{} uses \\{}, which KaTeX does not support", m.name, command));
+        }
+    }
+    None
+}
+
+/// Removes `%` comments, keeping the line breaks so line numbers stay the same
+fn strip_tex_comments(content: &str) -> String {
+    content
+        .lines()
+        .map(|line| {
+            let mut escaped = false;
+            for (i, c) in line.char_indices() {
+                match c {
+                    '%' if !escaped => return &line[..i],
+                    '\\' => escaped = !escaped,
+                    _ => escaped = false,
+                }
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A minimal reader for the arguments of LaTeX definitions
+struct TexScanner {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl TexScanner {
+    fn line(&self) -> usize {
+        self.chars[..self.pos].iter().filter(|c| **c == '\n').count() + 1
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_star(&mut self) -> bool {
+        self.skip_whitespace();
+        let star = self.chars.get(self.pos) == Some(&'*');
+        if star {
+            self.pos += 1;
+        }
+        star
+    }
+
+    /// Moves past the next control word and returns its name
+    fn next_command(&mut self) -> Option<String> {
+        while self.pos < self.chars.len() {
+            if self.chars[self.pos] == '\\' {
+                self.pos += 1;
+                let name = self.letters();
+                if !name.is_empty() {
+                    return Some(name);
+                }
+                // control symbol such as `\{`
+                self.pos += 1;
+            } else {
+                self.pos += 1;
+            }
+        }
+        None
+    }
+
+    fn letters(&mut self) -> String {
+        let start = self.pos;
+        while self
+            .chars
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_alphabetic() || *c == '@')
+        {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// Reads a control sequence such as `\R`, optionally wrapped in braces as in `{\R}`
+    fn control_sequence(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        let braced = self.chars.get(self.pos) == Some(&'{');
+        if braced {
+            self.pos += 1;
+            self.skip_whitespace();
+        }
+
+        if self.chars.get(self.pos) != Some(&'\\') {
+            return None;
+        }
+        self.pos += 1;
+        let mut name = self.letters();
+        if name.is_empty() {
+            name = self.chars.get(self.pos)?.to_string();
+            self.pos += 1;
+        }
+
+        if braced {
+            self.skip_whitespace();
+            if self.chars.get(self.pos) != Some(&'}') {
+                return None;
+            }
+            self.pos += 1;
+        }
+        Some(format!("\\{}", name))
+    }
+
+    /// Reads a `{...}` group with balanced braces, returning its contents
+    fn group(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        if self.chars.get(self.pos) != Some(&'{') {
+            return None;
+        }
+        self.balanced('}')
+    }
+
+    /// Reads a `[...]` argument if there is one
+    fn optional(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        if self.chars.get(self.pos) != Some(&'[') {
+            return None;
+        }
+        self.balanced(']')
+    }
+
+    /// Reads from the opening bracket to the matching `close`, skipping over braced groups and escapes
+    fn balanced(&mut self, close: char) -> Option<String> {
+        self.pos += 1;
+        let start = self.pos;
+        let mut depth = 0;
+
+        while let Some(&c) = self.chars.get(self.pos) {
+            match c {
+                '\\' => self.pos += 1,
+                c if c == close && depth == 0 => {
+                    self.pos += 1;
+                    return Some(self.chars[start..self.pos - 1].iter().collect());
+                }
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        None
+    }
+
+    /// `\newcommand{\name}[args][default]{body}`, after the command itself
+    fn newcommand(&mut self) -> Result<Macro, String> {
+        self.skip_star();
+        let name = self.control_sequence().ok_or("expected a command name")?;
+        let args = match self.optional() {
+            Some(args) => args
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| format!("{} has an invalid number of arguments", name))?,
+            None => 0,
+        };
+        let default = self.optional();
+        let body = self
+            .group()
+            .ok_or_else(|| format!("{} has no body", name))?;
+
+        Ok(Macro {
+            name,
+            body,
+            args,
+            default,
+            command: None,
+            tex: None,
+        })
+    }
+
+    /// `\def\name#1#2{body}`, after the command itself
+    fn def(&mut self) -> Result<Macro, String> {
+        let name = self.control_sequence().ok_or("expected a command name after \\def")?;
+
+        let start = self.pos;
+        while self.chars.get(self.pos).is_some_and(|c| *c != '{') {
+            self.pos += 1;
+        }
+        let params: String = self.chars[start..self.pos].iter().filter(|c| !c.is_whitespace()).collect();
+
+        // only undelimited parameters, i.e. exactly #1#2...#n, can be expressed as a macro
+        let args = params.len() as u64 / 2;
+        let expected: String = (1..=args).map(|i| format!("#{}", i)).collect();
+        if params != expected {
+            self.group();
+            return Err(format!("{} uses delimited parameters `{}`, which KaTeX does not support", name, params));
+        }
+
+        let body = self
+            .group()
+            .ok_or_else(|| format!("{} has no body", name))?;
+
+        Ok(Macro {
+            name,
+            body,
+            args,
+            default: None,
+            command: None,
+            tex: None,
+        })
+    }
+
+    /// `\DeclareMathOperator{\name}{text}`, after the command itself
+    fn math_operator(&mut self) -> Result<Macro, String> {
+        let star = if self.skip_star() { "*" } else { "" };
+        let name = self.control_sequence().ok_or("expected an operator name")?;
+        let text = self
+            .group()
+            .ok_or_else(|| format!("{} has no body", name))?;
+
+        Ok(Macro {
+            name,
+            body: format!("\\operatorname{}{{{}}}", star, text),
+            args: 0,
+            default: None,
+            command: None,
+            tex: None,
+        })
+    }
+}
+
+/// Formats a macro as a katex-macros.js entry, in the shortest form that keeps its meaning
+pub fn katex_entry(m: &Macro) -> String {
+    let quote = |s: &str| Value::String(s.to_string()).to_string();
+    let value = match (m.args, &m.default) {
+        (0, _) => quote(&m.body),
+        (args, None) => format!("[{}, {}]", quote(&m.body), args),
+        (args, Some(default)) => format!("[{}, {}, {}]", quote(&m.body), args, quote(default)),
+    };
+    format!("{}: {}", quote(&m.name), value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macro_forms() {
+        let source = r#"window.katexMacros = {
+            "\\R": "\\mathbb{R}",
+            "\\absv": ["\\left| #1 \\right|", 1],
+            "\\inner": { body: "\\langle #1, #2 \\rangle", args: 2 },
+            "\\pnorm": ["\\| #2 \\|_{#1}", 2, "2"],
+            "\\bad": [1, 2],
+        };"#;
+
+        let macros = parse_katex_macros(source).unwrap();
+        let tex: Vec<_> = macros.macros.iter().map(Macro::to_tex).collect();
+        assert_eq!(tex, [
+            "\\newcommand{\\R}{\\mathbb{R}}",
+            "\\newcommand{\\absv}[1]{\\left| #1 \\right|}",
+            "\\newcommand{\\inner}[2]{\\langle #1, #2 \\rangle}",
+            "\\newcommand{\\pnorm}[2][2]{\\| #2 \\|_{#1}}",
+        ]);
+        assert_eq!(macros.unknown, ["\\bad: [1,2]"]);
+    }
+
+    #[test]
+    fn test_definitions() {
+        let source = r#"{
+            "\\det": "\\operatorname{det}",
+            "\\abs": ["\\left| #1 \\right|", 1],
+            "\\argmax": "\\operatorname*{arg\\,max}",
+            "\\tr": "\\operatorname{tr}",
+            "\\qed": { body: "\\blacksquare", command: "providecommand" },
+            "\\E": { body: "\\mathbb{E}", tex: "\\mathop{\\mathbb{E}}" },
+        }"#;
+
+        let macros = parse_katex_macros(source).unwrap();
+        let tex: Vec<_> = macros.macros.iter().map(Macro::to_tex).collect();
+        assert_eq!(tex, [
+            "\\newcommand{\\E}{\\mathop{\\mathbb{E}}}",
+            "\\providecommand{\\abs}{}\n\\renewcommand{\\abs}[1]{\\left| #1 \\right|}",
+            "\\DeclareMathOperator*{\\argmax}{arg\\,max}",
+            "\\renewcommand{\\det}{\\operatorname{det}}",
+            "\\providecommand{\\qed}{\\blacksquare}",
+            "\\providecommand{\\tr}{}\n\\renewcommand{\\tr}{\\operatorname{tr}}",
+        ]);
+
+        assert!(macros_to_tex(&macros.macros).starts_with(GENERATED_HEADER));
+    }
+
+    #[test]
+    fn test_parse_tex_macros() {
+        let tex = r"
+            % \newcommand{\commented}{x}
+            \newcommand{\R}{\mathbb{R}}
+            \renewcommand*\vec[1]{\boldsymbol{#1}}
+            \newcommand{\norm}[2][2]{\left\| #2 \right\|_{#1}} % with a default
+            \def\pair#1#2{\left( #1, #2 \right)}
+            \DeclareMathOperator*{\argmax}{arg\,max}
+            \def\until#1.{#1}
+            \newcommand{\nice}{\ensuremath{x}}
+            \newenvironment{note}{\begin{quote}}{\end{quote}}
+        ";
+
+        let macros = parse_tex_macros(tex);
+        let entries: Vec<_> = macros.macros.iter().map(katex_entry).collect();
+        assert_eq!(entries, [
+            r#""\\R": "\\mathbb{R}""#,
+            r#""\\vec": ["\\boldsymbol{#1}", 1]"#,
+            r#""\\norm": ["\\left\\| #2 \\right\\|_{#1}", 2, "2"]"#,
+            r#""\\pair": ["\\left( #1, #2 \\right)", 2]"#,
+            r#""\\argmax": "\\operatorname*{arg\\,max}""#,
+        ]);
+
+        assert_eq!(macros.unsupported.len(), 3);
+        assert!(macros.unsupported[0].starts_with("line 8: \\until uses delimited parameters"));
+        assert!(macros.unsupported[1].contains("\\ensuremath"));
+        assert!(macros.unsupported[2].contains("\\newenvironment"));
+    }
+
+    #[test]
+    fn test_macros_preamble() {
+        let template = tempfile::tempdir().unwrap();
+        fs::write(template.path().join("katex-macros.js"), r#"{ "\\R": "\\mathbb{R}" }"#).unwrap();
+
+        // generated from katex-macros.js, even when macros.tex is stale
+        fs::write(template.path().join("macros.tex"), format!("{}\n\\newcommand{{\\Q}}{{Q}}\n", GENERATED_HEADER)).unwrap();
+        let preamble = macros_preamble(template.path()).unwrap();
+        assert!(preamble.ends_with("\\newcommand{\\R}{\\mathbb{R}}\n"));
+
+        // but a hand-written macros.tex wins
+        fs::write(template.path().join("macros.tex"), "\\def\\R{R}\n").unwrap();
+        assert_eq!(macros_preamble(template.path()).unwrap(), "\\def\\R{R}\n");
+    }
+
+    #[test]
+    fn test_is_generated() {
+        let js = r#"window.katexMacros = {
+            "\\R": "\\mathbb{R}",
+            "\\abs": ["|#1|", 1],
+            "\\pnorm": ["\\| #2 \\|_{#1}", 2, "2"],
+        };"#;
+
+        // written by this version, by compile-macros when it first added a header, and before
+        assert!(is_generated(&macros_to_tex(&[]), js));
+        assert!(is_generated(
+            "% Generated by tatum from katex-macros.js. Edit that file and run `tatum compile-macros`\n\
+             \\newcommand{\\R}{\\mathbb{R}}\n",
+            js
+        ));
+        let legacy = "\\newcommand{\\R}{\\mathbb{R}}\n\\newcommand{\\abs}[1]{|#1|}\n";
+        assert!(is_generated(legacy, js));
+
+        // the same layout written by hand, or for other macros, is kept
+        assert!(!is_generated("\\newcommand{\\R}{\\mathbb{R}}\n", js));
+        assert!(!is_generated("\\newcommand{\\N}{\\mathbb{N}}\n", js));
+        assert!(!is_generated(legacy, ""));
+        assert!(!is_generated("", js));
+        assert!(!is_generated("\\def\\R{R}\n", js));
+        assert!(!is_generated("% my macros\n\\newcommand{\\R}{\\mathbb{R}}\n", js));
+    }
+
+    #[test]
+    fn test_parse_error_is_reported() {
+        let e = parse_katex_macros("window.katexMacros = {\n  \"\\\\R\": \n};").unwrap_err();
+        assert!(e.to_string().contains("katex-macros.js:3:1: expected a value"), "{}", e);
+    }
+}
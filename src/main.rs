@@ -1,16 +1,45 @@
 mod page_template;
+mod pdf_template;
 mod render;
+mod site;
+mod slides;
 mod routes;
 mod svg_template;
+mod templates;
+mod theorems;
+mod typst;
+mod typst_math;
+mod bibliography;
+mod book;
+mod callouts;
+mod citations;
+mod check;
 mod commands;
+mod config;
+mod crossref;
+mod diagrams;
+mod error_template;
+mod exec;
+mod fmt;
+mod front_matter;
+mod includes;
+mod latex_log;
+mod js_object;
+mod latex_commands;
+mod macros;
 mod utils;
 
-use crate::commands::{to_html, init, new, compile_macros, to_latex, to_pdf, render_all};
+use crate::commands::{
+    to_html, init, new, compile_macros, import_macros, to_latex, to_pdf, to_document, to_typst,
+    render_all, DocumentFormat,
+};
+use crate::book::BookFormat;
+use crate::config::Config;
 use crate::utils::eshow;
 
 use std::path::PathBuf;
 
-use clap::{command, Parser};
+use clap::Parser;
 use routes::construct_router;
 use tracing::info;
 
@@ -28,23 +57,40 @@ enum Args {
         #[arg(short, long, default_value_t = false)]
         quiet: bool,
 
-        /// Which port to start listening. Defaults to a random, unoccupied port assigned by the
-        /// operating system.
-        #[arg(short, long, default_value_t = 0)]
-        port: u16,
+        /// Which port to start listening. Defaults to the port set in the config file, or a random,
+        /// unoccupied port assigned by the operating system.
+        #[arg(short, long)]
+        port: Option<u16>,
 
-        /// Which address to listen on.
-        #[arg(short, long, default_value_t = ("127.0.0.1").to_string())]
-        address: String,
+        /// Which address to listen on. Defaults to the address set in the config file, or
+        /// 127.0.0.1.
+        #[arg(short, long)]
+        address: Option<String>,
 
         /// Specify a file path to open in a browser.
         #[arg(short, long)]
         open: Option<PathBuf>,
 
-        /// Path to a template directory containing a page.html
+        /// Open the live PDF preview of the `open` file instead of the HTML one.
+        #[arg(long, default_value_t = false)]
+        pdf: bool,
+
+        /// Open the `open` file as slides, with a presenter view, instead of as a page.
+        #[arg(long, default_value_t = false)]
+        slides: bool,
+
+        /// Path to a template directory containing a page.html, or the name of a template in a
+        /// `.tatum` directory. Defaults to the `template` set in the config file
         #[arg(short, long)]
-        template: String,
+        template: Option<String>,
+
+        /// Run code blocks marked with `{exec}`, even if the config does not enable them
+        #[arg(long, conflicts_with = "no_exec")]
+        exec: bool,
 
+        /// Do not run code blocks marked with `{exec}`, even if the config enables them
+        #[arg(long)]
+        no_exec: bool,
     },
     /// Renders a .md file to HTML
     Render {
@@ -56,13 +102,26 @@ enum Args {
         #[arg(short, long)]
         out_file: Option<PathBuf>,
 
-        /// Path to a template directory containing a page.html
+        /// Path to a template directory containing a page.html, or the name of a template in a
+        /// `.tatum` directory. Defaults to the `template` set in the config file
         #[arg(short, long)]
-        template: String,
+        template: Option<String>,
 
         /// Whether to create parent directory of output file
         #[arg(short)]
         parent: bool,
+
+        /// Run code blocks marked with `{exec}`, even if the config does not enable them
+        #[arg(long, conflicts_with = "no_exec")]
+        exec: bool,
+
+        /// Do not run code blocks marked with `{exec}`, even if the config enables them
+        #[arg(long)]
+        no_exec: bool,
+
+        /// Render the document as slides, split at `---` rules and level 1 and 2 headings
+        #[arg(long)]
+        slides: bool,
     },
     /// Creates the tatum config directory
     Init,
@@ -70,22 +129,70 @@ enum Args {
     New {
         /// Name of template directory to create
         template_name: String,
+
+        /// Template to copy, either a template name, a path, or one of the built-in templates.
+        /// Defaults to the built-in `default` template
+        #[arg(long)]
+        from: Option<String>,
     },
-    /// Compiles a given template's katex-macros.json to macros.tex. Must be run before exporting
-    /// to PDF or LATEX
+    /// Manages templates
+    #[command(subcommand)]
+    Template(TemplateArgs),
+    /// Compiles a given template's katex-macros.js to macros.tex. Exports do this on their own,
+    /// unless macros.tex was written by hand
     CompileMacros {
-        /// Path to a template directory
+        /// Path to a template directory, or the name of a template in a `.tatum` directory.
+        /// Defaults to the `template` set in the config file
         #[arg(short, long)]
-        template: String,
+        template: Option<String>,
+        /// Only print the changes to macros.tex, without writing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Imports the \newcommand, \renewcommand, \def and \DeclareMathOperator definitions of a
+    /// .tex file into a template's katex-macros.js
+    ImportMacros {
+        /// Path to the .tex file, e.g. an existing preamble
+        tex_file: PathBuf,
+        /// Path to a template directory, or the name of a template in a `.tatum` directory.
+        /// Defaults to the `template` set in the config file
+        #[arg(short, long)]
+        template: Option<String>,
+        /// Only print the changes to katex-macros.js, without writing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Checks the math of a .md file for unbalanced delimiters, unknown commands and missing
+    /// arguments, which break LaTeX exports
+    Check {
+        /// Path to Markdown file to check.
+        in_file: PathBuf,
+        /// Template whose katex-macros.js defines additional commands. Defaults to the `template`
+        /// set in the config file
+        #[arg(short, long)]
+        template: Option<String>,
+    },
+    /// Normalizes the style of .md files: list markers, headings, tables, wrapping and blank
+    /// lines, following the `[fmt]` rules of the config file. Math, front matter and code are
+    /// left as they are
+    Fmt {
+        /// Markdown files to format
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Print the changes instead of writing them, exiting with an error if there are any
+        #[arg(long)]
+        check: bool,
     },
     /// Exports a .md file to LATEX
     ToLatex {
         /// Path to Markdown file to render.
         in_file: String,
 
-        /// Path to a template directory
+        /// Path to a template directory, or the name of a template in a `.tatum` directory.
+        /// Defaults to the `template` set in the config file
         #[arg(short, long)]
-        template: String,
+        template: Option<String>,
 
         /// The path the final `LATEX` file should be saved.
         /// Defaults to the same path as the `in_file`, but with the `.md` replaced with a `.tex`
@@ -94,17 +201,29 @@ enum Args {
 
         /// Whether to create parent directory of output file
         #[arg(short, long)]
-        parent: bool
+        parent: bool,
 
+        /// Run code blocks marked with `{exec}`, even if the config does not enable them
+        #[arg(long, conflicts_with = "no_exec")]
+        exec: bool,
+
+        /// Do not run code blocks marked with `{exec}`, even if the config enables them
+        #[arg(long)]
+        no_exec: bool,
+
+        /// Export the document as Beamer slides, split at `---` rules and level 1 and 2 headings
+        #[arg(long)]
+        slides: bool,
     },
     /// Exports a .md file to PDF using the pdflatex engine
     ToPdf {
         /// Path to Markdown file to render
         in_file: String,
 
-        /// Path to a template directory
+        /// Path to a template directory, or the name of a template in a `.tatum` directory.
+        /// Defaults to the `template` set in the config file
         #[arg(short, long)]
-        template: String,
+        template: Option<String>,
 
         /// The path the final `PDF` file should be saved.
         /// Defaults to the same path as the `in_file`, but with the `.md` replaced with `.pdf`.
@@ -113,21 +232,254 @@ enum Args {
 
         /// Whether to create parent directory of output file
         #[arg(short, long)]
-        parent: bool
+        parent: bool,
+
+        /// Keep the generated `.tex` and `.log` files next to the output for debugging
+        #[arg(long)]
+        keep_intermediate: bool,
+
+        /// Run code blocks marked with `{exec}`, even if the config does not enable them
+        #[arg(long, conflicts_with = "no_exec")]
+        exec: bool,
+
+        /// Do not run code blocks marked with `{exec}`, even if the config enables them
+        #[arg(long)]
+        no_exec: bool,
+
+        /// Export the document as Beamer slides, split at `---` rules and level 1 and 2 headings
+        #[arg(long)]
+        slides: bool,
+    },
+    /// Exports a .md file to a Word document, with math as Word equations and the styles of the
+    /// template's `reference.docx`
+    ToDocx {
+        /// Path to Markdown file to render
+        in_file: String,
+
+        /// Path to a template directory, or the name of a template in a `.tatum` directory.
+        /// Defaults to the `template` set in the config file
+        #[arg(short, long)]
+        template: Option<String>,
+
+        /// The path the final `DOCX` file should be saved.
+        /// Defaults to the same path as the `in_file`, but with the `.md` replaced with `.docx`.
+        #[arg(short, long)]
+        out_file: Option<String>,
+
+        /// Whether to create parent directory of output file
+        #[arg(short, long)]
+        parent: bool,
+
+        /// Run code blocks marked with `{exec}`, even if the config does not enable them
+        #[arg(long, conflicts_with = "no_exec")]
+        exec: bool,
+
+        /// Do not run code blocks marked with `{exec}`, even if the config enables them
+        #[arg(long)]
+        no_exec: bool,
+    },
+    /// Exports a .md file to an OpenDocument text file, with the styles of the template's
+    /// `reference.odt`
+    ToOdt {
+        /// Path to Markdown file to render
+        in_file: String,
+
+        /// Path to a template directory, or the name of a template in a `.tatum` directory.
+        /// Defaults to the `template` set in the config file
+        #[arg(short, long)]
+        template: Option<String>,
+
+        /// The path the final `ODT` file should be saved.
+        /// Defaults to the same path as the `in_file`, but with the `.md` replaced with `.odt`.
+        #[arg(short, long)]
+        out_file: Option<String>,
+
+        /// Whether to create parent directory of output file
+        #[arg(short, long)]
+        parent: bool,
+
+        /// Run code blocks marked with `{exec}`, even if the config does not enable them
+        #[arg(long, conflicts_with = "no_exec")]
+        exec: bool,
+
+        /// Do not run code blocks marked with `{exec}`, even if the config enables them
+        #[arg(long)]
+        no_exec: bool,
+    },
+    /// Exports a .md file to an EPUB for e-readers, styled with the template's `style.css`
+    ToEpub {
+        /// Path to Markdown file to render
+        in_file: String,
+
+        /// Path to a template directory, or the name of a template in a `.tatum` directory.
+        /// Defaults to the `template` set in the config file
+        #[arg(short, long)]
+        template: Option<String>,
+
+        /// The path the final `EPUB` file should be saved.
+        /// Defaults to the same path as the `in_file`, but with the `.md` replaced with `.epub`.
+        #[arg(short, long)]
+        out_file: Option<String>,
+
+        /// Whether to create parent directory of output file
+        #[arg(short, long)]
+        parent: bool,
+
+        /// Run code blocks marked with `{exec}`, even if the config does not enable them
+        #[arg(long, conflicts_with = "no_exec")]
+        exec: bool,
+
+        /// Do not run code blocks marked with `{exec}`, even if the config enables them
+        #[arg(long)]
+        no_exec: bool,
+    },
+    /// Exports a .md file to a Typst file, styled with the template's `header.typ`, and optionally
+    /// compiles it to a PDF with typst
+    ToTypst {
+        /// Path to Markdown file to render
+        in_file: String,
+
+        /// Path to a template directory, or the name of a template in a `.tatum` directory.
+        /// Defaults to the `template` set in the config file
+        #[arg(short, long)]
+        template: Option<String>,
+
+        /// The path the final `.typ` file should be saved.
+        /// Defaults to the same path as the `in_file`, but with the `.md` replaced with `.typ`.
+        #[arg(short, long)]
+        out_file: Option<String>,
+
+        /// Whether to create parent directory of output file
+        #[arg(short, long)]
+        parent: bool,
+
+        /// Run code blocks marked with `{exec}`, even if the config does not enable them
+        #[arg(long, conflicts_with = "no_exec")]
+        exec: bool,
+
+        /// Do not run code blocks marked with `{exec}`, even if the config enables them
+        #[arg(long)]
+        no_exec: bool,
+
+        /// Also compile the `.typ` file to a PDF next to it, with the `typst` binary
+        #[arg(long)]
+        pdf: bool,
+    },
+    /// Renders every .md file in a folder to a website, with links between notes pointing to their
+    /// pages, an index page for each folder and a sitemap
+    Build {
+        /// The folder of notes to build
+        dir: PathBuf,
+
+        /// The folder the site should be saved to.
+        /// Defaults to the `html` output directory set in the config file, or `site` in `dir`
+        #[arg(short, long)]
+        out_dir: Option<PathBuf>,
+
+        /// Path to a template directory, or the name of a template in a `.tatum` directory.
+        /// Defaults to the `template` set in the config file
+        #[arg(short, long)]
+        template: Option<String>,
+
+        /// The URL the site is served from, e.g. `https://notes.example.com`, for the sitemap
+        #[arg(long)]
+        base_url: Option<String>,
+
+        /// Run code blocks marked with `{exec}`, even if the config does not enable them
+        #[arg(long, conflicts_with = "no_exec")]
+        exec: bool,
+
+        /// Do not run code blocks marked with `{exec}`, even if the config enables them
+        #[arg(long)]
+        no_exec: bool,
+    },
+    /// Combines the chapters listed in a SUMMARY.md, or under [book] in the config, into one PDF
+    /// or LATEX document, or renders them to an HTML site with previous and next links
+    Book {
+        /// Path to the SUMMARY.md, or a directory containing one. Defaults to the chapters set in
+        /// the config file, or ./SUMMARY.md
+        summary: Option<PathBuf>,
+
+        /// What to build
+        #[arg(short, long, value_enum, default_value_t = BookFormat::Pdf)]
+        format: BookFormat,
+
+        /// The path the book should be saved, a directory for `html`.
+        /// Defaults to `book.pdf`, `book.tex` or `book/` next to the summary
+        #[arg(short, long)]
+        out_file: Option<PathBuf>,
+
+        /// Path to a template directory, or the name of a template in a `.tatum` directory.
+        /// Defaults to the `template` set in the config file
+        #[arg(short, long)]
+        template: Option<String>,
+
+        /// Whether to create parent directory of output file
+        #[arg(short, long)]
+        parent: bool,
+
+        /// Run code blocks marked with `{exec}`, even if the config does not enable them
+        #[arg(long, conflicts_with = "no_exec")]
+        exec: bool,
+
+        /// Do not run code blocks marked with `{exec}`, even if the config enables them
+        #[arg(long)]
+        no_exec: bool,
     },
     /// Renders all files specified in ./.tatum/render-list.json to their specified locations to
     /// HTML
     RenderAll {
-        /// Path to a template directory
+        /// Path to a template directory, or the name of a template in a `.tatum` directory.
+        /// Defaults to the `template` set in the config file
         #[arg(short, long)]
-        template: String,
+        template: Option<String>,
 
         /// Whether to create parent directory of output file
         #[arg(short)]
-        parent: bool
+        parent: bool,
+
+        /// Run code blocks marked with `{exec}`, even if the config does not enable them
+        #[arg(long, conflicts_with = "no_exec")]
+        exec: bool,
+
+        /// Do not run code blocks marked with `{exec}`, even if the config enables them
+        #[arg(long)]
+        no_exec: bool,
     }
 }
 
+#[derive(clap::Subcommand, Debug)]
+enum TemplateArgs {
+    /// Lists all discovered templates with their files
+    List,
+    /// Shows the location and files of a template
+    Show {
+        /// Name or path of the template
+        name: String,
+    },
+    /// Checks that a template's files parse and that its macros.tex is up to date
+    Validate {
+        /// Name or path of the template
+        name: String,
+    },
+    /// Copies a template
+    Copy {
+        /// Template to copy, either a template name, a path, or one of the built-in templates
+        src: String,
+
+        /// Name of the new template, created in the nearest .tatum directory, or a path
+        dst: String,
+    },
+    /// Deletes a template
+    Remove {
+        /// Name of the template, in one of the `.tatum` directories
+        name: String,
+
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
 
 #[tokio::main]
 async fn main() {
@@ -140,13 +492,38 @@ async fn main() {
             port,
             address,
             open,
+            pdf,
+            slides,
             template,
+            exec,
+            no_exec,
         } => {
             if !quiet {
                 tracing_subscriber::fmt::init();
             }
 
-            let app = construct_router(template);
+            // Look for the project config from the file being opened, or the working directory
+            let start = match &open {
+                Some(file) => file.clone(),
+                None => std::env::current_dir().unwrap(),
+            };
+            let mut config = match Config::load(&start) {
+                Ok(config) => config,
+                Err(e) => return eshow(Err(e)),
+            };
+            let template = match config.resolve_template(template) {
+                Ok(template) => template,
+                Err(e) => return eshow(Err(e)),
+            };
+
+            config.set_exec(exec_flag(exec, no_exec));
+
+            let port = port.or(config.serve.port).unwrap_or(0);
+            let address = address
+                .or(config.serve.address.clone())
+                .unwrap_or_else(|| "127.0.0.1".to_string());
+
+            let app = construct_router(template, config);
 
             let listener = tokio::net::TcpListener::bind((address, port))
                 .await
@@ -160,8 +537,9 @@ async fn main() {
 
             if let Some(url) = open {
                 open::that(format!(
-                    "http://{}?path={}",
+                    "http://{}{}?path={}",
                     listener.local_addr().unwrap(),
+                    if pdf { "/pdf" } else if slides { "/slides" } else { "" },
                     url.as_os_str().to_str().unwrap()
                 ))
                 .unwrap();
@@ -170,34 +548,101 @@ async fn main() {
             axum::serve(listener, app).await.unwrap();
         }
         // Render option - async
-        Args::Render { in_file, out_file, template, parent } => {
-            eshow(to_html(in_file, out_file, template, parent).await);
+        Args::Render { in_file, out_file, template, parent, exec, no_exec, slides } => {
+            let exec = exec_flag(exec, no_exec);
+            eshow(to_html(in_file, out_file, template, parent, exec, slides).await);
         }
         // Init option
         Args::Init => { 
             eshow(init());
         }
         // New option
-        Args::New { template_name } => {
-            eshow(new(template_name.to_string()));
+        Args::New { template_name, from } => {
+            eshow(new(template_name.to_string(), from));
+        }
+        // Template management options
+        Args::Template(command) => {
+            eshow(match command {
+                TemplateArgs::List => templates::list(),
+                TemplateArgs::Show { name } => templates::show(name),
+                TemplateArgs::Validate { name } => templates::validate(name),
+                TemplateArgs::Copy { src, dst } => templates::copy(src, dst),
+                TemplateArgs::Remove { name, yes } => templates::remove(name, yes),
+            });
         }
         // CompileMacros option
-        Args::CompileMacros { template } => {
-            eshow(compile_macros(template));
+        Args::CompileMacros { template, dry_run } => {
+            eshow(compile_macros(template, dry_run));
+        }
+        Args::ImportMacros { tex_file, template, dry_run } => {
+            eshow(import_macros(tex_file, template, dry_run));
+        }
+        // Check exits with an error for CI when it finds problems
+        Args::Check { in_file, template } => {
+            if let Err(e) = check::check(in_file, template) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        // Fmt exits with an error for CI when files need formatting
+        Args::Fmt { files, check } => {
+            if let Err(e) = fmt::fmt(files, check) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
         }
         // ToLatex option - compiles to a latex.
         // Used to give more control to user
-        Args::ToLatex { in_file, template, out_file, parent } => {
-            eshow(to_latex(in_file, template, out_file, parent));
+        Args::ToLatex { in_file, template, out_file, parent, exec, no_exec, slides } => {
+            let exec = exec_flag(exec, no_exec);
+            eshow(to_latex(in_file, template, out_file, parent, exec, slides));
         }
         // ToPdf option - compiles to a pdf
-        Args::ToPdf { in_file, template, out_file, parent } => {
-            eshow(to_pdf(in_file, template, out_file, parent));
+        Args::ToPdf {
+            in_file, template, out_file, parent, keep_intermediate, exec, no_exec, slides
+        } => {
+            let exec = exec_flag(exec, no_exec);
+            eshow(to_pdf(
+                in_file, template, out_file, parent, keep_intermediate, exec, slides
+            ));
+        }
+        Args::ToDocx { in_file, template, out_file, parent, exec, no_exec } => {
+            let exec = exec_flag(exec, no_exec);
+            eshow(to_document(DocumentFormat::Docx, in_file, template, out_file, parent, exec));
+        }
+        Args::ToOdt { in_file, template, out_file, parent, exec, no_exec } => {
+            let exec = exec_flag(exec, no_exec);
+            eshow(to_document(DocumentFormat::Odt, in_file, template, out_file, parent, exec));
+        }
+        Args::ToEpub { in_file, template, out_file, parent, exec, no_exec } => {
+            let exec = exec_flag(exec, no_exec);
+            eshow(to_document(DocumentFormat::Epub, in_file, template, out_file, parent, exec));
+        }
+        Args::ToTypst { in_file, template, out_file, parent, exec, no_exec, pdf } => {
+            let exec = exec_flag(exec, no_exec);
+            eshow(to_typst(in_file, template, out_file, parent, exec, pdf));
+        }
+        Args::Build { dir, out_dir, template, base_url, exec, no_exec } => {
+            let exec = exec_flag(exec, no_exec);
+            eshow(site::build(dir, out_dir, template, base_url, exec).await);
+        }
+        Args::Book { summary, format, out_file, template, parent, exec, no_exec } => {
+            let exec = exec_flag(exec, no_exec);
+            eshow(book::build(summary, format, out_file, template, parent, exec).await);
         }
         // RenderAll option - renders all the files in the render-list.json file
-        Args::RenderAll {template, parent} => {
-            eshow(render_all(template, parent).await);
+        Args::RenderAll {template, parent, exec, no_exec} => {
+            let exec = exec_flag(exec, no_exec);
+            eshow(render_all(template, parent, exec).await);
         }
     }
 }
 
+/// The `--exec` or `--no-exec` flag given to a command, if any
+fn exec_flag(exec: bool, no_exec: bool) -> Option<bool> {
+    match (exec, no_exec) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
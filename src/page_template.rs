@@ -1,28 +1,232 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::anyhow;
+use colored::*;
+use serde_json::{Map, Value};
+use include_dir::Dir;
 use tera::{Context, Tera};
 
+use crate::config::{is_path_like, TemplateConfig};
+use crate::templates::builtin_template;
+use crate::utils::err;
+
 pub trait Renderable {
-    fn template_path(&self) -> &str;
+    /// Directory of the template the page is rendered with
+    fn template_dir(&self) -> &str;
     fn context(&self) -> Context;
 
+    /// Name of the file in the template directory to render
+    fn template_name(&self) -> &str {
+        "page.html"
+    }
+
     fn render(&self) -> anyhow::Result<String> {
-        let mut tera = Tera::default();
-        tera.add_template_file(self.template_path(), Some("template"))?;
-        tera.render("template", &self.context()).map_err(Into::into)
+        let mut tera = load_templates(Path::new(self.template_dir()))?;
+
+        // templates made before a page was added to the built-in ones use that page
+        let name = self.template_name();
+        if !tera.get_template_names().any(|template| template == name) {
+            let builtin = builtin_template("default")
+                .and_then(|dir| dir.get_file(name))
+                .and_then(|file| file.contents_utf8());
+            if let Some(content) = builtin {
+                tera.add_raw_template(name, content)?;
+            }
+        }
+
+        tera.render(name, &self.context()).map_err(Into::into)
+    }
+}
+
+/// Loads every `*.html` file of a template, and of the templates it extends, into one `Tera`.
+///
+/// A template's own files are named by their path relative to the template directory, e.g.
+/// `page.html` or `partials/nav.html`. Files of a parent template are also available as
+/// `<parent>/<file>`, so `{% extends "default/page.html" %}` works, and under their plain name
+/// unless the child template has a file with the same name, so parents can include partials
+/// their children override.
+pub fn load_templates(template_dir: &Path) -> anyhow::Result<Tera> {
+    let mut templates: Vec<(String, String)> = Vec::new();
+    let mut names = HashSet::new();
+
+    // own files
+    for (name, content) in html_files(template_dir)? {
+        names.insert(name.clone());
+        templates.push((name, content));
+    }
+
+    // parent files, following the `extends` chain
+    let mut visited = HashSet::from([canonical(template_dir)]);
+    let mut current = TemplateSource::Dir(template_dir.to_path_buf());
+
+    while let Some(parent) = current.extends()? {
+        let parent_name = parent.name();
+        let source = resolve_parent(&current, &parent)?;
+
+        if let TemplateSource::Dir(dir) = &source {
+            if !visited.insert(canonical(dir)) {
+                return Err(anyhow!(err(
+                    format!("Template `{}` extends itself", parent_name).as_str()
+                )));
+            }
+        }
+
+        for (name, content) in source.html_files()? {
+            let prefixed = format!("{}/{}", parent_name, name);
+            if names.insert(prefixed.clone()) {
+                templates.push((prefixed, content.clone()));
+            }
+            if names.insert(name.clone()) {
+                templates.push((name, content));
+            }
+        }
+
+        current = source;
+    }
+
+    let mut tera = Tera::default();
+    tera.add_raw_templates(templates)?;
+    Ok(tera)
+}
+
+/// A template directory on disk, or one of the templates bundled with tatum
+enum TemplateSource {
+    Dir(PathBuf),
+    Builtin(&'static Dir<'static>),
+}
+
+/// The `extends` value of a template
+struct Parent(String);
+
+impl Parent {
+    /// The name parent files are prefixed with
+    fn name(&self) -> String {
+        Path::new(&self.0)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.0.clone())
     }
 }
 
+impl TemplateSource {
+    fn extends(&self) -> anyhow::Result<Option<Parent>> {
+        match self {
+            TemplateSource::Dir(dir) => Ok(TemplateConfig::load(dir)?.extends.map(Parent)),
+            TemplateSource::Builtin(_) => Ok(None),
+        }
+    }
+
+    fn html_files(&self) -> anyhow::Result<Vec<(String, String)>> {
+        match self {
+            TemplateSource::Dir(dir) => html_files(dir),
+            TemplateSource::Builtin(dir) => Ok(dir
+                .files()
+                .filter(|file| file.path().extension().is_some_and(|ext| ext == "html"))
+                .filter_map(|file| {
+                    Some((
+                        file.path().to_string_lossy().into_owned(),
+                        file.contents_utf8()?.to_string(),
+                    ))
+                })
+                .collect()),
+        }
+    }
+}
+
+/// Finds the template `parent` refers to. Names are looked up next to the child template first,
+/// then among the built-in templates.
+fn resolve_parent(child: &TemplateSource, parent: &Parent) -> anyhow::Result<TemplateSource> {
+    if let TemplateSource::Dir(dir) = child {
+        let candidate = if is_path_like(&parent.0) {
+            dir.join(&parent.0)
+        } else {
+            dir.parent().unwrap_or(dir).join(&parent.0)
+        };
+
+        if candidate.is_dir() {
+            return Ok(TemplateSource::Dir(candidate));
+        }
+    }
+
+    builtin_template(&parent.0)
+        .map(TemplateSource::Builtin)
+        .ok_or_else(|| anyhow!(err(
+            format!("Parent template `{}` not found", parent.0).as_str()
+        )))
+}
+
+/// Returns the `*.html` files in a template directory, recursively, named by their relative path
+fn html_files(dir: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    let mut files = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+
+    while let Some(current) = stack.pop() {
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                stack.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "html") {
+                let name = path
+                    .strip_prefix(dir)?
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                files.push((name, fs::read_to_string(&path)?));
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Renders a template's header.tex or header.typ with Tera, giving it access to the template
+/// variables as `vars` and the document's front matter as `meta`.
+///
+/// LaTeX can clash with Tera's syntax, e.g. `{#1}` starts a Tera comment, so headers that do not
+/// parse as templates are used as they are.
+pub fn render_header(header: &str, vars: &Map<String, Value>, meta: &Map<String, Value>) -> String {
+    let mut ctx = Context::new();
+    ctx.insert("vars", vars);
+    ctx.insert("meta", meta);
+
+    Tera::one_off(header, &ctx, false).unwrap_or_else(|e| {
+        println!(
+            "{}",
+            format!("The header could not be rendered with variables, using it as is: {}", e)
+                .yellow()
+        );
+        header.to_string()
+    })
+}
+
 pub struct PageTemplate {
     pub title: String,
     pub body: String,
     pub css: String,
     pub macros: String,
     pub use_websocket: bool,
-    pub template_path: String,
+    pub template_dir: String,
+    /// Variables from vars.toml
+    pub vars: Map<String, Value>,
+    /// The document's front matter
+    pub meta: Map<String, Value>,
+    /// `page.html`, or `slides.html` for slides
+    pub template_name: String,
 }
 
 impl Renderable for PageTemplate {
-    fn template_path(&self) -> &str {
-        &self.template_path
+    fn template_dir(&self) -> &str {
+        &self.template_dir
+    }
+
+    fn template_name(&self) -> &str {
+        &self.template_name
     }
 
     fn context(&self) -> Context {
@@ -43,6 +247,76 @@ impl Renderable for PageTemplate {
         // insert the corresponding katex macros file
         ctx.insert("katex_macros", &self.macros);
 
+        // insert the template variables and front matter
+        ctx.insert("vars", &self.vars);
+        ctx.insert("meta", &self.meta);
+
         ctx
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extends_parent_template() {
+        let root = tempfile::tempdir().unwrap();
+        let base = root.path().join("base");
+        let child = root.path().join("child");
+        fs::create_dir_all(&base).unwrap();
+        fs::create_dir_all(child.join("partials")).unwrap();
+
+        fs::write(
+            base.join("page.html"),
+            "<h1>{% block title %}Base{% endblock title %}</h1>{% include \"footer.html\" %}",
+        )
+        .unwrap();
+        fs::write(base.join("footer.html"), "base footer").unwrap();
+
+        fs::write(child.join("template.toml"), "extends = \"base\"\n").unwrap();
+        fs::write(
+            child.join("page.html"),
+            "{% extends \"base/page.html\" %}{% block title %}{% include \"partials/title.html\" %}{% endblock title %}",
+        )
+        .unwrap();
+        fs::write(child.join("partials/title.html"), "Child").unwrap();
+        fs::write(child.join("footer.html"), "child footer").unwrap();
+
+        let tera = load_templates(&child).unwrap();
+        assert_eq!(
+            tera.render("page.html", &Context::new()).unwrap(),
+            "<h1>Child</h1>child footer"
+        );
+    }
+
+    #[test]
+    fn test_render_header() {
+        let mut vars = Map::new();
+        vars.insert("name".into(), "Alice".into());
+        let mut meta = Map::new();
+        meta.insert("module".into(), "CS141".into());
+
+        assert_eq!(
+            render_header("\\fancyhead[C]{ {{- vars.name }} | {{ meta.module }}}", &vars, &meta),
+            "\\fancyhead[C]{Alice | CS141}"
+        );
+
+        // `{#1}` is a Tera comment, so the header is left alone
+        let header = "\\newcommand{\\foo}[1]{#1}";
+        assert_eq!(render_header(header, &vars, &meta), header);
+    }
+
+    #[test]
+    fn test_extends_cycle() {
+        let root = tempfile::tempdir().unwrap();
+        for (name, parent) in [("a", "b"), ("b", "a")] {
+            let dir = root.path().join(name);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("template.toml"), format!("extends = \"{}\"\n", parent)).unwrap();
+            fs::write(dir.join("page.html"), "page").unwrap();
+        }
+
+        assert!(load_templates(&root.path().join("a")).is_err());
+    }
+}
@@ -0,0 +1,20 @@
+use askama::Template;
+
+/// Page that embeds the PDF preview of a document and reloads when it changes.
+#[derive(Debug, Template)]
+#[template(path = "pdf-preview.html")]
+pub struct PdfPreviewTemplate {
+    pub title: String,
+    pub path: String,
+    pub stamp: u64,
+}
+
+/// Page shown in place of the PDF preview when the export fails.
+#[derive(Debug, Template)]
+#[template(path = "pdf-error.html")]
+pub struct PdfErrorTemplate {
+    pub title: String,
+    pub message: String,
+    pub diagnostics: Vec<String>,
+    pub log: String,
+}
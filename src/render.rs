@@ -7,8 +7,20 @@ use url::Url;
 
 use tokio::fs::{read, read_to_string};
 
+use crate::bibliography::Bibliography;
+use crate::callouts;
+use crate::citations;
+use crate::config::{load_vars, Config, TemplateConfig};
+use crate::crossref::{CrossRefs, Numbering};
+use crate::diagrams;
+use crate::exec;
+use crate::front_matter::parse_front_matter;
+use crate::includes;
+use crate::slides;
 use crate::svg_template::SvgTemplate;
+use crate::theorems::Theorems;
 use crate::page_template::Renderable;
+use crate::utils::escape_html;
 
 fn data_url(data: &[u8], mime_type: &str) -> String {
     let encoded = general_purpose::STANDARD.encode(data);
@@ -41,24 +53,138 @@ fn generate_message_data_url(message: impl AsRef<str>, color: impl AsRef<str>) -
     )
 }
 
+/// How a document is rendered by `render_doc`
+#[derive(Debug, Default, Clone)]
+pub struct RenderOptions {
+    /// Include code for automatically updating the document with a WebSocket connection
+    pub use_websocket: bool,
+    /// Whether to run code blocks marked with `{exec}`, overriding the document's config when set
+    pub exec: Option<bool>,
+    /// Point links to other markdown files at their `.html` pages, written next to this one,
+    /// instead of at the `/?path=` URLs of `tatum serve`
+    pub static_links: bool,
+    /// Links to the previous and next pages, shown below the document
+    pub navigation: Navigation,
+    /// Keep relative image paths instead of inlining the images as data URLs, for pages whose
+    /// images are copied next to them
+    pub link_images: bool,
+    /// Render the document as slides with the template's `slides.html`
+    pub slides: bool,
+}
+
+/// The pages before and after a document, e.g. the chapters of a book
+#[derive(Debug, Default, Clone)]
+pub struct Navigation {
+    pub previous: Option<NavLink>,
+    pub next: Option<NavLink>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NavLink {
+    pub title: String,
+    pub href: String,
+}
+
+impl Navigation {
+    fn to_html(&self) -> String {
+        if self.previous.is_none() && self.next.is_none() {
+            return String::new();
+        }
+
+        let mut html = String::from("<nav class=\"page-nav\">");
+        if let Some(link) = &self.previous {
+            html.push_str(&format!(
+                "<a class=\"page-nav-previous\" href=\"{}\">← {}</a>",
+                escape_html(&link.href),
+                escape_html(&link.title)
+            ));
+        }
+        if let Some(link) = &self.next {
+            html.push_str(&format!(
+                "<a class=\"page-nav-next\" href=\"{}\">{} →</a>",
+                escape_html(&link.href),
+                escape_html(&link.title)
+            ));
+        }
+        html.push_str("</nav>\n");
+        html
+    }
+}
+
 /// Renders a file to an HTML string.
-///
-/// `use_websocket` determines whether to include code for automatically updating the document with a
-/// WebSocket connection.
 pub async fn render_doc(
     path: impl AsRef<Path>, 
-    use_websocket: bool,
+    options: &RenderOptions,
     serve_path: String,
 ) -> anyhow::Result<String> {
     let path = path.as_ref().canonicalize()?;
-
     let file = read_to_string(&path).await?;
+    render_markdown(path, file, options, serve_path).await
+}
 
-    let options = pulldown_cmark::Options::all();
-
-    let parser = pulldown_cmark::Parser::new_ext(file.as_str(), options);
+/// Renders markdown to an HTML string as if it was the content of the file at `path`, which
+/// must be absolute but need not exist.
+pub async fn render_markdown(
+    path: PathBuf,
+    file: String,
+    options: &RenderOptions,
+    serve_path: String,
+) -> anyhow::Result<String> {
+    // unknown citation keys are reported at their lines before the includes are expanded
+    let included = includes::expand(&path, &file)?;
+    let (original, file) = (file, included.source.clone());
+
+    let meta = parse_front_matter(&file)?;
+    let mut config = Config::load(&path)?;
+    config.set_exec(options.exec);
+
+    // Run code blocks and insert their output
+    let exec = config.exec_enabled();
+    let (md_path, exec_config) = (path.clone(), config.exec.clone());
+    let file = tokio::task::spawn_blocking(move || {
+        exec::run_blocks(&md_path, &file, &exec_config, exec)
+    })
+    .await?;
+
+    // Each slide becomes a section of its own
+    let file = if options.slides { slides::to_html_markdown(&file) } else { file };
+
+    // Number theorems, equations, figures and tables, and resolve references to them
+    let numbering = Numbering::for_document(Path::new(&serve_path), &meta)?;
+    let source = Theorems::load(Path::new(&serve_path), numbering)?.to_html_markdown(&file);
+    let events = CrossRefs::collect(&source, numbering).to_html(&source);
+
+    // GitHub and Obsidian callouts become boxes instead of blockquotes
+    let mut events = callouts::to_html(events);
+
+    // Format citations and list the cited entries at the end
+    let template_path = Path::new(&serve_path);
+    if let Some(bibliography) = Bibliography::for_document(&path, template_path, &meta)? {
+        citations::warn_unknown_keys(&path, &original, &included.files, &bibliography);
+        let style = TemplateConfig::load(template_path)?.citation_style;
+        events = citations::to_html(events, &bibliography, style, &meta);
+    }
 
-    let mut events: Vec<_> = parser.collect();
+    // Graphviz and TikZ blocks are drawn as inline SVG, or an error image if they cannot be
+    let diagrams = diagrams::find(&events);
+    if !diagrams.is_empty() {
+        let engine = config.pdf_engine().to_string();
+        let html = tokio::task::spawn_blocking(move || {
+            diagrams
+                .iter()
+                .map(|diagram| match diagram.to_svg(&engine) {
+                    Ok(svg) => format!("<div class=\"diagram\">{}</div>\n", svg),
+                    Err(message) => format!(
+                        "<img class=\"diagram-error\" src=\"{}\" alt=\"{}\">\n",
+                        generate_message_data_url(&message, "red"),
+                        escape_html(&message)
+                    ),
+                })
+                .collect()
+        })
+        .await?;
+        events = diagrams::replace_blocks(events, html);
+    }
 
     // Handle URLs
     for event in events.iter_mut() {
@@ -71,15 +197,16 @@ pub async fn render_doc(
         {
             if dest_url.parse::<Url>().is_ok() {
                 continue;
-            } else if let Ok(image_path) = dest_url.parse::<PathBuf>() {
-                *dest_url = path_to_data_url(image_path.resolve_in(&path))
-                    .await
-                    .unwrap_or(generate_message_data_url("Disk error.", "red"))
-                    .into()
-                // Tag::Link
-            } else {
-                *dest_url = generate_message_data_url("Unable to parse image path.", "red").into();
             }
+            if options.link_images && Path::new(dest_url.as_ref()).is_relative() {
+                continue;
+            }
+
+            let image_path = PathBuf::from(dest_url.as_ref());
+            *dest_url = path_to_data_url(image_path.resolve_in(&path))
+                .await
+                .unwrap_or(generate_message_data_url("Disk error.", "red"))
+                .into()
         }
 
         // Rewrite URLs to open links
@@ -89,28 +216,34 @@ pub async fn render_doc(
             ..
         }) = event
         {
+            if options.static_links {
+                if let Some(page) = static_link(dest_url) {
+                    *dest_url = page.into();
+                }
+                continue;
+            }
+
             // If the link is a valid URL, leave it
-            if !dest_url.parse::<Url>().is_ok() {
-                // Otherwise, try to parse it as a file path
-                if let Ok(file_path) = dest_url.parse::<PathBuf>() {
-                    // If it's a filepath check if it's relative
-                    if let Some(file_path) = file_path.to_str() {
-                        let file_path = if Path::new(file_path).is_relative() {
-                            // If it's relative, join it to the current file
-                            join_and_canonicalize(&file_path, path.clone())
-                                .unwrap_or(file_path.into())
-                        } else {
-                            // Otherwise, use the file path as is
-                            file_path.into()
-                        };
-
-                        // If possible, return a relative path from the cwd
-                        let file_path = match get_relative_path_under_cwd(file_path.clone()) {
-                            Some(path) => path,
-                            None => file_path,
-                        };
-                        *dest_url = format!("/?path={}", file_path.to_str().unwrap()).into()
-                    }
+            if dest_url.parse::<Url>().is_err() {
+                // Otherwise, treat it as a file path
+                let file_path = PathBuf::from(dest_url.as_ref());
+                // If it's a filepath check if it's relative
+                if let Some(file_path) = file_path.to_str() {
+                    let file_path = if Path::new(file_path).is_relative() {
+                        // If it's relative, join it to the current file
+                        join_and_canonicalize(file_path, path.clone())
+                            .unwrap_or(file_path.into())
+                    } else {
+                        // Otherwise, use the file path as is
+                        file_path.into()
+                    };
+
+                    // If possible, return a relative path from the cwd
+                    let file_path = match get_relative_path_under_cwd(file_path.clone()) {
+                        Some(path) => path,
+                        None => file_path,
+                    };
+                    *dest_url = format!("/?path={}", file_path.to_str().unwrap()).into()
                 }
             }
         }
@@ -118,30 +251,54 @@ pub async fn render_doc(
 
     let mut body = String::new();
     pulldown_cmark::html::push_html(&mut body, events.into_iter());
+    body.push_str(&options.navigation.to_html());
     
     let css = read_to_string(format!("{}/style.css", serve_path)).await?;
     let macros = read_to_string(format!("{}/katex-macros.js", serve_path)).await?;
-    let template_path = format!("{}/page.html", serve_path);
 
-    // get the filename of the path to use as document title
-    let title = path.file_name()
-        .and_then(|name| name.to_str()) // convert OsStr to &str
-        .map(|s| s.to_string()) // Convert &str to String
+    // use the title in the front matter, or the filename of the path as document title
+    let title = meta.get("title")
+        .and_then(|title| title.as_str())
+        .map(|title| title.to_string())
+        .or_else(|| {
+            path.file_name()
+                .and_then(|name| name.to_str()) // convert OsStr to &str
+                .map(|s| s.to_string()) // Convert &str to String
+        })
         .unwrap_or_else(|| "Untitled".into()); // error fallback
 
+    let vars = load_vars(Path::new(&serve_path), config.project_root.as_deref())?;
+
     let template = PageTemplate {
         body,
         title,
         css,
         macros,
-        use_websocket,
-        template_path,
+        use_websocket: options.use_websocket,
+        template_dir: serve_path,
+        vars,
+        meta,
+        template_name: if options.slides { "slides.html" } else { "page.html" }.to_string(),
     };
 
     let html = template.render()?;
     Ok(html)
 }
 
+/// The `.html` page of a relative link to a markdown file, keeping its `#fragment`
+fn static_link(dest_url: &str) -> Option<String> {
+    if dest_url.parse::<Url>().is_ok() || dest_url.starts_with('#') {
+        return None;
+    }
+
+    let (file, fragment) = match dest_url.split_once('#') {
+        Some((file, fragment)) => (file, format!("#{}", fragment)),
+        None => (dest_url, String::new()),
+    };
+    let page = file.strip_suffix(".md")?;
+    Some(format!("{}.html{}", page, fragment))
+}
+
 /// Returns a relative path to a file if it is under the working directory
 ///
 /// # Arguments
@@ -192,7 +349,7 @@ fn get_relative_path_under_cwd(file_path: PathBuf) -> Option<PathBuf> {
 /// assert_eq!(rel_to_abspath("../linux.md", current_file), String::from("/home/user/Notes/slipbox/linux.md"));
 /// ```
 ///
-fn join_and_canonicalize(path: &str, current_file: PathBuf) -> std::io::Result<PathBuf> {
+pub fn join_and_canonicalize(path: &str, current_file: PathBuf) -> std::io::Result<PathBuf> {
     let current_dir = current_file
         .parent()
         .ok_or(std::io::Error::new(
@@ -243,7 +400,7 @@ fn truncate_cwd(file_path: &PathBuf) -> Option<PathBuf> {
         file_path
             .strip_prefix(&current_dir)
             .ok()?
-            .into_iter()
+            .iter()
             .map(|p| p.to_owned())
             .collect(),
     )
@@ -292,6 +449,7 @@ fn is_child_path(parent_dir: PathBuf, child: PathBuf) -> bool {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
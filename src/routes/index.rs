@@ -3,13 +3,15 @@ use std::path::PathBuf;
 use axum::{extract::Query, response::Html};
 use resolve_path::PathResolveExt;
 use serde::Deserialize;
-use tracing::info;
+use tracing::{info, warn};
 
 use axum::extract::Extension;
 use std::sync::Arc;
 
-use crate::render::render_doc;
+use crate::error_template::RenderErrorTemplate;
+use crate::render::{render_doc, RenderOptions};
 use crate::routes::AppState;
+use crate::utils::strip_ansi;
 
 #[derive(Debug, Deserialize)]
 pub struct IndexParams {
@@ -22,5 +24,73 @@ pub async fn index(
 ) -> Html<String> {
     info!("Rendering document {}", path.to_string_lossy());
 
-    Html(render_doc(path.resolve(), true, state.template_path.clone()).await.unwrap())
+    let options = RenderOptions {
+        use_websocket: true,
+        exec: state.config.exec.enabled,
+        ..RenderOptions::default()
+    };
+    render_page(path, &options, &state).await
+}
+
+/// Renders a document as slides. The presenter view is the same page, opened with `presenter=1`
+pub async fn slides(
+    Query(IndexParams { path }): Query<IndexParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Html<String> {
+    info!("Rendering slides {}", path.to_string_lossy());
+
+    let options = RenderOptions {
+        use_websocket: true,
+        exec: state.config.exec.enabled,
+        slides: true,
+        ..RenderOptions::default()
+    };
+    render_page(path, &options, &state).await
+}
+
+/// Renders a document, showing the error in the page instead when it fails
+async fn render_page(path: PathBuf, options: &RenderOptions, state: &AppState) -> Html<String> {
+    match render_doc(path.resolve(), options, state.template_path.clone()).await {
+        Ok(page) => Html(page),
+        Err(e) => {
+            warn!("Failed to render {}", path.to_string_lossy());
+
+            let title = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "Untitled".into());
+            Html(
+                RenderErrorTemplate {
+                    title,
+                    message: strip_ansi(&format!("{:#}", e)),
+                }
+                .to_string(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::broadcast;
+
+    use crate::config::Config;
+
+    #[tokio::test]
+    async fn test_invalid_front_matter_shows_error_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.md");
+        std::fs::write(&path, "---\ntitle: [unclosed\n---\n# Notes\n").unwrap();
+
+        let state = AppState {
+            template_path: "templates/default".into(),
+            config: Config::default(),
+            slide_changes: broadcast::channel(1).0,
+        };
+        let Html(page) = render_page(path, &RenderOptions::default(), &state).await;
+
+        assert!(page.contains("Could not render notes.md"));
+        assert!(page.contains("Invalid front matter"));
+    }
 }
@@ -1,28 +1,51 @@
 use axum::{routing::get, Router};
 use axum::extract::Extension;
+use std::path::PathBuf;
 use std::sync::Arc;
+use tokio::sync::broadcast;
 use tower_http::services::ServeDir;
 
+use crate::config::Config;
+
 mod index;
+mod pdf;
 mod watch;
-use index::index;
+use index::{index, slides};
+use pdf::{pdf, pdf_file};
 use watch::watch;
 
 #[derive(Clone)]
 struct AppState {
     template_path: String,
+    config: Config,
+    /// Slide changes sent by the windows showing a deck, passed on to the others
+    slide_changes: broadcast::Sender<SlideChange>,
+}
+
+/// A message from a window showing slides, e.g. `{"slide":3}`
+#[derive(Debug, Clone)]
+struct SlideChange {
+    path: PathBuf,
+    /// The socket that sent it, which does not get it back
+    socket: usize,
+    message: String,
 }
 
-pub fn construct_router(template_path: String) -> Router {
+pub fn construct_router(template_path: String, config: Config) -> Router {
 
     let serve_path = template_path.clone();
 
     let app_state = AppState {
-        template_path
+        template_path,
+        config,
+        slide_changes: broadcast::channel(16).0,
     };
 
     Router::new()
         .route("/", get(index))
+        .route("/slides", get(slides))
+        .route("/pdf", get(pdf))
+        .route("/pdf/file", get(pdf_file))
         .route("/watch", get(watch))
         .layer(Extension(Arc::new(app_state)))
         .nest_service("/static", ServeDir::new(serve_path))
@@ -0,0 +1,233 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use axum::{
+    extract::Query,
+    http::{header, StatusCode},
+    response::{Html, IntoResponse},
+};
+use resolve_path::PathResolveExt;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use axum::extract::Extension;
+use std::sync::Arc;
+
+use crate::commands::build_pdf;
+use crate::config::{Config, TATUM_DIR};
+use crate::latex_log::LatexError;
+use crate::pdf_template::{PdfErrorTemplate, PdfPreviewTemplate};
+use crate::routes::watch::document_inputs;
+use crate::routes::AppState;
+use crate::utils::{cache_dir, strip_ansi, PandocError};
+
+#[derive(Debug, Deserialize)]
+pub struct PdfParams {
+    path: PathBuf,
+}
+
+/// Renders a page previewing the PDF export of a document.
+///
+/// The PDF is rebuilt whenever the document, the files it uses or the template are newer than the
+/// cached copy. Build failures are shown in the page together with pandoc's log.
+pub async fn pdf(
+    Query(PdfParams { path }): Query<PdfParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Html<String> {
+    info!("Rendering PDF preview for {}", path.to_string_lossy());
+
+    let title = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "Untitled".into());
+
+    let md_path = path.resolve().into_owned();
+    let template_path = state.template_path.clone();
+    let config = state.config.clone();
+
+    let result = tokio::task::spawn_blocking(move || {
+        build_cached_pdf(&md_path, &template_path, &config)
+    })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result);
+
+    let page = match result {
+        Ok(stamp) => PdfPreviewTemplate {
+            title,
+            path: path.to_string_lossy().into_owned(),
+            stamp,
+        }
+        .to_string(),
+        Err(e) => {
+            warn!("Failed to build PDF for {}", path.to_string_lossy());
+
+            // Pandoc and LaTeX failures carry their logs, everything else only has a message
+            let (message, diagnostics, log) = if let Some(latex) = e.downcast_ref::<LatexError>() {
+                (
+                    "LaTeX failed to build the PDF".to_string(),
+                    latex.diagnostics.iter().map(|d| d.describe(&title)).collect(),
+                    latex.log.clone(),
+                )
+            } else if let Some(pandoc) = e.downcast_ref::<PandocError>() {
+                (
+                    strip_ansi(&crate::utils::err_pandoc_fails(&pandoc.status)),
+                    Vec::new(),
+                    pandoc.log.clone(),
+                )
+            } else {
+                (strip_ansi(&e.to_string()), Vec::new(), String::new())
+            };
+
+            PdfErrorTemplate {
+                title,
+                message,
+                diagnostics,
+                log,
+            }
+            .to_string()
+        }
+    };
+
+    Html(page)
+}
+
+/// Serves the cached PDF built by the `pdf` route.
+pub async fn pdf_file(
+    Query(PdfParams { path }): Query<PdfParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    let Ok(cached) = cached_pdf_path(&path.resolve(), &state.template_path) else {
+        return (StatusCode::NOT_FOUND, "The PDF has not been built yet.").into_response();
+    };
+
+    match tokio::fs::read(cached).await {
+        Ok(bytes) => ([(header::CONTENT_TYPE, "application/pdf")], bytes).into_response(),
+        Err(_) => (StatusCode::NOT_FOUND, "The PDF has not been built yet.").into_response(),
+    }
+}
+
+/// Returns the location in the cache directory where the preview of `md_path` built with the
+/// template at `template_path` is stored.
+fn cached_pdf_path(md_path: &Path, template_path: &str) -> anyhow::Result<PathBuf> {
+    let md_path = md_path.canonicalize().unwrap_or_else(|_| md_path.to_path_buf());
+    let template_path = Path::new(template_path);
+    let template_path = template_path.canonicalize().unwrap_or_else(|_| template_path.to_path_buf());
+
+    let mut hasher = DefaultHasher::new();
+    md_path.hash(&mut hasher);
+    template_path.hash(&mut hasher);
+
+    Ok(cache_dir()?
+        .join("pdf")
+        .join(format!("{:016x}.pdf", hasher.finish())))
+}
+
+/// Builds the PDF for `md_path` into the cache unless the cached copy is up to date.
+///
+/// Returns the modification time of the PDF in seconds, used to stop browsers reusing an old copy.
+fn build_cached_pdf(md_path: &Path, template_path: &str, config: &Config) -> anyhow::Result<u64> {
+    let pdf_path = cached_pdf_path(md_path, template_path)?;
+
+    if is_stale(&pdf_path, &pdf_sources(md_path, template_path, config)) {
+        info!("Building PDF for {}", md_path.to_string_lossy());
+        std::fs::create_dir_all(pdf_path.parent().unwrap())?;
+        build_pdf(md_path, template_path, &pdf_path, config, false, false)?;
+    }
+
+    Ok(modified(&pdf_path)
+        .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|t| t.as_secs())
+        .unwrap_or_default())
+}
+
+/// Returns the files the PDF of `md_path` is built from: the document with the files it includes
+/// and the images it shows, the template's files and the project's variables
+fn pdf_sources(md_path: &Path, template_path: &str, config: &Config) -> Vec<PathBuf> {
+    let template_path = Path::new(template_path);
+
+    let mut sources = vec![md_path.to_path_buf()];
+    sources.extend(document_inputs(md_path));
+    sources.extend(
+        ["katex-macros.js", "macros.tex", "header.tex", "template.toml", "vars.toml"]
+            .map(|name| template_path.join(name)),
+    );
+    sources.extend(
+        config.project_root.as_ref().map(|root| root.join(TATUM_DIR).join("vars.toml")),
+    );
+    sources
+}
+
+/// Whether the PDF at `pdf_path` is missing or older than any of the files it is built from.
+/// Sources that do not exist are ignored
+fn is_stale(pdf_path: &Path, sources: &[PathBuf]) -> bool {
+    match modified(pdf_path) {
+        Some(built) => sources.iter().any(|src| modified(src).is_some_and(|t| t > built)),
+        None => true,
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::time::Duration;
+
+    fn set_modified(path: &Path, time: SystemTime) {
+        File::options().write(true).open(path).unwrap().set_modified(time).unwrap();
+    }
+
+    #[test]
+    fn test_pdf_is_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let template = root.join("template");
+        fs::create_dir_all(&template).unwrap();
+        fs::create_dir_all(root.join(TATUM_DIR)).unwrap();
+
+        let md_path = root.join("notes.md");
+        fs::write(&md_path, "# Notes\n\n![Graph](graph.png)\n").unwrap();
+        let inputs = [
+            root.join("graph.png"),
+            template.join("header.tex"),
+            root.join(TATUM_DIR).join("vars.toml"),
+        ];
+        for input in &inputs {
+            fs::write(input, "").unwrap();
+        }
+
+        let config = Config { project_root: Some(root.to_path_buf()), ..Config::default() };
+        let sources = pdf_sources(&md_path, template.to_str().unwrap(), &config);
+        let pdf_path = root.join("notes.pdf");
+        assert!(is_stale(&pdf_path, &sources));
+
+        let built = SystemTime::now() - Duration::from_secs(60);
+        for path in sources.iter().filter(|path| path.exists()) {
+            set_modified(path, built - Duration::from_secs(60));
+        }
+        fs::write(&pdf_path, "").unwrap();
+        set_modified(&pdf_path, built);
+        assert!(!is_stale(&pdf_path, &sources));
+
+        for input in &inputs {
+            set_modified(input, SystemTime::now());
+            assert!(is_stale(&pdf_path, &sources), "{:?} is not a source", input);
+            set_modified(input, built - Duration::from_secs(60));
+        }
+    }
+
+    #[test]
+    fn test_cache_depends_on_template() {
+        let md_path = Path::new("notes.md");
+        assert_ne!(
+            cached_pdf_path(md_path, "templates/default").unwrap(),
+            cached_pdf_path(md_path, "templates/bluetot").unwrap()
+        );
+    }
+}
@@ -1,32 +1,56 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use axum::{
     extract::{
         ws::{Message, WebSocket},
-        Query, WebSocketUpgrade,
+        Extension, Query, WebSocketUpgrade,
     },
     response::IntoResponse,
 };
 use notify::{Config, RecommendedWatcher, Watcher};
+use pulldown_cmark::{Event, Options, Parser, Tag};
 use resolve_path::PathResolveExt;
 use serde::Deserialize;
-use tracing::info;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+use url::Url;
+
+use crate::config::TATUM_DIR;
+use crate::includes;
+use crate::routes::{AppState, SlideChange};
 
 #[derive(Debug, Deserialize)]
 pub struct WatchParams {
     /// The path to watch for changes.
     path: PathBuf,
+    /// Whether the client shows slides, and sends and receives the slide it is on
+    #[serde(default)]
+    slides: bool,
 }
 
+/// Gives every socket an id, so slide changes are not sent back to the window they came from
+static NEXT_SOCKET: AtomicUsize = AtomicUsize::new(0);
+
 /// A WebSocket endpoint that watches files for changes and notifies the client when they occur.
-pub async fn watch(ws: WebSocketUpgrade, Query(params): Query<WatchParams>) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_ws(socket, params))
+///
+/// Windows showing slides also send the slide they move to, which is passed on to the other
+/// windows showing the same document, so a presenter view and the audience view stay in sync.
+pub async fn watch(
+    ws: WebSocketUpgrade,
+    Query(params): Query<WatchParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    ws.on_upgrade(|socket| handle_ws(socket, params, state))
 }
 
-async fn handle_ws(mut socket: WebSocket, WatchParams { path }: WatchParams) {
+async fn handle_ws(mut socket: WebSocket, params: WatchParams, state: Arc<AppState>) {
     let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
 
-    let path = path.resolve();
+    let path = params.path.resolve().into_owned();
+    let id = NEXT_SOCKET.fetch_add(1, Ordering::Relaxed);
+    let mut slide_changes = state.slide_changes.subscribe();
 
     let mut watcher =
         RecommendedWatcher::new(move |event| tx.send(event).unwrap(), Config::default()).unwrap();
@@ -35,9 +59,92 @@ async fn handle_ws(mut socket: WebSocket, WatchParams { path }: WatchParams) {
         .watch(&path, notify::RecursiveMode::NonRecursive)
         .unwrap();
 
-    while let Some(_event) = rx.recv().await {
-        info!("Received file change event for {}", path.to_string_lossy());
+    // Files used by the document are watched too, so editing them updates the preview
+    let mut inputs = Vec::new();
+    watch_inputs(&mut watcher, &path, &mut inputs);
+
+    // as are the template and the project's variables, which both previews are built with
+    let project_dir = state.config.project_root.as_ref().map(|root| root.join(TATUM_DIR));
+    for dir in std::iter::once(PathBuf::from(&state.template_path)).chain(project_dir) {
+        if let Err(e) = watcher.watch(&dir, notify::RecursiveMode::NonRecursive) {
+            warn!("Could not watch {}: {}", dir.to_string_lossy(), e);
+        }
+    }
+
+    loop {
+        tokio::select! {
+            event = rx.recv() => {
+                if event.is_none() {
+                    break;
+                }
+                info!("Received file change event for {}", path.to_string_lossy());
+
+                // The document may use other files since it last changed
+                watch_inputs(&mut watcher, &path, &mut inputs);
+
+                if socket.send(Message::Text("".to_string())).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv(), if params.slides => match message {
+                Some(Ok(Message::Text(message))) => {
+                    let change = SlideChange { path: path.clone(), socket: id, message };
+                    let _ = state.slide_changes.send(change);
+                }
+                Some(Ok(_)) => {}
+                _ => break,
+            },
+            change = slide_changes.recv(), if params.slides => match change {
+                Ok(change) if change.path == path && change.socket != id => {
+                    if socket.send(Message::Text(change.message)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            },
+        }
+    }
+}
+
+/// Returns the files a document is built from besides itself: the files it includes and the local
+/// images it shows. A document that cannot be read has none
+pub fn document_inputs(path: &Path) -> Vec<PathBuf> {
+    let Some(included) = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|source| includes::expand(path, &source).ok())
+    else {
+        return Vec::new();
+    };
+
+    // images in included files are relative to the document they end up in
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut files = included.files;
+    for event in Parser::new_ext(&included.source, Options::all()) {
+        if let Event::Start(Tag::Image { dest_url, .. }) = event {
+            if dest_url.parse::<Url>().is_err() {
+                let image = dir.join(dest_url.as_ref());
+                if image.is_file() && !files.contains(&image) {
+                    files.push(image);
+                }
+            }
+        }
+    }
+    files
+}
+
+/// Updates the files watched besides the document to the ones it currently uses
+fn watch_inputs(watcher: &mut RecommendedWatcher, path: &Path, watched: &mut Vec<PathBuf>) {
+    let files = document_inputs(path);
 
-        socket.send(Message::Text("".to_string())).await.unwrap();
+    for file in watched.iter().filter(|file| !files.contains(file)) {
+        let _ = watcher.unwatch(file);
     }
+    for file in files.iter().filter(|file| !watched.contains(file)) {
+        if let Err(e) = watcher.watch(file, notify::RecursiveMode::NonRecursive) {
+            warn!("Could not watch {}: {}", file.to_string_lossy(), e);
+        }
+    }
+
+    *watched = files;
 }
@@ -0,0 +1,216 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+
+use crate::config::Config;
+use crate::front_matter::document_title;
+use crate::render::{render_doc, render_markdown, RenderOptions};
+use crate::utils::{err, escape_html, notify_overwrite, warn};
+
+/// Renders every markdown file under `dir` to a page of `out_dir`, keeping the layout of the
+/// folder, so that links between notes work as relative links to their pages.
+///
+/// Other files are copied as they are, and images link to the copies instead of being inlined.
+/// Folders without an `index.md` get an index page listing their notes and subfolders, and
+/// `sitemap.xml` lists every page.
+pub async fn build(
+    dir: PathBuf,
+    out_dir: Option<PathBuf>,
+    template: Option<String>,
+    base_url: Option<String>,
+    exec: Option<bool>
+) -> Result<()> {
+
+    let dir = dir
+        .canonicalize()
+        .with_context(|| err(format!("Could not find {:?}", dir).as_str()))?;
+    let config = Config::load(&dir)?;
+    let template = config.resolve_template(template)?;
+
+    let out_dir = out_dir
+        .or_else(|| config.output.html.clone())
+        .unwrap_or_else(|| dir.join("site"));
+    if out_dir.join("index.html").exists() {
+        notify_overwrite()?;
+    }
+    fs::create_dir_all(&out_dir)
+        .with_context(|| err(format!("Could not create {:?}", out_dir).as_str()))?;
+    let out_dir = out_dir.canonicalize()?;
+
+    let files = files_in(&dir, &out_dir)?;
+    let notes: BTreeSet<&PathBuf> = files.iter().filter(|file| is_markdown(file)).collect();
+
+    let options = RenderOptions {
+        exec,
+        static_links: true,
+        link_images: true,
+        ..RenderOptions::default()
+    };
+
+    let mut pages = Vec::new();
+    for file in &files {
+        let target = out_dir.join(file);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| err(format!("Could not create {:?}", parent).as_str()))?;
+        }
+
+        if !is_markdown(file) {
+            fs::copy(dir.join(file), &target)
+                .with_context(|| err(format!("Could not copy {:?}", file).as_str()))?;
+            continue;
+        }
+
+        // a broken note should not stop the rest of the site from building
+        let page = file.with_extension("html");
+        match render_doc(dir.join(file), &options, template.clone()).await {
+            Ok(html) => {
+                fs::write(out_dir.join(&page), html)
+                    .with_context(|| err(format!("Could not write {:?}", page).as_str()))?;
+                pages.push(page);
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    // every folder leading to a note gets an index page
+    let folders: BTreeSet<PathBuf> = notes
+        .iter()
+        .flat_map(|note| note.ancestors().skip(1).map(Path::to_path_buf))
+        .collect();
+    for folder in &folders {
+        if notes.contains(&folder.join("index.md")) {
+            continue;
+        }
+
+        let subfolders: Vec<String> = folders
+            .iter()
+            .filter(|other| other.parent() == Some(folder.as_path()))
+            .filter_map(|other| other.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect();
+        let entries: Vec<(String, String)> = notes
+            .iter()
+            .filter(|note| note.parent() == Some(folder.as_path()))
+            .map(|note| {
+                let name = note.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                (document_title(&dir.join(note)), name)
+            })
+            .collect();
+
+        let path = dir.join(folder);
+        let title = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let markdown = index_markdown(&title, &subfolders, &entries);
+        let page = folder.join("index.html");
+        let html =
+            render_markdown(path.join("index.md"), markdown, &options, template.clone()).await?;
+        fs::write(out_dir.join(&page), html)
+            .with_context(|| err(format!("Could not write {:?}", page).as_str()))?;
+        pages.push(page);
+    }
+
+    if base_url.is_none() {
+        println!(
+            "{}",
+            warn("sitemap.xml lists relative URLs. Pass --base-url for search engines to read it")
+        );
+    }
+    pages.sort();
+    fs::write(out_dir.join("sitemap.xml"), sitemap(&pages, base_url.as_deref()))
+        .with_context(|| err("Could not write sitemap.xml"))?;
+
+    println!("Built {} pages to {:?}", pages.len(), out_dir);
+    Ok(())
+}
+
+/// The paths of the files under `dir` relative to it, leaving out hidden files and folders, such
+/// as `.tatum`, and the folder the site is written to
+fn files_in(dir: &Path, out_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+
+    while let Some(current) = stack.pop() {
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+            let hidden = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            if hidden || path == out_dir {
+                continue;
+            }
+
+            if path.is_dir() {
+                stack.push(path);
+            } else if let Ok(rel) = path.strip_prefix(dir) {
+                files.push(rel.to_path_buf());
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+fn is_markdown(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "md")
+}
+
+/// The markdown of a folder's index page, listing its subfolders and notes, given as
+/// `(title, file name)`
+fn index_markdown(title: &str, subfolders: &[String], notes: &[(String, String)]) -> String {
+    let mut markdown = format!("---\ntitle: {:?}\n---\n\n# {}\n\n", title, title);
+    for folder in subfolders {
+        markdown.push_str(&format!("- [{}/](<{}/index.md>)\n", folder, folder));
+    }
+    for (title, file) in notes {
+        markdown.push_str(&format!("- [{}](<{}>)\n", title, file));
+    }
+    markdown
+}
+
+/// A sitemap of the pages, relative to the site. Their URLs are relative too without `base_url`
+fn sitemap(pages: &[PathBuf], base_url: Option<&str>) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for page in pages {
+        let path: Vec<_> = page.iter().map(|part| part.to_string_lossy()).collect();
+        let url = match base_url {
+            Some(base) => format!("{}/{}", base.trim_end_matches('/'), path.join("/")),
+            None => path.join("/"),
+        };
+        xml.push_str(&format!("  <url><loc>{}</loc></url>\n", escape_html(&url)));
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_markdown() {
+        let notes = [("Linear Maps".to_string(), "linear maps.md".to_string())];
+        assert_eq!(
+            index_markdown("algebra", &["groups".to_string()], &notes),
+            "---\ntitle: \"algebra\"\n---\n\n# algebra\n\n\
+             - [groups/](<groups/index.md>)\n- [Linear Maps](<linear maps.md>)\n"
+        );
+    }
+
+    #[test]
+    fn test_sitemap() {
+        let pages = [PathBuf::from("index.html"), PathBuf::from("algebra/groups.html")];
+        assert_eq!(
+            sitemap(&pages, Some("https://notes.example.com/")),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n  \
+             <url><loc>https://notes.example.com/index.html</loc></url>\n  \
+             <url><loc>https://notes.example.com/algebra/groups.html</loc></url>\n\
+             </urlset>\n"
+        );
+    }
+}
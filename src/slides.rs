@@ -0,0 +1,130 @@
+use crate::front_matter::split_front_matter;
+
+/// Arguments making pandoc write Beamer slides, split the same way as the HTML ones
+pub const BEAMER_ARGS: &[&str] = &["--to=beamer", "--slide-level=2"];
+
+/// A slide of a deck, with its speaker notes
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Slide {
+    content: String,
+    notes: String,
+}
+
+impl Slide {
+    fn is_empty(&self) -> bool {
+        self.content.trim().is_empty() && self.notes.trim().is_empty()
+    }
+}
+
+/// Wraps each slide of a document in a `<section class="slide">`, and its speaker notes in an
+/// `<aside class="notes">`, leaving the markdown inside them to be rendered as usual
+pub fn to_html_markdown(source: &str) -> String {
+    let body = split_front_matter(source).1;
+    let mut out = source[..source.len() - body.len()].to_string();
+
+    for slide in split(body) {
+        let content = slide.content.trim_matches('\n');
+        out.push_str(&format!("<section class=\"slide\">\n\n{}\n\n", content));
+        if !slide.notes.trim().is_empty() {
+            out.push_str(&format!(
+                "<aside class=\"notes\">\n\n{}\n\n</aside>\n\n",
+                slide.notes.trim_matches('\n')
+            ));
+        }
+        out.push_str("</section>\n\n");
+    }
+
+    out
+}
+
+/// Splits a document into slides, as pandoc does with `--slide-level=2`: a slide starts at each
+/// level 1 or 2 heading, and at each `---` rule. The content of `::: notes` blocks becomes the
+/// speaker notes of its slide.
+fn split(source: &str) -> Vec<Slide> {
+    let mut slides = vec![Slide::default()];
+    // the backticks or tildes of the code block the line is in
+    let mut fence: Option<String> = None;
+    // how many containers are open within the notes, counting the notes themselves
+    let mut notes_depth = 0;
+    let mut previous_blank = true;
+
+    for line in source.split_inclusive('\n') {
+        let trimmed = line.trim();
+        let slide = slides.last_mut().unwrap();
+        let text = if notes_depth > 0 { &mut slide.notes } else { &mut slide.content };
+
+        if let Some(marker) = &fence {
+            let char = marker.chars().next().unwrap_or('`');
+            if trimmed.starts_with(marker.as_str()) && trimmed.trim_start_matches(char).is_empty() {
+                fence = None;
+            }
+            text.push_str(line);
+            continue;
+        }
+        if let Some(char) = trimmed.chars().next().filter(|c| matches!(c, '`' | '~')) {
+            let marker: String = trimmed.chars().take_while(|c| *c == char).collect();
+            if marker.len() >= 3 {
+                fence = Some(marker);
+                text.push_str(line);
+                continue;
+            }
+        }
+
+        if trimmed.starts_with(":::") {
+            let name = trimmed.trim_start_matches(':').trim();
+            if notes_depth > 0 {
+                notes_depth = if name.is_empty() { notes_depth - 1 } else { notes_depth + 1 };
+                if notes_depth > 0 {
+                    slide.notes.push_str(line);
+                }
+                continue;
+            }
+            if name == "notes" || name == "{.notes}" {
+                notes_depth = 1;
+                continue;
+            }
+        }
+
+        let is_rule = trimmed == "---" && previous_blank;
+        let is_heading = line.starts_with("# ") || line.starts_with("## ");
+        previous_blank = trimmed.is_empty();
+        if notes_depth == 0 && (is_rule || is_heading) {
+            if !slides.last().is_some_and(Slide::is_empty) {
+                slides.push(Slide::default());
+            }
+            if is_rule {
+                continue;
+            }
+        }
+
+        let slide = slides.last_mut().unwrap();
+        let text = if notes_depth > 0 { &mut slide.notes } else { &mut slide.content };
+        text.push_str(line);
+    }
+
+    slides.retain(|slide| !slide.is_empty());
+    slides
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let source = "# Graphs\n\n## Paths\n\nA walk.\n\n::: notes\nMention cycles.\n:::\n\n---\n\n\
+                      ```yaml\n---\n## not a slide\n```\n";
+        let slide = |content: &str, notes: &str| Slide {
+            content: content.to_string(),
+            notes: notes.to_string(),
+        };
+        assert_eq!(
+            split(source),
+            vec![
+                slide("# Graphs\n\n", ""),
+                slide("## Paths\n\nA walk.\n\n\n", "Mention cycles.\n"),
+                slide("\n```yaml\n---\n## not a slide\n```\n", ""),
+            ]
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use std::path::{Path, PathBuf};
+use std::fs;
+use include_dir::{include_dir, Dir};
+use colored::*;
+use inquire::Confirm;
+use anyhow::{Context, Result, anyhow};
+
+use crate::config::{is_template_name, Config};
+use crate::page_template::load_templates;
+use crate::macros::{is_generated, macros_to_tex, parse_katex_macros};
+use crate::utils::{err, extract_templates_to};
+
+/// Templates bundled with tatum, copied into `.tatum` by `tatum init`
+pub static BUILTIN_TEMPLATES: [(&str, Dir<'static>); 2] = [
+    ("default", include_dir!("$CARGO_MANIFEST_DIR/templates/default")),
+    ("bluetot", include_dir!("$CARGO_MANIFEST_DIR/templates/bluetot")),
+];
+
+/// Returns the bundled template called `name`
+pub fn builtin_template(name: &str) -> Option<&'static Dir<'static>> {
+    BUILTIN_TEMPLATES
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, dir)| dir)
+}
+
+/// Lists the templates in every discovered `.tatum` directory, with their files
+pub fn list() -> Result<()> {
+    let config = Config::load(&std::env::current_dir()?)?;
+
+    for tatum_dir in &config.template_dirs {
+        println!("{}", tatum_dir.to_string_lossy().bold());
+
+        for template in templates_in(tatum_dir)? {
+            let name = template.file_name().unwrap().to_string_lossy().into_owned();
+            let files = files_in(&template)?;
+            println!("  {} {}", name.green().bold(), files.join(", ").dimmed());
+        }
+    }
+
+    println!("{}", "Built-in".bold());
+    for (name, dir) in &BUILTIN_TEMPLATES {
+        let files: Vec<_> = dir
+            .files()
+            .map(|file| file.path().to_string_lossy().into_owned())
+            .collect();
+        println!("  {} {}", name.green().bold(), files.join(", ").dimmed());
+    }
+
+    Ok(())
+}
+
+/// Prints the location and files of a template
+pub fn show(name: String) -> Result<()> {
+    let config = Config::load(&std::env::current_dir()?)?;
+    let template_path = config.resolve_template(Some(name))?;
+
+    println!("{}", template_path.bold());
+    for file in files_in(Path::new(&template_path))? {
+        let size = fs::metadata(Path::new(&template_path).join(&file))
+            .map(|m| m.len())
+            .unwrap_or_default();
+        println!("  {} {}", file, format!("({} bytes)", size).dimmed());
+    }
+
+    Ok(())
+}
+
+/// Checks that the files of a template can be used for previewing and exporting
+pub fn validate(name: String) -> Result<()> {
+    let config = Config::load(&std::env::current_dir()?)?;
+    let template_path = config.resolve_template(Some(name.clone()))?;
+    let dir = Path::new(&template_path);
+
+    let checks = [
+        ("page.html and the other *.html files parse", check_page(dir)),
+        ("katex-macros.js parses", check_katex_macros(dir)),
+        ("macros.tex is in sync with katex-macros.js", check_macros_tex(dir)),
+        ("header.tex exists", check_exists(dir, "header.tex")),
+        ("style.css exists", check_exists(dir, "style.css")),
+    ];
+
+    let mut failed = 0;
+    for (description, result) in checks {
+        match result {
+            Ok(()) => println!("{} {}", "✓".green().bold(), description),
+            Err(e) => {
+                failed += 1;
+                println!("{} {}\n    {}", "✗".red().bold(), description, e.to_string().yellow());
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!(err(
+            format!("Template `{}` failed {} check(s)", name, failed).as_str()
+        )));
+    }
+
+    println!("Template `{}` is valid", name);
+    Ok(())
+}
+
+fn check_page(dir: &Path) -> Result<()> {
+    check_exists(dir, "page.html")?;
+
+    // load the same way as for rendering, so partials and parent templates are checked too
+    let tera = load_templates(dir).map_err(|e| match e.downcast_ref::<tera::Error>() {
+        Some(e) => anyhow!("{}", tera_error_chain(e)),
+        None => e,
+    })?;
+    if !tera.get_template_names().any(|name| name == "page.html") {
+        return Err(anyhow!("page.html is not a template"));
+    }
+    Ok(())
+}
+
+fn check_katex_macros(dir: &Path) -> Result<()> {
+    let content = fs::read_to_string(dir.join("katex-macros.js"))
+        .map_err(|e| anyhow!("Could not read katex-macros.js: {}", e))?;
+    let macros = parse_katex_macros(&content)?;
+    if !macros.unknown.is_empty() {
+        return Err(anyhow!("Macros with unknown format: {}", macros.unknown.join(", ")));
+    }
+    Ok(())
+}
+
+fn check_macros_tex(dir: &Path) -> Result<()> {
+    let content = fs::read_to_string(dir.join("katex-macros.js"))
+        .map_err(|e| anyhow!("Could not read katex-macros.js: {}", e))?;
+    let expected = macros_to_tex(&parse_katex_macros(&content)?.macros);
+
+    // without a macros.tex, exports generate the macros from katex-macros.js
+    let Ok(actual) = fs::read_to_string(dir.join("macros.tex")) else {
+        return Ok(());
+    };
+
+    // a hand-written macros.tex is used as is
+    if is_generated(&actual, &content) && actual != expected {
+        return Err(anyhow!("macros.tex is out of date. Run `tatum compile-macros` or delete it"));
+    }
+    Ok(())
+}
+
+fn check_exists(dir: &Path, file: &str) -> Result<()> {
+    if !dir.join(file).is_file() {
+        return Err(anyhow!("{} does not exist", file));
+    }
+    Ok(())
+}
+
+/// Formats a Tera error with its causes, which hold the actual parse error
+fn tera_error_chain(e: &tera::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = std::error::Error::source(e);
+    while let Some(cause) = source {
+        message.push_str(&format!("\n    {}", cause));
+        source = cause.source();
+    }
+    message
+}
+
+/// Copies the template `src` to `dst`.
+///
+/// `src` can be a bundled template, a template name or a path. `dst` is created in the
+/// project's `.tatum` directory unless it is a path.
+pub fn copy(src: String, dst: String) -> Result<()> {
+    let config = Config::load(&std::env::current_dir()?)?;
+    let dst = destination(&config, &dst)?;
+
+    if dst.exists() {
+        return Err(anyhow!(err(
+            format!("{} directory already exists.", dst.to_string_lossy()).as_str()
+        )));
+    }
+
+    create_from(&config, &src, &dst)?;
+
+    println!("Copied `{}` to {}", src, dst.to_string_lossy());
+    Ok(())
+}
+
+/// Deletes a template after asking for confirmation
+pub fn remove(name: String, yes: bool) -> Result<()> {
+    let config = Config::load(&std::env::current_dir()?)?;
+    let template_path = removable(&config, &name)?;
+    let template_path = template_path.to_string_lossy();
+
+    if !yes {
+        let ans = Confirm::new(format!("Delete {} and all of its files?", template_path).as_str())
+            .with_default(false)
+            .prompt();
+
+        if !matches!(ans, Ok(true)) {
+            return Err(anyhow!("{}", "Exiting..".red()));
+        }
+    }
+
+    fs::remove_dir_all(template_path.as_ref())
+        .with_context(|| err(format!("Could not remove {}", template_path).as_str()))?;
+
+    println!("Removed {}", template_path);
+    Ok(())
+}
+
+/// Finds the template `remove` may delete: only a template given by name, in one of the
+/// discovered `.tatum` directories, so that a path or `.` can never delete anything else
+fn removable(config: &Config, name: &str) -> Result<PathBuf> {
+    if !is_template_name(name) {
+        return Err(anyhow!(err(format!(
+            "`{}` is not a template name. Only templates in a .tatum directory can be removed",
+            name
+        ).as_str())));
+    }
+
+    config
+        .template_dirs
+        .iter()
+        .map(|dir| dir.join(name))
+        .find(|path| fs::symlink_metadata(path).is_ok_and(|meta| meta.is_dir()))
+        .ok_or_else(|| anyhow!(err(
+            format!("Template `{}` not found in a .tatum directory", name).as_str()
+        )))
+}
+
+/// Fills `dst` with the files of the template `src`.
+///
+/// Templates found in the `.tatum` directories take precedence over the bundled ones, so a
+/// project can shadow `default` with its own version.
+pub fn create_from(config: &Config, src: &str, dst: &Path) -> Result<()> {
+    match config.resolve_template(Some(src.to_string())) {
+        Ok(src_path) => copy_dir(Path::new(&src_path), dst)
+            .with_context(|| err(format!("Could not copy template `{}`", src).as_str())),
+        Err(e) => {
+            let builtin = builtin_template(src).ok_or(e)?;
+            fs::create_dir_all(dst)?;
+            extract_templates_to(builtin, dst)
+                .with_context(|| err(format!("Could not copy template `{}`", src).as_str()))
+        }
+    }
+}
+
+/// Resolves where a new template called `name` should be created
+pub fn destination(config: &Config, name: &str) -> Result<PathBuf> {
+    if name.contains('/') || name.contains('\\') {
+        Ok(PathBuf::from(name))
+    } else {
+        Ok(config.project_dir()?.join(name))
+    }
+}
+
+/// Returns the template directories in a `.tatum` directory
+fn templates_in(tatum_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut templates: Vec<_> = fs::read_dir(tatum_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    templates.sort();
+    Ok(templates)
+}
+
+/// Returns the paths of all files in a directory relative to it, recursively
+fn files_in(dir: &Path) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+
+    while let Some(current) = stack.pop() {
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                stack.push(path);
+            } else if let Ok(rel) = path.strip_prefix(dir) {
+                files.push(rel.to_string_lossy().into_owned());
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Recursively copies the directory `src` to `dst`
+fn copy_dir(src: &Path, dst: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TATUM_DIR;
+
+    #[test]
+    fn test_new_copy_list_and_remove() {
+        let root = tempfile::tempdir().unwrap();
+        let tatum = root.path().join(TATUM_DIR);
+        fs::create_dir_all(&tatum).unwrap();
+        let config = Config::load_from(root.path(), Some(&root.path().join("home"))).unwrap();
+
+        // `new --from` a built-in template, then `copy` it under another name
+        let notes = destination(&config, "notes").unwrap();
+        create_from(&config, "default", &notes).unwrap();
+        let slides = destination(&config, "slides").unwrap();
+        create_from(&config, "notes", &slides).unwrap();
+
+        assert_eq!(templates_in(&tatum).unwrap(), vec![notes.clone(), slides.clone()]);
+        assert_eq!(files_in(&notes).unwrap(), files_in(&slides).unwrap());
+        assert!(files_in(&slides).unwrap().contains(&"page.html".to_string()));
+
+        assert_eq!(removable(&config, "slides").unwrap(), slides);
+        for name in [".", "..", "", "notes/..", "./notes", "missing"] {
+            assert!(removable(&config, name).is_err(), "{:?} could be removed", name);
+        }
+        let path = notes.to_string_lossy();
+        assert!(removable(&config, &path).is_err());
+    }
+}
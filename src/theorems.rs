@@ -0,0 +1,543 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag};
+use serde::Deserialize;
+
+use crate::config::TemplateConfig;
+use crate::crossref::Numbering;
+use crate::utils::{err, escape_html};
+
+/// The amsthm style of an environment, which sets how its title and body are typeset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TheoremStyle {
+    /// Bold title and italic body, for theorems and lemmas
+    Plain,
+    /// Bold title and upright body, for definitions and examples
+    Definition,
+    /// Italic title and upright body, for remarks
+    Remark,
+}
+
+impl TheoremStyle {
+    fn name(self) -> &'static str {
+        match self {
+            TheoremStyle::Plain => "plain",
+            TheoremStyle::Definition => "definition",
+            TheoremStyle::Remark => "remark",
+        }
+    }
+}
+
+/// An environment as set in `[theorems.<name>]` of template.toml. Unset values are taken from
+/// the built-in environment of the same name
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct TheoremConfig {
+    /// The title blocks start with, e.g. `Theorem`
+    pub title: Option<String>,
+    pub style: Option<TheoremStyle>,
+    pub numbered: Option<bool>,
+    /// Another environment whose numbers are shared, e.g. lemmas counting along with theorems
+    pub counter: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Environment {
+    pub title: String,
+    pub style: TheoremStyle,
+    pub numbered: bool,
+    pub counter: Option<String>,
+}
+
+/// Built-in environments, as name, title, style, whether they are numbered and shared counter
+static BUILTIN: &[(&str, &str, TheoremStyle, bool, Option<&str>)] = &[
+    ("theorem", "Theorem", TheoremStyle::Plain, true, None),
+    ("lemma", "Lemma", TheoremStyle::Plain, true, Some("theorem")),
+    ("proposition", "Proposition", TheoremStyle::Plain, true, Some("theorem")),
+    ("corollary", "Corollary", TheoremStyle::Plain, true, Some("theorem")),
+    ("definition", "Definition", TheoremStyle::Definition, true, None),
+    ("example", "Example", TheoremStyle::Definition, true, None),
+    ("remark", "Remark", TheoremStyle::Remark, false, None),
+    ("proof", "Proof", TheoremStyle::Remark, false, None),
+];
+
+/// The theorem environments of a template and how they are numbered
+#[derive(Debug, Clone)]
+pub struct Theorems {
+    pub environments: BTreeMap<String, Environment>,
+    pub numbering: Numbering,
+}
+
+/// A `::: name "Title" {#id}` line opening a container
+#[derive(Debug, Clone, PartialEq)]
+struct Opening<'a> {
+    name: &'a str,
+    title: Option<&'a str>,
+    id: Option<&'a str>,
+}
+
+impl Theorems {
+    /// Loads the environments of a template, with the built-in ones as defaults
+    pub fn load(template_path: &Path, numbering: Numbering) -> Result<Theorems> {
+        let config = TemplateConfig::load(template_path)?;
+        Theorems::new(&config.theorems, numbering)
+    }
+
+    pub fn new(config: &BTreeMap<String, TheoremConfig>, numbering: Numbering) -> Result<Theorems> {
+        let mut environments: BTreeMap<String, Environment> = BUILTIN
+            .iter()
+            .map(|(name, title, style, numbered, counter)| {
+                let environment = Environment {
+                    title: title.to_string(),
+                    style: *style,
+                    numbered: *numbered,
+                    counter: counter.map(str::to_string),
+                };
+                (name.to_string(), environment)
+            })
+            .collect();
+
+        for (name, settings) in config {
+            let builtin = environments.get(name).cloned();
+            let environment = Environment {
+                title: settings
+                    .title
+                    .clone()
+                    .or_else(|| builtin.as_ref().map(|b| b.title.clone()))
+                    .unwrap_or_else(|| capitalize(name)),
+                style: settings
+                    .style
+                    .or(builtin.as_ref().map(|b| b.style))
+                    .unwrap_or(TheoremStyle::Plain),
+                numbered: settings
+                    .numbered
+                    .or(builtin.as_ref().map(|b| b.numbered))
+                    .unwrap_or(true),
+                counter: settings.counter.clone().or(builtin.and_then(|b| b.counter)),
+            };
+            environments.insert(name.clone(), environment);
+        }
+
+        // shared counters must belong to a numbered environment with a counter of its own
+        for (name, environment) in &environments {
+            let Some(counter) = &environment.counter else { continue };
+            let valid = environments
+                .get(counter)
+                .is_some_and(|other| other.numbered && other.counter.is_none());
+            if !valid {
+                return Err(anyhow!(err(format!(
+                    "Theorem environment `{}` shares the counter of `{}`, which is not a numbered \
+                     environment with its own counter",
+                    name, counter
+                ).as_str())));
+            }
+        }
+
+        Ok(Theorems { environments, numbering })
+    }
+
+    /// Turns the containers of a document into `<div class="theorem">` blocks with numbered titles
+    pub fn to_html_markdown(&self, source: &str) -> String {
+        self.expand_titled(
+            source,
+            |opening, environment, title| {
+                let id = opening
+                    .id
+                    .map(|id| format!(" id=\"{}\"", escape_html(id)))
+                    .unwrap_or_default();
+                format!(
+                    "<div class=\"theorem theorem-{} {}\"{}>\n\n\
+                     <span class=\"theorem-title\">{}.</span>\n",
+                    environment.style.name(),
+                    escape_html(opening.name),
+                    id,
+                    escape_html(title)
+                )
+            },
+            |opening, _| {
+                if opening.name == "proof" {
+                    "\n<p class=\"qed\">∎</p>\n</div>\n".to_string()
+                } else {
+                    "\n</div>\n".to_string()
+                }
+            },
+        )
+    }
+
+    /// Turns the containers of a document into divs for pandoc's Word and OpenDocument writers,
+    /// starting with a numbered title in bold, or in italics for the remark style. Each div has
+    /// the environment's title as `custom-style`, so a reference document can style its body.
+    pub fn to_word_markdown(&self, source: &str) -> String {
+        self.expand_titled(
+            source,
+            |opening, environment, title| {
+                let id = opening.id.map(|id| format!("#{} ", id)).unwrap_or_default();
+                let emphasis = match environment.style {
+                    TheoremStyle::Remark => "*",
+                    _ => "**",
+                };
+                format!(
+                    "::: {{{}.{} custom-style=\"{}\"}}\n{}{}.{} ",
+                    id, opening.name, environment.title, emphasis, title, emphasis
+                )
+            },
+            |opening, _| {
+                if opening.name == "proof" {
+                    "\n∎\n:::\n".to_string()
+                } else {
+                    ":::\n".to_string()
+                }
+            },
+        )
+    }
+
+    /// Turns the containers of a document into blocks for the Typst export, written as raw Typst
+    /// around their content, which starts with the title as in `to_word_markdown`
+    pub fn to_typst_markdown(&self, source: &str) -> String {
+        self.expand_titled(
+            source,
+            |_, environment, title| {
+                let emphasis = match environment.style {
+                    TheoremStyle::Remark => "*",
+                    _ => "**",
+                };
+                format!(
+                    "```{{=typst}}\n#block(width: 100%, above: 1.2em, below: 1.2em)[\n```\n\
+                     {}{}.{} ",
+                    emphasis, title, emphasis
+                )
+            },
+            |opening, _| {
+                let qed = if opening.name == "proof" { "\n#h(1fr) ∎" } else { "" };
+                let label = opening.id.map(|id| format!(" <{}>", id)).unwrap_or_default();
+                format!("```{{=typst}}{}\n]{}\n```\n", qed, label)
+            },
+        )
+    }
+
+    /// Like `expand`, giving `open` the title of the container: the environment's title with its
+    /// number and the name given to the container, e.g. `Theorem 2 (Lagrange)`
+    fn expand_titled<'a>(
+        &self,
+        source: &'a str,
+        mut open: impl FnMut(&Opening<'a>, &Environment, &str) -> String,
+        close: impl Fn(&Opening<'a>, &Environment) -> String,
+    ) -> String {
+        let sections = section_starts(source);
+        let mut counters: BTreeMap<String, usize> = BTreeMap::new();
+        let mut section = 0;
+
+        self.expand(
+            source,
+            |opening, environment, offset| {
+                // numbers restart at each top level heading when numbered per section
+                let current = sections.iter().filter(|start| **start < offset).count();
+                if current != section {
+                    section = current;
+                    if self.numbering == Numbering::Section {
+                        counters.clear();
+                    }
+                }
+
+                let mut title = environment.title.clone();
+                if opening.name == "proof" {
+                    // like amsthm, the title of a proof replaces "Proof"
+                    title = opening.title.unwrap_or(&environment.title).to_string();
+                } else {
+                    if environment.numbered {
+                        let counter = environment.counter.as_deref().unwrap_or(opening.name);
+                        let count = counters.entry(counter.to_string()).or_default();
+                        *count += 1;
+                        match self.numbering {
+                            Numbering::Document => title.push_str(&format!(" {}", count)),
+                            Numbering::Section => {
+                                title.push_str(&format!(" {}.{}", section, count))
+                            }
+                        }
+                    }
+                    if let Some(name) = opening.title {
+                        title.push_str(&format!(" ({})", name));
+                    }
+                }
+
+                open(opening, environment, &title)
+            },
+            close,
+        )
+    }
+
+    /// Turns the containers of a document into amsthm environments for pandoc
+    pub fn to_latex_markdown(&self, source: &str) -> String {
+        self.expand(
+            source,
+            |opening, _, _| {
+                let title = opening.title.map(|title| format!("[{}]", title)).unwrap_or_default();
+                let label = opening.id.map(|id| format!("\\label{{{}}}", id)).unwrap_or_default();
+                format!("```{{=latex}}\n\\begin{{{}}}{}{}\n```\n", opening.name, title, label)
+            },
+            |opening, _| format!("```{{=latex}}\n\\end{{{}}}\n```\n", opening.name),
+        )
+    }
+
+    /// LaTeX defining the environments with amsthm. Environments the template's header.tex
+    /// defines already are left alone
+    pub fn latex_preamble(&self) -> String {
+        let mut preamble = String::from("\\usepackage{amsthm}\n");
+
+        // environments with their own counter come first, as the others refer to them
+        let mut environments: Vec<_> = self
+            .environments
+            .iter()
+            .filter(|(name, _)| name.as_str() != "proof")
+            .collect();
+        environments.sort_by_key(|(_, environment)| environment.counter.is_some());
+
+        for style in [TheoremStyle::Plain, TheoremStyle::Definition, TheoremStyle::Remark] {
+            let mut definitions = environments.iter().filter(|(_, e)| e.style == style).peekable();
+            if definitions.peek().is_none() {
+                continue;
+            }
+            preamble.push_str(&format!("\\theoremstyle{{{}}}\n", style.name()));
+
+            for (name, environment) in definitions {
+                let definition = match (&environment.counter, environment.numbered) {
+                    (_, false) => format!("\\newtheorem*{{{}}}{{{}}}", name, environment.title),
+                    (Some(counter), true) => {
+                        format!("\\newtheorem{{{}}}[{}]{{{}}}", name, counter, environment.title)
+                    }
+                    (None, true) => {
+                        let within = match self.numbering {
+                            Numbering::Document => "",
+                            Numbering::Section => "[section]",
+                        };
+                        format!("\\newtheorem{{{}}}{{{}}}{}", name, environment.title, within)
+                    }
+                };
+                preamble.push_str(&format!(
+                    "\\ifcsname {}\\endcsname\\else{}\\fi\n",
+                    name, definition
+                ));
+            }
+        }
+
+        preamble
+    }
+
+    /// Replaces the opening and closing lines of the containers of known environments.
+    ///
+    /// `open` gets the offset of the opening line, and containers of other names, as well as
+    /// lines in code blocks, are left as they are.
+    fn expand<'a>(
+        &self,
+        source: &'a str,
+        mut open: impl FnMut(&Opening<'a>, &Environment, usize) -> String,
+        close: impl Fn(&Opening<'a>, &Environment) -> String,
+    ) -> String {
+        let mut out = String::with_capacity(source.len());
+        let mut stack: Vec<(usize, Option<Opening<'a>>)> = Vec::new();
+        let mut fence: Option<&str> = None;
+        let mut offset = 0;
+
+        for line in source.split_inclusive('\n') {
+            let start = offset;
+            offset += line.len();
+            let trimmed = line.trim();
+
+            if let Some(marker) = fence {
+                if trimmed.starts_with(marker) {
+                    fence = None;
+                }
+                out.push_str(line);
+                continue;
+            }
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                fence = Some(&trimmed[..3]);
+                out.push_str(line);
+                continue;
+            }
+
+            let colons = trimmed.chars().take_while(|c| *c == ':').count();
+            if colons < 3 {
+                out.push_str(line);
+                continue;
+            }
+
+            // a line of colons closes the innermost container at least as long
+            if colons == trimmed.len() {
+                match stack.iter().rposition(|(length, _)| *length <= colons) {
+                    Some(index) => {
+                        let (_, opening) = stack.remove(index);
+                        match opening {
+                            Some(opening) => {
+                                out.push_str(&close(&opening, &self.environments[opening.name]))
+                            }
+                            None => out.push_str(line),
+                        }
+                    }
+                    None => out.push_str(line),
+                }
+                continue;
+            }
+
+            let opening = parse_opening(&trimmed[colons..]);
+            match opening.filter(|opening| self.environments.contains_key(opening.name)) {
+                Some(opening) => {
+                    out.push_str(&open(&opening, &self.environments[opening.name], start));
+                    stack.push((colons, Some(opening)));
+                }
+                None => {
+                    out.push_str(line);
+                    stack.push((colons, None));
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// Parses the rest of a `::: name "Title" {#id}` line
+fn parse_opening(rest: &str) -> Option<Opening<'_>> {
+    let rest = rest.trim();
+    let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+        .unwrap_or(rest.len());
+    let name = &rest[..end];
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut rest = rest[end..].trim();
+    let mut title = None;
+    if let Some(quoted) = rest.strip_prefix('"') {
+        let close = quoted.find('"')?;
+        title = Some(&quoted[..close]);
+        rest = quoted[close + 1..].trim();
+    }
+
+    let mut id = None;
+    if let Some(attribute) = rest.strip_prefix("{#").and_then(|a| a.strip_suffix('}')) {
+        id = Some(attribute.trim());
+        rest = "";
+    }
+
+    rest.is_empty().then_some(Opening { name, title, id })
+}
+
+/// Offsets of the top level headings of a document
+fn section_starts(source: &str) -> Vec<usize> {
+    Parser::new_ext(source, Options::all())
+        .into_offset_iter()
+        .filter(|(event, _)| {
+            matches!(event, Event::Start(Tag::Heading { level: HeadingLevel::H1, .. }))
+        })
+        .map(|(_, range)| range.start)
+        .collect()
+}
+
+fn capitalize(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOC: &str = "\
+# Groups
+
+::: definition \"Group\"
+A set with an operation.
+:::
+
+::: theorem \"Lagrange\" {#thm:lagrange}
+The order of a subgroup divides the order of the group.
+:::
+
+::: lemma
+Cosets partition the group.
+:::
+
+::: proof
+Obvious.
+:::
+
+```
+::: theorem
+```
+";
+
+    #[test]
+    fn test_to_html_markdown() {
+        let theorems = Theorems::new(&BTreeMap::new(), Numbering::Document).unwrap();
+        let markdown = theorems.to_html_markdown(DOC);
+
+        assert!(markdown.contains("<span class=\"theorem-title\">Definition 1 (Group).</span>"));
+        assert!(markdown
+            .contains("<div class=\"theorem theorem-plain theorem\" id=\"thm:lagrange\">"));
+        assert!(markdown.contains("<span class=\"theorem-title\">Theorem 1 (Lagrange).</span>"));
+        // lemmas share the counter of theorems
+        assert!(markdown.contains("<span class=\"theorem-title\">Lemma 2.</span>"));
+        assert!(markdown.contains("<span class=\"theorem-title\">Proof.</span>"));
+        assert!(markdown.contains("<p class=\"qed\">∎</p>"));
+        // containers in code blocks are left alone
+        assert!(markdown.contains("```\n::: theorem\n```"));
+
+        let sections = Theorems::new(&BTreeMap::new(), Numbering::Section).unwrap();
+        assert!(sections.to_html_markdown(DOC).contains("Theorem 1.1 (Lagrange)."));
+
+        // titles and ids written by the author are escaped
+        let source = "::: theorem \"<b>A & B</b>\" {#a\"b}\nText\n:::\n";
+        let markdown = theorems.to_html_markdown(source);
+        assert!(markdown.contains(" id=\"a&quot;b\">"));
+        assert!(markdown.contains("Theorem 1 (&lt;b&gt;A &amp; B&lt;/b&gt;).</span>"));
+    }
+
+    #[test]
+    fn test_to_latex_markdown() {
+        let theorems = Theorems::new(&BTreeMap::new(), Numbering::Document).unwrap();
+        let markdown = theorems.to_latex_markdown(DOC);
+
+        assert!(markdown
+            .contains("```{=latex}\n\\begin{theorem}[Lagrange]\\label{thm:lagrange}\n```\n"));
+        assert!(markdown.contains("```{=latex}\n\\end{proof}\n```\n"));
+
+        let preamble = theorems.latex_preamble();
+        assert!(preamble.contains("\\newtheorem{theorem}{Theorem}"));
+        assert!(preamble.contains("\\newtheorem{lemma}[theorem]{Lemma}"));
+        assert!(preamble.contains("\\newtheorem*{remark}{Remark}"));
+        assert!(!preamble.contains("{proof}"));
+    }
+
+    #[test]
+    fn test_to_word_markdown() {
+        let theorems = Theorems::new(&BTreeMap::new(), Numbering::Document).unwrap();
+        let markdown = theorems.to_word_markdown(DOC);
+
+        assert!(markdown.contains(
+            "::: {#thm:lagrange .theorem custom-style=\"Theorem\"}\n\
+             **Theorem 1 (Lagrange).** The order of a subgroup divides the order of the group.\n:::\n"
+        ));
+        assert!(markdown.contains("*Proof.* Obvious.\n\n∎\n:::\n"));
+    }
+
+    #[test]
+    fn test_template_config() {
+        let config: BTreeMap<String, TheoremConfig> = toml::from_str(
+            "[lemma]\ncounter = \"definition\"\n[conjecture]\nstyle = \"remark\"\n",
+        )
+        .unwrap();
+        let theorems = Theorems::new(&config, Numbering::Document).unwrap();
+        assert_eq!(theorems.environments["lemma"].counter.as_deref(), Some("definition"));
+        assert_eq!(theorems.environments["conjecture"].title, "Conjecture");
+
+        let config: BTreeMap<String, TheoremConfig> =
+            toml::from_str("[lemma]\ncounter = \"remark\"\n").unwrap();
+        assert!(Theorems::new(&config, Numbering::Document).is_err());
+    }
+}
@@ -0,0 +1,679 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::Path;
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, LinkType, Tag, TagEnd};
+use serde_json::{Map, Value};
+
+use crate::bibliography::Bibliography;
+use crate::citations::{self, CitationStyle};
+use crate::crossref::{self, CrossRefs, Kind, Numbering};
+use crate::macros::Macro;
+use crate::typst_math;
+use crate::utils::relative_path;
+
+/// A markdown document being written as Typst markup.
+///
+/// The markdown has its theorem containers and callouts already turned into raw `{=typst}`
+/// blocks and its diagrams into images, as for the other exports.
+pub struct Document<'a> {
+    source: &'a str,
+    events: Vec<(Event<'a>, Range<usize>)>,
+    refs: &'a CrossRefs,
+    /// The template's KaTeX macros, expanded in math
+    macros: &'a [Macro],
+    bibliography: Option<&'a Bibliography>,
+    /// The directory of the markdown file, which images are relative to
+    md_dir: &'a Path,
+    /// The directory of the `.typ` file, which images are made relative to
+    out_dir: &'a Path,
+    /// The labels in the output, which links to `#id` can point to
+    labels: HashSet<String>,
+    /// Where the definition of each footnote is in `events`
+    footnotes: HashMap<String, Range<usize>>,
+}
+
+impl<'a> Document<'a> {
+    pub fn new(
+        source: &'a str,
+        refs: &'a CrossRefs,
+        macros: &'a [Macro],
+        bibliography: Option<&'a Bibliography>,
+        md_dir: &'a Path,
+        out_dir: &'a Path,
+    ) -> Document<'a> {
+        let events = crossref::parse(source);
+
+        let mut labels: HashSet<String> =
+            refs.targets.iter().filter_map(|target| target.id.clone()).collect();
+        let mut footnotes = HashMap::new();
+        for (index, (event, _)) in events.iter().enumerate() {
+            match event {
+                Event::Start(Tag::Heading { id: Some(id), .. }) if is_label(id) => {
+                    labels.insert(id.to_string());
+                }
+                Event::Start(Tag::FootnoteDefinition(name)) => {
+                    let end = end_of(&events, index);
+                    footnotes.insert(name.to_string(), index + 1..end);
+                }
+                _ => {}
+            }
+        }
+
+        Document { source, events, refs, macros, bibliography, md_dir, out_dir, labels, footnotes }
+    }
+
+    /// Writes the body of the document as Typst markup
+    pub fn to_typst(&self) -> String {
+        let mut writer = Writer::new(self);
+        writer.events(0..self.events.len());
+        writer.out.trim_end().to_string() + "\n"
+    }
+
+    /// Writes some of the events on their own, e.g. a caption or a footnote
+    fn render(&self, range: Range<usize>) -> String {
+        let mut writer = Writer::new(self);
+        writer.events(range);
+        writer.out.trim().to_string()
+    }
+
+    fn math(&self, math: &str) -> String {
+        let math = self.refs.resolve_math(&crossref::without_label(math));
+        typst_math::to_typst(&math, self.macros)
+    }
+
+    /// The path of an image relative to the `.typ` file, or `None` for remote images, which Typst
+    /// cannot embed
+    fn image_path(&self, dest: &str) -> Option<String> {
+        if dest.contains("://") || dest.starts_with("data:") {
+            return None;
+        }
+        let path = relative_path(self.out_dir, &self.md_dir.join(dest));
+        Some(path.to_string_lossy().into_owned())
+    }
+
+    /// The table caption next to the table at `range`, and where its paragraph is in `events`
+    fn table_caption(&self, range: &Range<usize>) -> Option<(&crossref::Target, Range<usize>)> {
+        let target = self.refs.targets.iter().filter(|t| t.kind == Kind::Table).find(|t| {
+            let before = t.range.end <= range.start
+                && self.source[t.range.end..range.start].trim().is_empty();
+            let after = t.range.start >= range.end
+                && self.source[range.end..t.range.start].trim().is_empty();
+            before || after
+        })?;
+        let start = self.events.iter().position(|(event, r)| {
+            matches!(event, Event::Start(Tag::Paragraph)) && r.start == target.range.start
+        })?;
+        Some((target, start + 1..end_of(&self.events, start)))
+    }
+}
+
+/// Writes events as Typst markup, keeping the indentation of list items
+struct Writer<'d, 'a> {
+    doc: &'d Document<'a>,
+    out: String,
+    /// The widths of the markers of the open list items, which their content is indented by
+    indent: Vec<usize>,
+    /// Whether nothing but indentation or a list marker is on the current line
+    line_start: bool,
+    /// The next number of each open list, `None` for bullet lists
+    lists: Vec<Option<u64>>,
+    /// What ends each open inline element, and heading or quote
+    closers: Vec<String>,
+    /// Whether the next text starts a table caption, whose `: ` is left out
+    in_caption: bool,
+}
+
+impl<'d, 'a> Writer<'d, 'a> {
+    fn new(doc: &'d Document<'a>) -> Writer<'d, 'a> {
+        Writer {
+            doc,
+            out: String::new(),
+            indent: Vec::new(),
+            line_start: true,
+            lists: Vec::new(),
+            closers: Vec::new(),
+            in_caption: false,
+        }
+    }
+
+    fn events(&mut self, range: Range<usize>) {
+        let doc = self.doc;
+        let events = &doc.events;
+        let mut index = range.start;
+
+        while index < range.end {
+            let (event, span) = &events[index];
+            match event {
+                Event::Start(Tag::Paragraph) => {
+                    if doc.refs.at(Kind::Table, span.start).is_some() {
+                        // written with its table
+                        index = end_of(events, index);
+                    } else if let Some(end) = self.figure(index) {
+                        index = end;
+                    }
+                }
+                Event::End(TagEnd::Paragraph) => self.end_block(),
+                Event::Start(Tag::Heading { level, id, .. }) => {
+                    self.write(&format!("{} ", "=".repeat(*level as usize)));
+                    self.line_start = true;
+                    self.closers.push(match id {
+                        Some(id) if is_label(id) => format!(" <{}>", id),
+                        _ => String::new(),
+                    });
+                }
+                Event::End(TagEnd::Heading(_)) => {
+                    self.close();
+                    self.end_block();
+                }
+                Event::Start(Tag::BlockQuote(_)) => {
+                    self.write("#quote(block: true)[\n");
+                    self.closers.push("]".to_string());
+                }
+                Event::End(TagEnd::BlockQuote) => {
+                    self.new_line();
+                    self.close();
+                    self.end_block();
+                }
+                Event::Start(Tag::CodeBlock(kind)) => {
+                    let end = end_of(events, index);
+                    let code: String = events[index + 1..end]
+                        .iter()
+                        .filter_map(|(event, _)| match event {
+                            Event::Text(text) => Some(text.as_ref()),
+                            _ => None,
+                        })
+                        .collect();
+                    self.code_block(kind, &code);
+                    index = end;
+                }
+                Event::Start(Tag::HtmlBlock)
+                | Event::Start(Tag::MetadataBlock(_))
+                | Event::Start(Tag::FootnoteDefinition(_)) => index = end_of(events, index),
+                Event::Start(Tag::List(start)) => {
+                    self.new_line();
+                    self.lists.push(*start);
+                }
+                Event::End(TagEnd::List(_)) => {
+                    self.lists.pop();
+                    if self.lists.is_empty() {
+                        self.end_block();
+                    }
+                }
+                Event::Start(Tag::Item) => {
+                    self.new_line();
+                    let marker = match self.lists.last_mut() {
+                        Some(Some(number)) => {
+                            *number += 1;
+                            format!("{}. ", *number - 1)
+                        }
+                        _ => "- ".to_string(),
+                    };
+                    self.write(&marker);
+                    self.line_start = true;
+                    self.indent.push(marker.len());
+                }
+                Event::End(TagEnd::Item) => {
+                    self.new_line();
+                    self.indent.pop();
+                }
+                Event::Start(Tag::Table(alignments)) => {
+                    index = self.table(index, alignments);
+                }
+                Event::Start(Tag::Emphasis) => self.open("#emph[", "]"),
+                Event::Start(Tag::Strong) => self.open("#strong[", "]"),
+                Event::Start(Tag::Strikethrough) => self.open("#strike[", "]"),
+                Event::Start(Tag::Link { link_type, dest_url, .. }) => {
+                    match dest_url.strip_prefix('#') {
+                        Some(id) if doc.labels.contains(id) => {
+                            self.open(&format!("#link(label({}))[", string(id)), "]")
+                        }
+                        Some(_) => self.open("", ""),
+                        None if *link_type == LinkType::Email => {
+                            self.open(&format!("#link({})[", string(&format!("mailto:{}", dest_url))), "]")
+                        }
+                        None => self.open(&format!("#link({})[", string(dest_url)), "]"),
+                    }
+                }
+                Event::End(TagEnd::Emphasis)
+                | Event::End(TagEnd::Strong)
+                | Event::End(TagEnd::Strikethrough)
+                | Event::End(TagEnd::Link) => self.close(),
+                Event::Start(Tag::Image { dest_url, .. }) => {
+                    let end = end_of(events, index);
+                    match doc.image_path(dest_url) {
+                        Some(path) => self.write(&format!("#box(image({}))", string(&path))),
+                        None => {
+                            let alt = doc.render(index + 1..end);
+                            self.write(&format!("#link({})[{}]", string(dest_url), alt));
+                        }
+                    }
+                    index = end;
+                }
+                Event::Text(text) => {
+                    let attributes: Vec<_> =
+                        doc.refs.targets.iter().filter_map(|t| t.attribute.clone()).collect();
+                    let mut text =
+                        crossref::without_attributes(doc.source, text.clone(), span, &attributes);
+                    if self.in_caption {
+                        text = crossref::caption_text(&text).to_string();
+                        self.in_caption = false;
+                    }
+                    self.text(&text);
+                }
+                Event::Code(code) => {
+                    if code.contains('`') {
+                        self.write(&format!("#raw({})", string(code)));
+                    } else {
+                        self.write(&format!("`{}`", code));
+                    }
+                }
+                Event::InlineMath(math) => {
+                    self.write(&format!("${}$", doc.math(math).trim()));
+                }
+                Event::DisplayMath(math) => {
+                    let math = doc.math(math);
+                    match doc.refs.at(Kind::Equation, span.start) {
+                        Some(target) => self.write(&format!(
+                            "#math.equation(block: true, numbering: _ => \"({})\", $ {} $){}",
+                            target.number,
+                            math.trim(),
+                            label(target.id.as_deref())
+                        )),
+                        None => self.write(&format!("$ {} $", math.trim())),
+                    }
+                }
+                Event::FootnoteReference(name) => match doc.footnotes.get(name.as_ref()) {
+                    Some(definition) => {
+                        let note = doc.render(definition.clone());
+                        self.write(&format!("#footnote[{}]", note));
+                    }
+                    None => self.text(&format!("[^{}]", name)),
+                },
+                Event::SoftBreak => self.write("\n"),
+                Event::HardBreak => self.write(" \\\n"),
+                Event::Rule => {
+                    self.write("#line(length: 100%)");
+                    self.end_block();
+                }
+                Event::TaskListMarker(checked) => {
+                    self.write(if *checked { "☒ " } else { "☐ " });
+                }
+                _ => {}
+            }
+            index += 1;
+        }
+    }
+
+    /// Writes a paragraph made of an image with alt text as a numbered figure. Returns the index
+    /// of the end of the paragraph, or `None` if it is not a figure
+    fn figure(&mut self, paragraph: usize) -> Option<usize> {
+        let doc = self.doc;
+        let (Event::Start(Tag::Image { dest_url, .. }), image) = &doc.events.get(paragraph + 1)?
+        else {
+            return None;
+        };
+        let target = doc.refs.at(Kind::Figure, image.start)?;
+
+        let caption = doc.render(paragraph + 2..end_of(&doc.events, paragraph + 1));
+        let body = match doc.image_path(dest_url) {
+            Some(path) => format!("image({})", string(&path)),
+            None => format!("link({})", string(dest_url)),
+        };
+        self.write(&format!(
+            "#figure({}, caption: [{}], numbering: _ => \"{}\"){}",
+            body,
+            caption,
+            target.number,
+            label(target.id.as_deref())
+        ));
+        self.end_block();
+        Some(end_of(&doc.events, paragraph))
+    }
+
+    /// Writes a table, in a numbered figure if it has a caption. Returns the index of its end
+    fn table(&mut self, start: usize, alignments: &[Alignment]) -> usize {
+        let doc = self.doc;
+        let end = end_of(&doc.events, start);
+        let caption = doc.table_caption(&doc.events[start].1);
+
+        let align: Vec<_> = alignments
+            .iter()
+            .map(|alignment| match alignment {
+                Alignment::None => "auto",
+                Alignment::Left => "left",
+                Alignment::Center => "center",
+                Alignment::Right => "right",
+            })
+            .collect();
+
+        let mut table = format!(
+            "table(\n  columns: {},\n  align: ({},),\n",
+            alignments.len(),
+            align.join(", ")
+        );
+        let mut row: Vec<String> = Vec::new();
+        let mut index = start + 1;
+        while index < end {
+            match &doc.events[index].0 {
+                Event::Start(Tag::TableCell) => {
+                    let cell_end = end_of(&doc.events, index);
+                    row.push(format!("[{}]", doc.render(index + 1..cell_end)));
+                    index = cell_end;
+                }
+                Event::End(TagEnd::TableHead) => {
+                    table.push_str(&format!("  table.header({}),\n", row.join(", ")));
+                    row.clear();
+                }
+                Event::End(TagEnd::TableRow) => {
+                    table.push_str(&format!("  {},\n", row.join(", ")));
+                    row.clear();
+                }
+                _ => {}
+            }
+            index += 1;
+        }
+        table.push(')');
+
+        match caption {
+            Some((target, paragraph)) => {
+                let mut writer = Writer::new(doc);
+                writer.in_caption = true;
+                writer.events(paragraph);
+                let table = table.replace('\n', "\n  ");
+                self.write(&format!(
+                    "#figure(\n  {},\n  caption: [{}],\n  numbering: _ => \"{}\",\n){}",
+                    table,
+                    writer.out.trim(),
+                    target.number,
+                    label(target.id.as_deref())
+                ));
+            }
+            None => self.write(&format!("#{}", table)),
+        }
+        self.end_block();
+        end
+    }
+
+    fn code_block(&mut self, kind: &CodeBlockKind, code: &str) {
+        let info = match kind {
+            CodeBlockKind::Fenced(info) => info.trim(),
+            CodeBlockKind::Indented => "",
+        };
+        if info == "{=typst}" {
+            self.write(code);
+            self.end_block();
+            return;
+        }
+        // other raw blocks are meant for other outputs
+        if info.starts_with("{=") {
+            return;
+        }
+
+        let lang = info
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .trim_matches(['{', '}', '.']);
+        let mut fence = "```".to_string();
+        while code.contains(fence.as_str()) {
+            fence.push('`');
+        }
+        self.write(&format!("{}{}\n{}", fence, lang, code));
+        self.new_line();
+        self.write(&fence);
+        self.end_block();
+    }
+
+    /// Writes text with the references and citations in it
+    fn text(&mut self, text: &str) {
+        let doc = self.doc;
+        let mut last = 0;
+        for reference in crossref::references(text) {
+            self.plain(&text[last..reference.range.start]);
+            match doc.refs.get(&reference.id) {
+                Some(target) => {
+                    let text = escape(&reference.style.format(target.kind, &target.number));
+                    self.write(&format!("#link(label({}))[{}]", string(&reference.id), text));
+                }
+                None => self.write("??"),
+            }
+            last = reference.range.end;
+        }
+        self.plain(&text[last..]);
+    }
+
+    fn plain(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let mut markup = match self.doc.bibliography {
+            Some(bibliography) => citations::to_typst(text, bibliography, escape),
+            None => escape(text),
+        };
+        if self.line_start {
+            markup = escape_line_start(&markup);
+        }
+        self.write(&markup);
+    }
+
+    fn open(&mut self, start: &str, end: &str) {
+        self.write(start);
+        self.closers.push(end.to_string());
+    }
+
+    fn close(&mut self) {
+        let closer = self.closers.pop().unwrap_or_default();
+        self.write(&closer);
+    }
+
+    /// Writes `text`, indenting its lines to the open list items
+    fn write(&mut self, text: &str) {
+        for c in text.chars() {
+            if (self.out.is_empty() || self.out.ends_with('\n')) && c != '\n' {
+                self.out.push_str(&" ".repeat(self.indent.iter().sum()));
+            }
+            self.out.push(c);
+        }
+        if !text.is_empty() {
+            self.line_start = text.ends_with('\n');
+        }
+    }
+
+    fn new_line(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+        self.line_start = true;
+    }
+
+    /// Ends a block with a blank line
+    fn end_block(&mut self) {
+        self.new_line();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+}
+
+/// The index of the end of the element started at `events[start]`
+fn end_of(events: &[(Event, Range<usize>)], start: usize) -> usize {
+    let mut depth = 0;
+    for (index, (event, _)) in events.iter().enumerate().skip(start) {
+        match event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => {
+                depth -= 1;
+                if depth == 0 {
+                    return index;
+                }
+            }
+            _ => {}
+        }
+    }
+    events.len()
+}
+
+/// Escapes the characters that are markup in Typst
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\#*_$@<>[]`~/".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Escapes what would start a heading or a list item at the start of a line
+fn escape_line_start(markup: &str) -> String {
+    if markup.starts_with(['-', '+', '=']) {
+        return format!("\\{}", markup);
+    }
+    let digits = markup.find(|c: char| !c.is_ascii_digit()).unwrap_or(markup.len());
+    if digits > 0 && markup[digits..].starts_with('.') {
+        return format!("{}\\{}", &markup[..digits], &markup[digits..]);
+    }
+    markup.to_string()
+}
+
+/// A Typst string literal
+fn string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Typst labels may only have letters, digits and `_-:.`
+fn is_label(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_alphanumeric() || "_-:.".contains(c))
+}
+
+fn label(id: Option<&str>) -> String {
+    match id {
+        Some(id) if is_label(id) => format!(" <{}>", id),
+        _ => String::new(),
+    }
+}
+
+/// The settings and title block at the top of the `.typ` file: the document's title and
+/// authors, section numbers when equations, figures and tables are numbered per section, and
+/// the template's `header.typ`, rendered
+pub fn preamble(meta: &Map<String, Value>, numbering: Numbering, header: Option<&str>) -> String {
+    let title = meta.get("title").and_then(Value::as_str);
+    let authors: Vec<&str> = match meta.get("author") {
+        Some(Value::String(author)) => vec![author],
+        Some(Value::Array(authors)) => authors.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    let date = meta.get("date").and_then(Value::as_str);
+
+    let mut document = Vec::new();
+    if let Some(title) = title {
+        document.push(format!("title: {}", string(title)));
+    }
+    if !authors.is_empty() {
+        let authors: Vec<_> = authors.iter().map(|author| string(author)).collect();
+        document.push(format!("author: ({},)", authors.join(", ")));
+    }
+
+    let mut out = String::new();
+    if !document.is_empty() {
+        out.push_str(&format!("#set document({})\n", document.join(", ")));
+    }
+    if numbering == Numbering::Section {
+        out.push_str("#set heading(numbering: \"1.1\")\n");
+    }
+    if let Some(header) = header {
+        out.push_str(header.trim_end());
+        out.push('\n');
+    }
+
+    if title.is_some() || !authors.is_empty() || date.is_some() {
+        let mut block = Vec::new();
+        if let Some(title) = title {
+            block.push(format!("#text(size: 1.7em, weight: \"bold\")[{}]", escape(title)));
+        }
+        if !authors.is_empty() {
+            let authors: Vec<_> = authors.iter().map(|author| escape(author)).collect();
+            block.push(authors.join(", "));
+        }
+        if let Some(date) = date {
+            block.push(escape(date));
+        }
+        out.push_str(&format!("\n#align(center)[\n  {}\n]\n", block.join("\n\n  ")));
+    }
+    out
+}
+
+/// The references section, listing the cited entries of the BibTeX files of the bibliography,
+/// with paths relative to `out_dir`. A CSL file takes the place of the built-in `style`
+pub fn bibliography(
+    bibliography: &Bibliography,
+    style: CitationStyle,
+    csl: Option<&Path>,
+    title: &str,
+    out_dir: &Path,
+) -> String {
+    let files: Vec<_> = bibliography
+        .files
+        .iter()
+        .map(|file| string(&relative_path(out_dir, file).to_string_lossy()))
+        .collect();
+    let style = match (csl, style) {
+        (Some(csl), _) => string(&relative_path(out_dir, csl).to_string_lossy()),
+        (None, CitationStyle::AuthorDate) => string("chicago-author-date"),
+        (None, CitationStyle::Numeric) => string("ieee"),
+    };
+    format!(
+        "#bibliography(({},), title: [{}], style: {})\n",
+        files.join(", "),
+        escape(title),
+        style
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(source: &str) -> String {
+        let refs = CrossRefs::collect(source, Numbering::Document);
+        Document::new(source, &refs, &[], None, Path::new("/notes"), Path::new("/out")).to_typst()
+    }
+
+    #[test]
+    fn test_to_typst() {
+        let typst = convert(
+            "# Intro {#intro}\n\n\
+             Some *text* with `code`, a [link](https://example.com) and 50% #tags.\n\n\
+             $$E = mc^2$$ {#eq:energy}\n\n\
+             See @eq:energy and [the intro](#intro).\n\n\
+             - one\n- two\n  1. nested\n\n\
+             ![A plot](plot.png){#fig:plot}\n",
+        );
+        assert!(typst.contains("= Intro <intro>\n\n"));
+        assert!(typst
+            .contains("Some #emph[text] with `code`, a #link(\"https://example.com\")[link]"));
+        assert!(typst.contains("50% \\#tags."));
+        assert!(typst.contains(
+            "#math.equation(block: true, numbering: _ => \"(1)\", $ E = m c^2 $) <eq:energy>"
+        ));
+        assert!(typst.contains(
+            "See #link(label(\"eq:energy\"))[Equation (1)] and #link(label(\"intro\"))[the intro]."
+        ));
+        assert!(typst.contains("- one\n- two\n  1. nested\n"));
+        assert!(typst.contains(
+            "#figure(image(\"../notes/plot.png\"), caption: [A plot], numbering: _ => \"1\") <fig:plot>"
+        ));
+    }
+
+    #[test]
+    fn test_table_caption() {
+        let typst = convert("| a | b |\n|:--|--:|\n| 1 | 2 |\n\n: Results {#tbl:results}\n");
+        assert_eq!(
+            typst,
+            "#figure(\n  table(\n    columns: 2,\n    align: (left, right,),\n    \
+             table.header([a], [b]),\n    [1], [2],\n  ),\n  caption: [Results],\n  \
+             numbering: _ => \"1\",\n) <tbl:results>\n"
+        );
+    }
+}
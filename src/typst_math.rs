@@ -0,0 +1,734 @@
+use crate::macros::Macro;
+
+/// LaTeX commands with the same name in Typst math, written without their backslash
+static NAMES: &[&str] = &[
+    // Greek letters, besides the variants in `SYMBOLS`
+    "alpha", "beta", "gamma", "delta", "zeta", "eta", "theta", "iota", "kappa", "lambda", "mu",
+    "nu", "xi", "omicron", "pi", "rho", "sigma", "tau", "upsilon", "chi", "psi", "omega",
+    "Gamma", "Delta", "Theta", "Lambda", "Xi", "Pi", "Sigma", "Upsilon", "Phi", "Psi", "Omega",
+    // operators
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh",
+    "tanh", "coth", "exp", "log", "ln", "lg", "lim", "liminf", "limsup", "max", "min", "sup",
+    "inf", "det", "dim", "gcd", "lcm", "deg", "arg", "hom", "ker", "Pr",
+    // symbols
+    "in", "subset", "supset", "sum", "nabla", "times", "div", "approx", "equiv", "perp",
+    "parallel", "angle", "aleph", "ell", "top", "bot", "forall", "exists", "therefore",
+    "because", "diamond", "prime", "models", "Re", "Im",
+];
+
+/// LaTeX commands written differently in Typst math. Commands that are neither here nor in
+/// `NAMES` are kept as text, so they show up in the output instead of breaking the document
+static SYMBOLS: &[(&str, &str)] = &[
+    // letters whose variants are named the other way around
+    ("epsilon", "epsilon.alt"),
+    ("varepsilon", "epsilon"),
+    ("phi", "phi.alt"),
+    ("varphi", "phi"),
+    ("vartheta", "theta.alt"),
+    ("varrho", "rho.alt"),
+    ("varsigma", "sigma.alt"),
+    ("varpi", "pi.alt"),
+    ("varkappa", "kappa.alt"),
+    // binary operators and relations
+    ("cdot", "dot.op"),
+    ("cdotp", "dot.op"),
+    ("pm", "plus.minus"),
+    ("mp", "minus.plus"),
+    ("ast", "ast.op"),
+    ("star", "star.op"),
+    ("circ", "compose"),
+    ("oplus", "plus.circle"),
+    ("ominus", "minus.circle"),
+    ("otimes", "times.circle"),
+    ("odot", "dot.circle"),
+    ("setminus", "without"),
+    ("leq", "<="),
+    ("le", "<="),
+    ("geq", ">="),
+    ("ge", ">="),
+    ("neq", "!="),
+    ("ne", "!="),
+    ("ll", "<<"),
+    ("gg", ">>"),
+    ("leqslant", "lt.eq.slant"),
+    ("geqslant", "gt.eq.slant"),
+    ("lesssim", "lt.tilde"),
+    ("gtrsim", "gt.tilde"),
+    ("prec", "prec"),
+    ("succ", "succ"),
+    ("preceq", "prec.eq"),
+    ("succeq", "succ.eq"),
+    ("sim", "tilde.op"),
+    ("simeq", "tilde.eq"),
+    ("cong", "tilde.equiv"),
+    ("propto", "prop"),
+    ("notin", "in.not"),
+    ("ni", "in.rev"),
+    ("subseteq", "subset.eq"),
+    ("supseteq", "supset.eq"),
+    ("subsetneq", "subset.neq"),
+    ("supsetneq", "supset.neq"),
+    ("nsubseteq", "subset.eq.not"),
+    ("cup", "union"),
+    ("cap", "sect"),
+    ("sqcup", "union.sq"),
+    ("sqcap", "sect.sq"),
+    ("uplus", "union.plus"),
+    ("wedge", "and"),
+    ("land", "and"),
+    ("vee", "or"),
+    ("lor", "or"),
+    ("neg", "not"),
+    ("lnot", "not"),
+    ("mid", "divides"),
+    ("nmid", "divides.not"),
+    ("vdash", "tack.r"),
+    ("dashv", "tack.l"),
+    ("coloneqq", ":="),
+    ("colon", "colon"),
+    // big operators
+    ("bigcup", "union.big"),
+    ("bigcap", "sect.big"),
+    ("bigsqcup", "union.sq.big"),
+    ("bigoplus", "plus.circle.big"),
+    ("bigotimes", "times.circle.big"),
+    ("bigwedge", "and.big"),
+    ("bigvee", "or.big"),
+    ("prod", "product"),
+    ("coprod", "product.co"),
+    ("int", "integral"),
+    ("iint", "integral.double"),
+    ("iiint", "integral.triple"),
+    ("oint", "integral.cont"),
+    // arrows
+    ("to", "->"),
+    ("rightarrow", "->"),
+    ("leftarrow", "<-"),
+    ("gets", "<-"),
+    ("leftrightarrow", "<->"),
+    ("Rightarrow", "=>"),
+    ("implies", "==>"),
+    ("Leftarrow", "arrow.l.double"),
+    ("impliedby", "<=="),
+    ("Leftrightarrow", "<=>"),
+    ("iff", "<==>"),
+    ("mapsto", "|->"),
+    ("longrightarrow", "-->"),
+    ("longleftarrow", "<--"),
+    ("longmapsto", "arrow.r.long.bar"),
+    ("hookrightarrow", "arrow.r.hook"),
+    ("hookleftarrow", "arrow.l.hook"),
+    ("twoheadrightarrow", "->>"),
+    ("uparrow", "arrow.t"),
+    ("downarrow", "arrow.b"),
+    ("Uparrow", "arrow.t.double"),
+    ("Downarrow", "arrow.b.double"),
+    ("nearrow", "arrow.tr"),
+    ("searrow", "arrow.br"),
+    // delimiters
+    ("langle", "angle.l"),
+    ("rangle", "angle.r"),
+    ("lfloor", "floor.l"),
+    ("rfloor", "floor.r"),
+    ("lceil", "ceil.l"),
+    ("rceil", "ceil.r"),
+    ("vert", "|"),
+    ("lvert", "|"),
+    ("rvert", "|"),
+    ("Vert", "||"),
+    ("lVert", "||"),
+    ("rVert", "||"),
+    ("|", "||"),
+    ("lbrace", "\\{"),
+    ("rbrace", "\\}"),
+    ("{", "\\{"),
+    ("}", "\\}"),
+    ("lbrack", "["),
+    ("rbrack", "]"),
+    ("backslash", "backslash"),
+    // other symbols
+    ("infty", "infinity"),
+    ("partial", "diff"),
+    ("emptyset", "emptyset"),
+    ("varnothing", "emptyset"),
+    ("nexists", "exists.not"),
+    ("ldots", "..."),
+    ("dots", "..."),
+    ("dotsc", "..."),
+    ("cdots", "dots.c"),
+    ("dotsb", "dots.c"),
+    ("vdots", "dots.v"),
+    ("ddots", "dots.down"),
+    ("hbar", "planck.reduce"),
+    ("imath", "dotless.i"),
+    ("jmath", "dotless.j"),
+    ("triangle", "triangle.t"),
+    ("square", "square"),
+    ("Box", "square"),
+    ("dagger", "dagger"),
+    ("ddagger", "dagger.double"),
+    ("bullet", "bullet"),
+    ("degree", "degree"),
+    ("checkmark", "checkmark"),
+    ("%", "%"),
+    ("&", "\\&"),
+    ("_", "\\_"),
+    ("#", "\\#"),
+    ("$", "\\$"),
+    ("'", "'"),
+    // spacing
+    (",", "thin"),
+    (":", "med"),
+    (">", "med"),
+    (";", "thick"),
+    (" ", "space"),
+    ("quad", "quad"),
+    ("qquad", "wide"),
+    ("enspace", "space.en"),
+];
+
+/// Commands taking an argument, and the Typst function they become
+static FUNCTIONS: &[(&str, &str)] = &[
+    ("mathbf", "bold"),
+    ("boldsymbol", "bold"),
+    ("bm", "bold"),
+    ("mathit", "italic"),
+    ("mathcal", "cal"),
+    ("mathscr", "cal"),
+    ("mathfrak", "frak"),
+    ("mathsf", "sans"),
+    ("mathtt", "mono"),
+    ("mathbb", "bb"),
+    ("hat", "hat"),
+    ("widehat", "hat"),
+    ("tilde", "tilde"),
+    ("widetilde", "tilde"),
+    ("bar", "macron"),
+    ("overline", "overline"),
+    ("underline", "underline"),
+    ("vec", "arrow"),
+    ("overrightarrow", "arrow"),
+    ("dot", "dot"),
+    ("ddot", "dot.double"),
+    ("breve", "breve"),
+    ("check", "caron"),
+    ("acute", "acute"),
+    ("grave", "grave"),
+    ("overbrace", "overbrace"),
+    ("underbrace", "underbrace"),
+    ("cancel", "cancel"),
+    ("boxed", "rect"),
+];
+
+/// Commands that are left out, as they have no effect in Typst or are handled elsewhere
+static IGNORED: &[&str] = &[
+    "displaystyle", "textstyle", "scriptstyle", "limits", "nolimits", "nonumber", "notag", "!",
+    "big", "Big", "bigg", "Bigg", "bigl", "bigr", "Bigl", "Bigr",
+    "biggl", "biggr", "Biggl", "Biggr",
+];
+
+/// How many macros are expanded at most, so that a macro using itself does not loop forever
+const MAX_EXPANSIONS: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// `\name`, or `\,` and other escaped characters
+    Command(String),
+    Open,
+    Close,
+    Char(char),
+}
+
+impl Token {
+    fn source(&self) -> String {
+        match self {
+            Token::Command(name) => format!("\\{}", name),
+            Token::Open => "{".to_string(),
+            Token::Close => "}".to_string(),
+            Token::Char(c) => c.to_string(),
+        }
+    }
+}
+
+fn tokenize(math: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = math.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let mut name = String::new();
+                while let Some(&next) = chars.peek().filter(|c| c.is_ascii_alphabetic()) {
+                    name.push(next);
+                    chars.next();
+                }
+                if name.is_empty() {
+                    name.extend(chars.next());
+                } else if name == "operatorname" && chars.peek() == Some(&'*') {
+                    name.push('*');
+                    chars.next();
+                }
+                tokens.push(Token::Command(name));
+            }
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            // LaTeX ignores spaces in math, and so does Typst, except between letters
+            c if c.is_whitespace() => {
+                if tokens.last() != Some(&Token::Char(' ')) {
+                    tokens.push(Token::Char(' '));
+                }
+            }
+            c => tokens.push(Token::Char(c)),
+        }
+    }
+
+    tokens
+}
+
+/// Converts LaTeX math, as written for KaTeX, to Typst math. `macros` are expanded first, so
+/// e.g. `\R` defined as `\mathbb{R}` becomes Typst's `RR`.
+///
+/// Commands without a Typst counterpart are kept as text, e.g. `\foo` becomes `"\\foo"`, so the
+/// document still compiles and shows what was not converted.
+pub fn to_typst(math: &str, macros: &[Macro]) -> String {
+    Converter::new(math, macros).sequence(false, &|_| false).trim().to_string()
+}
+
+struct Converter<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    macros: &'a [Macro],
+    expansions: usize,
+}
+
+impl Converter<'_> {
+    fn new<'m>(math: &str, macros: &'m [Macro]) -> Converter<'m> {
+        Converter { tokens: tokenize(math), pos: 0, macros, expansions: 0 }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek() == Some(&Token::Char(' ')) {
+            self.pos += 1;
+        }
+    }
+
+    /// Converts tokens until `stop` matches one, which is left unread. In the arguments of a
+    /// Typst function, commas and semicolons are escaped so they are not read as separators
+    fn sequence(&mut self, in_args: bool, stop: &dyn Fn(&Token) -> bool) -> String {
+        let mut out = String::new();
+
+        while let Some(token) = self.peek() {
+            if stop(token) {
+                break;
+            }
+            let token = self.next().unwrap();
+            match token {
+                Token::Char(' ') => {}
+                Token::Char(c @ ('^' | '_')) => {
+                    let script = self.argument();
+                    let trimmed = out.trim_end().len();
+                    out.truncate(trimmed);
+                    if out.is_empty() {
+                        // a script with nothing to attach to, e.g. `{}^{14}C`
+                        out.push_str("zws");
+                    }
+                    out.push(c);
+                    out.push_str(&wrap(&script));
+                }
+                Token::Char(c) if c.is_ascii_digit() => {
+                    let mut number = c.to_string();
+                    while let Some(Token::Char(next)) = self.peek() {
+                        if !(next.is_ascii_digit() || *next == '.') {
+                            break;
+                        }
+                        number.push(*next);
+                        self.pos += 1;
+                    }
+                    push(&mut out, &number);
+                }
+                Token::Char(c) => push(&mut out, &char_to_typst(c, in_args)),
+                Token::Open => {
+                    let group = self.sequence(in_args, &|t| *t == Token::Close);
+                    self.next();
+                    push(&mut out, &group);
+                }
+                Token::Close => {}
+                Token::Command(name) => {
+                    let converted = self.command(&name);
+                    push(&mut out, &converted);
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Reads the argument of a command: a group, or else a single token
+    fn argument(&mut self) -> String {
+        self.skip_spaces();
+        match self.next() {
+            Some(Token::Open) => {
+                let group = self.sequence(true, &|t| *t == Token::Close);
+                self.next();
+                group
+            }
+            Some(Token::Command(name)) => self.command(&name),
+            Some(Token::Char(c)) => char_to_typst(c, true),
+            _ => String::new(),
+        }
+    }
+
+    /// The source of the next argument, without its braces, for text and macros
+    fn raw_argument(&mut self) -> String {
+        self.skip_spaces();
+        match self.next() {
+            Some(Token::Open) => {
+                let mut depth = 0;
+                let mut source = String::new();
+                while let Some(token) = self.next() {
+                    match token {
+                        Token::Open => depth += 1,
+                        Token::Close if depth == 0 => break,
+                        Token::Close => depth -= 1,
+                        _ => {}
+                    }
+                    source.push_str(&token.source());
+                }
+                source
+            }
+            Some(token) => token.source(),
+            None => String::new(),
+        }
+    }
+
+    /// Reads a `[...]` optional argument, if there is one
+    fn optional_argument(&mut self) -> Option<String> {
+        self.skip_spaces();
+        if self.peek() != Some(&Token::Char('[')) {
+            return None;
+        }
+        self.next();
+        let argument = self.sequence(true, &|t| *t == Token::Char(']'));
+        self.next();
+        Some(argument)
+    }
+
+    fn command(&mut self, name: &str) -> String {
+        if let Some(expanded) = self.expand_macro(name) {
+            self.tokens.splice(self.pos..self.pos, expanded);
+            return String::new();
+        }
+        if IGNORED.contains(&name) {
+            return String::new();
+        }
+        if let Some((_, function)) = FUNCTIONS.iter().find(|(command, _)| *command == name) {
+            let argument = self.argument();
+            // Typst has shorthands for the common number sets
+            if *function == "bb" && matches!(argument.as_str(), "N" | "Z" | "Q" | "R" | "C") {
+                return format!("{}{}", argument, argument);
+            }
+            return format!("{}({})", function, argument);
+        }
+
+        match name {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let (numerator, denominator) = (self.argument(), self.argument());
+                format!("frac({}, {})", numerator, denominator)
+            }
+            "binom" | "dbinom" | "tbinom" => {
+                let (n, k) = (self.argument(), self.argument());
+                format!("binom({}, {})", n, k)
+            }
+            "sqrt" => match self.optional_argument() {
+                Some(index) => format!("root({}, {})", index, self.argument()),
+                None => format!("sqrt({})", self.argument()),
+            },
+            "text" | "textrm" | "textnormal" | "mbox" | "hbox" | "textup" => {
+                string(&self.raw_argument())
+            }
+            "textbf" => format!("bold({})", string(&self.raw_argument())),
+            "textit" | "emph" => format!("italic({})", string(&self.raw_argument())),
+            "mathrm" | "rm" | "mathup" => {
+                let argument = self.raw_argument();
+                if argument.chars().all(char::is_alphanumeric) && argument.chars().count() > 1 {
+                    format!("upright({})", string(&argument))
+                } else {
+                    let converted =
+                        Converter::new(&argument, self.macros).sequence(true, &|_| false);
+                    format!("upright({})", converted)
+                }
+            }
+            "operatorname" | "operatorname*" => {
+                let limits = if name.ends_with('*') { ", limits: #true" } else { "" };
+                format!("op({}{})", string(&self.raw_argument()), limits)
+            }
+            "label" | "tag" => {
+                self.raw_argument();
+                String::new()
+            }
+            "left" => {
+                let open = self.delimiter();
+                let body =
+                    self.sequence(true, &|t| matches!(t, Token::Command(name) if name == "right"));
+                let close = match self.next() {
+                    Some(_) => self.delimiter(),
+                    None => String::new(),
+                };
+                lr(&open, body.trim(), &close)
+            }
+            // a `\right` without its `\left`
+            "right" => self.delimiter(),
+            "middle" => format!("mid({})", self.delimiter()),
+            "bmod" => "mod".to_string(),
+            // spaced like LaTeX does, which also stops it being read as a call
+            "pmod" => format!("quad (mod {})", self.argument()),
+            "begin" => {
+                let environment = self.raw_argument();
+                self.environment(&environment)
+            }
+            "end" => {
+                self.raw_argument();
+                String::new()
+            }
+            "\\" => "\\".to_string(),
+            "not" => {
+                let argument = self.argument();
+                format!("cancel({})", argument)
+            }
+            _ if NAMES.contains(&name) => name.to_string(),
+            _ => match SYMBOLS.iter().find(|(command, _)| *command == name) {
+                Some((_, symbol)) => symbol.to_string(),
+                None => string(&format!("\\{}", name)),
+            },
+        }
+    }
+
+    /// Reads the delimiter after `\left`, `\middle` or `\right`, where `.` is none
+    fn delimiter(&mut self) -> String {
+        self.skip_spaces();
+        match self.next() {
+            Some(Token::Char('.')) | None => String::new(),
+            Some(Token::Char(c)) => char_to_typst(c, false),
+            Some(Token::Command(name)) => self.command(&name),
+            Some(token) => token.source(),
+        }
+    }
+
+    /// The tokens a macro expands to, with its arguments in place of `#1`, `#2`, ...
+    fn expand_macro(&mut self, name: &str) -> Option<Vec<Token>> {
+        let definition = format!("\\{}", name);
+        let m = self.macros.iter().find(|m| m.name == definition)?;
+        if self.expansions >= MAX_EXPANSIONS {
+            return None;
+        }
+        self.expansions += 1;
+
+        let mut arguments = Vec::new();
+        if m.args > 0 {
+            if let Some(default) = &m.default {
+                self.skip_spaces();
+                let first = if self.peek() == Some(&Token::Char('[')) {
+                    self.next();
+                    let mut source = String::new();
+                    while let Some(token) = self.next() {
+                        if token == Token::Char(']') {
+                            break;
+                        }
+                        source.push_str(&token.source());
+                    }
+                    source
+                } else {
+                    default.clone()
+                };
+                arguments.push(first);
+            }
+        }
+        while (arguments.len() as u64) < m.args {
+            arguments.push(self.raw_argument());
+        }
+
+        let mut body = m.body.clone();
+        for (i, argument) in arguments.iter().enumerate().rev() {
+            body = body.replace(&format!("#{}", i + 1), &format!("{{{}}}", argument));
+        }
+        Some(tokenize(&body))
+    }
+
+    /// Converts the body of `\begin{environment}`, up to its `\end`
+    fn environment(&mut self, environment: &str) -> String {
+        let delimiter = match environment {
+            "pmatrix" => Some("\"(\""),
+            "bmatrix" => Some("\"[\""),
+            "Bmatrix" => Some("\"{\""),
+            "vmatrix" => Some("\"|\""),
+            "Vmatrix" => Some("\"||\""),
+            "matrix" | "smallmatrix" | "array" => Some("#none"),
+            _ => None,
+        };
+        if environment == "array" {
+            // the column specification
+            self.raw_argument();
+        }
+
+        let rows = self.rows();
+        if let Some(delimiter) = delimiter {
+            let rows: Vec<String> = rows.iter().map(|cells| cells.join(", ")).collect();
+            return format!("mat(delim: {}, {})", delimiter, rows.join("; "));
+        }
+        if environment == "cases" {
+            let rows: Vec<String> = rows.iter().map(|cells| cells.join(" & ")).collect();
+            return format!("cases({})", rows.join(", "));
+        }
+
+        // aligned, gathered and the like keep their alignment points
+        let rows: Vec<String> = rows.iter().map(|cells| cells.join(" & ")).collect();
+        rows.join(" \\ ")
+    }
+
+    /// Reads the rows of an environment, split at `\\`, and their cells, split at `&`
+    fn rows(&mut self) -> Vec<Vec<String>> {
+        let mut rows = vec![Vec::new()];
+        loop {
+            let cell = self.sequence(true, &|t| {
+                matches!(t, Token::Char('&'))
+                    || matches!(t, Token::Command(name) if name == "\\" || name == "end")
+            });
+            rows.last_mut().unwrap().push(cell.trim().to_string());
+            match self.next() {
+                Some(Token::Char('&')) => {}
+                Some(Token::Command(name)) if name == "\\" => rows.push(Vec::new()),
+                Some(Token::Command(_)) => {
+                    self.raw_argument();
+                    break;
+                }
+                _ => break,
+            }
+        }
+
+        // a `\\` after the last row leaves an empty one
+        if rows.last().is_some_and(|row| row.iter().all(String::is_empty)) && rows.len() > 1 {
+            rows.pop();
+        }
+        rows
+    }
+}
+
+/// Appends a converted piece, separated by a space so that letters do not run together into a
+/// Typst variable name
+fn push(out: &mut String, piece: &str) {
+    if piece.is_empty() {
+        return;
+    }
+    let joins = piece.starts_with(['(', ')', ',', '!', '\'', ']'])
+        || out.ends_with(['(', '[', ' '])
+        || out.is_empty();
+    if !joins {
+        out.push(' ');
+    }
+    out.push_str(piece);
+}
+
+/// Delimiters sized to their content, e.g. `lr((a + b))`. Parentheses that are not a pair are
+/// escaped, so they are not read as the end of the call, as in `lr([0 \, 1 \))`
+fn lr(open: &str, body: &str, close: &str) -> String {
+    let paired = open == "(" && close == ")";
+    let escape = |delimiter: &str| match delimiter {
+        "(" | ")" if !paired => format!("\\{}", delimiter),
+        _ => delimiter.to_string(),
+    };
+
+    let mut out = String::new();
+    for piece in [escape(open), body.to_string(), escape(close)] {
+        push(&mut out, &piece);
+    }
+    format!("lr({})", out)
+}
+
+/// A single character of LaTeX math in Typst
+fn char_to_typst(c: char, in_args: bool) -> String {
+    match c {
+        '/' => "\\/".to_string(),
+        '"' => "\\\"".to_string(),
+        '#' | '$' | '@' => format!("\\{}", c),
+        '~' => "space".to_string(),
+        ',' | ';' if in_args => format!("\\{}", c),
+        c => c.to_string(),
+    }
+}
+
+/// Wraps a script in parentheses unless it is a single symbol, e.g. `x^2` but `x^(n + 1)`
+fn wrap(script: &str) -> String {
+    let simple = script.chars().count() == 1
+        || (!script.is_empty() && script.chars().all(|c| c.is_ascii_digit()));
+    if simple {
+        script.to_string()
+    } else {
+        format!("({})", script)
+    }
+}
+
+/// A Typst string literal of text in math
+fn string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_typst() {
+        let macros = [Macro {
+            name: "\\R".into(),
+            body: "\\mathbb{R}".into(),
+            args: 0,
+            default: None,
+            command: None,
+            tex: None,
+        }];
+        assert_eq!(
+            to_typst("\\frac{a}{b} + \\sqrt[3]{x^{n+1}} \\in \\R", &macros),
+            "frac(a, b) + root(3, x^(n + 1)) in RR"
+        );
+        assert_eq!(to_typst("\\alpha_i \\leq \\text{max}", &[]), "alpha_i <= \"max\"");
+        assert_eq!(
+            to_typst("\\begin{pmatrix} a & b \\\\ c & d \\end{pmatrix}", &[]),
+            "mat(delim: \"(\", a, b; c, d)"
+        );
+        // letters are kept apart, so they are not read as a variable
+        assert_eq!(to_typst("ab", &[]), "a b");
+    }
+
+    #[test]
+    fn test_delimiters() {
+        assert_eq!(to_typst("\\left( \\frac{a}{b} \\right)", &[]), "lr((frac(a, b)))");
+        assert_eq!(to_typst("\\left[0, 1\\right)", &[]), "lr([0 \\, 1 \\))");
+        assert_eq!(
+            to_typst("\\left\\{ x \\middle| x > 0 \\right\\}", &[]),
+            "lr(\\{ x mid(|) x > 0 \\})"
+        );
+        assert_eq!(to_typst("\\left. f \\right|_a", &[]), "lr(f |)_a");
+        assert_eq!(
+            to_typst("\\left\\langle \\left| x \\right| \\right\\rangle", &[]),
+            "lr(angle.l lr(| x |) angle.r)"
+        );
+    }
+
+    #[test]
+    fn test_unknown_commands() {
+        assert_eq!(to_typst("\\sin x + \\Omega", &[]), "sin x + Omega");
+        // kept as text, as Typst has nothing of that name
+        assert_eq!(to_typst("a \\foo b", &[]), "a \"\\\\foo\" b");
+        assert_eq!(to_typst("a \\equiv b \\pmod{n}", &[]), "a equiv b quad (mod n)");
+    }
+}
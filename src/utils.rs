@@ -26,28 +26,17 @@ pub fn extract_templates_to(template_dir: &Dir<'_>, dest: &Path) -> std::io::Res
 
 pub fn eshow(result: Result<()>) {
     result.unwrap_or_else(|e| {
-        eprintln!("{}", e.to_string());
+        eprintln!("{}", e);
     })
 }
 
 
-// Print error message for when macros.tex is not found
-pub fn err_no_macro_tex(template_path: String) -> String {
-    format!(
-        "{} {}\n {}",
-        "ERROR:".red().bold(),
-        format!("{}/macros.tex does not exist.", template_path),
-        "Either run `tatum compile-macros <template-path>` \
-         or write your own macros.tex".yellow()
-    )
-}
-
 // Print error message for when header.tex is not found
 pub fn err_no_header_tex(template_path: String) -> String {
     format!(
-        "{} {}\n {}",
+        "{} {}/header.tex does not exist.\n {}",
         "ERROR:".red().bold(),
-        format!("{}/header.tex does not exist.", template_path),
+        template_path,
         "If you do not require a header, create a blank file".yellow()
     )
 }
@@ -55,21 +44,40 @@ pub fn err_no_header_tex(template_path: String) -> String {
 // Print error message for when markdown file is not found
 pub fn err_no_md_file(md_path: &Path) -> String{
     format!(
-        "{} {}",
+        "{} Markdown file {} does not exist",
         "ERROR:".red().bold(),
-        format!("Markdown file {} does not exist", md_path.to_str().unwrap())
+        md_path.to_str().unwrap()
     )
 }
 
 // Print error message for when pandoc fails
 pub fn err_pandoc_fails(status: &std::process::ExitStatus) -> String {
     format!(
-        "{} {}",
+        "{} Pandoc failed with status {}",
         "ERROR:".red().bold(),
-        format!("Pandoc failed with status {}", status)
+        status
     )
 }
 
+/// Error returned when pandoc exits unsuccessfully, carrying everything it printed to stderr
+#[derive(Debug)]
+pub struct PandocError {
+    pub status: std::process::ExitStatus,
+    pub log: String,
+}
+
+impl std::fmt::Display for PandocError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", err_pandoc_fails(&self.status))?;
+        if !self.log.trim().is_empty() {
+            write!(f, "\n{}", self.log.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PandocError {}
+
 // Print error message
 pub fn err(msg: &str) -> String {
     format!(
@@ -79,6 +87,62 @@ pub fn err(msg: &str) -> String {
     )
 }
 
+// Print warning message
+pub fn warn(msg: &str) -> String {
+    format!(
+        "{} {}",
+        "WARNING:".yellow().bold(),
+        msg
+    )
+}
+
+/// Escapes text for use in HTML, including attribute values
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Removes ANSI colour codes from a message so it can be shown outside of a terminal
+pub fn strip_ansi(msg: &str) -> String {
+    let mut out = String::with_capacity(msg.len());
+    let mut chars = msg.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // skip the escape sequence up to and including its final letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Directory used to store generated files that can be rebuilt at any time, e.g. PDF previews.
+///
+/// Cached files are used as they are, so this is the user's own cache directory, e.g.
+/// `~/.cache/tatum`, rather than one other users can write to. It is an error when the user has
+/// none, i.e. neither `$XDG_CACHE_HOME` nor `$HOME` is set.
+pub fn cache_dir() -> Result<PathBuf> {
+    dirs::cache_dir()
+        .map(|dir| dir.join("tatum"))
+        .ok_or_else(|| anyhow!("Could not find your cache directory. Set $XDG_CACHE_HOME or $HOME"))
+}
+
+/// The path of `to` relative to the directory `from`. Both must be absolute, or both relative to
+/// the same directory
+pub fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<_> = from.components().collect();
+    let to: Vec<_> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut path: PathBuf = from[common..].iter().map(|_| "..").collect();
+    path.extend(&to[common..]);
+    path
+}
+
 pub fn notify_overwrite() -> Result<()> {
     let ans = Confirm::new("The output file exists. Do you wish to overwrite?")
         .with_default(false)
@@ -94,11 +158,11 @@ pub fn notify_overwrite() -> Result<()> {
     }
 }
 
-pub fn create_parent_directories(out_file: &PathBuf) {
+pub fn create_parent_directories(out_file: &Path) {
     let dir_path = out_file.parent().unwrap_or_else(|| Path::new("."));
     if !dir_path.exists() {
-        fs::create_dir_all(&dir_path)
-            .expect(format!("Could not create directory {}", &dir_path.to_str().unwrap()).as_str());
+        fs::create_dir_all(dir_path)
+            .unwrap_or_else(|_| panic!("Could not create directory {}", dir_path.to_str().unwrap()));
         println!(
             "{}",
             format!(
@@ -107,3 +171,24 @@ pub fn create_parent_directories(out_file: &PathBuf) {
         );
     }
 }
+
+/// Prints the lines that differ between `old` and `new` with a few lines of context
+pub fn print_diff(old: &str, new: &str) {
+    let diff = similar::TextDiff::from_lines(old, new);
+
+    for (i, group) in diff.grouped_ops(2).iter().enumerate() {
+        if i > 0 {
+            println!("{}", "...".dimmed());
+        }
+        for op in group {
+            for change in diff.iter_changes(op) {
+                let line = change.value().trim_end_matches('\n');
+                match change.tag() {
+                    similar::ChangeTag::Delete => println!("{}", format!("-{}", line).red()),
+                    similar::ChangeTag::Insert => println!("{}", format!("+{}", line).green()),
+                    similar::ChangeTag::Equal => println!(" {}", line.dimmed()),
+                }
+            }
+        }
+    }
+}